@@ -40,11 +40,14 @@ use number_types::d16_type::d16;
 use number_types::a16_type::a16;
 use number_types::a8_type::a8;
 use memory::Memory;
+use memory::watch::WatchAction;
 
 #[derive(Debug, Copy, Clone)]
 pub enum CpuMode {
     DMG,
     MGB,
+    // a DMG inside a Super Game Boy; see `memory::sgb`
+    SGB,
     CGB,
 }
 
@@ -64,6 +67,12 @@ pub struct Cpu {
 
 impl Cpu {
     pub fn new(mode: CpuMode) -> Self {
+        Self::with_memory(mode, Memory::new_zeros())
+    }
+
+    pub fn with_memory(mode: CpuMode, mut memory: Memory) -> Self {
+        memory.set_model(mode);
+
         let stack_pointer = d16(Wrapping(0xfffe));
         let program_counter = d16(Wrapping(0x0100));
 
@@ -74,12 +83,32 @@ impl Cpu {
             stack_pointer,
             program_counter,
             cycle_count: 0,
-            memory: Memory::new_zeros(),
+            memory,
+        }
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    // runs one instruction, and says whether a watchpoint asked to halt
+    // during it. Whatever is driving the CPU should stop stepping if so.
+    pub fn step(&mut self, ins: ::instructions::RawOpcode) -> WatchAction {
+        self.process_instruction(ins);
+        if self.memory.take_watch_halt() {
+            WatchAction::Halt
+        } else {
+            WatchAction::Continue
         }
     }
 
     pub fn process_instruction(&mut self, ins: ::instructions::RawOpcode) {
         use instructions::RawOpcode::*;
+        self.memory.begin_instruction(self.program_counter.into());
         self.program_counter += d16(Wrapping(1)); // inc the program counter before doing work so that loading subsequent bytes will work
         match ins {
             NOP => self.nop(),
@@ -101,7 +130,7 @@ impl Cpu {
             RRCA => self.rotate_right_carry(r8::A),
 
             
-            STOP_0 => unimplemented!(),
+            STOP_0 => self.stop(),
             LD_DE_d16 => self.ld_r16_d16(r16::DE),
             LD_DE_A => self.ld_r16_r8(r16::DE, r8::A),
             INC_DE => self.inc_r16(r16::BC),
@@ -387,12 +416,29 @@ impl Cpu {
         }
     }
 
+    // STOP is two bytes, the second ignored. Only the CGB speed switch is
+    // emulated; the low-power stop that waits for a button isn't yet.
+    fn stop(&mut self) {
+        self.program_counter += d16(Wrapping(1));
+        if !self.memory.switch_speed() {
+            unimplemented!()
+        }
+        self.cycle(4);
+    }
+
     fn cycle(&mut self, count: u64) {
         debug_assert_eq!(count % 4, 0);
         // The GameBoy processor ops all take an amount of time that is a multiple of 4
         // Some sources use the cycle count / 4 instead, so if you see a resource that
         // says some methods have a time of 1 or 2, that's why
         self.cycle_count += count;
+        self.memory.tick(count);
+        // VRAM DMA and speed switches halt the CPU for a while
+        let stall = self.memory.take_stall_cycles();
+        if stall > 0 {
+            self.cycle_count += stall;
+            self.memory.tick(stall);
+        }
     }
 
     fn read_next_d8(&mut self) -> d8 {
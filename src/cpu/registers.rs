@@ -15,18 +15,22 @@ impl Registers {
         let AF: d16 = match mode {
             CpuMode::DMG => d16(Wrapping(0x01b0)),
             CpuMode::MGB => d16(Wrapping(0xffb0)),
+            CpuMode::SGB => d16(Wrapping(0x0100)),
             CpuMode::CGB => d16(Wrapping(0x1180)),
         };
         let BC: d16 = match mode {
             CpuMode::CGB => d16(Wrapping(0x0000)),
+            CpuMode::SGB => d16(Wrapping(0x0014)),
             _ => d16(Wrapping(0x0013)),
         };
         let DE: d16 = match mode {
             CpuMode::CGB => d16(Wrapping(0x0008)),
+            CpuMode::SGB => d16(Wrapping(0x0000)),
             _ => d16(Wrapping(0x00d8)),
         };
         let HL: d16 = match mode {
             CpuMode::CGB => d16(Wrapping(0x007c)),
+            CpuMode::SGB => d16(Wrapping(0xc060)),
             _ => d16(Wrapping(0x014d)),
         };
         Registers { registers: [AF, BC, DE, HL] }
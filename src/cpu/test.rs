@@ -49,6 +49,17 @@ fn initial_register_values_mgb() {
     assert_eq!(cpu.program_counter, 0x0100);
 }
 
+#[test]
+fn initial_register_values_sgb() {
+    let cpu = Cpu::new(super::CpuMode::SGB);
+    assert_eq!(cpu.gp_registers[r16::AF], 0x0100);
+    assert_eq!(cpu.gp_registers[r16::BC], 0x0014);
+    assert_eq!(cpu.gp_registers[r16::DE], 0x0000);
+    assert_eq!(cpu.gp_registers[r16::HL], 0xc060);
+    assert_eq!(cpu.stack_pointer, 0xfffe);
+    assert_eq!(cpu.program_counter, 0x0100);
+}
+
 #[test]
 fn initial_register_values_cgb() {
     let cpu = Cpu::new(super::CpuMode::CGB);
@@ -133,3 +144,27 @@ fn addition_instructions() {
         d16(Wrapping(0x014du16) + Wrapping(0x0013u16))
     );
 }
+
+#[test]
+fn stop_switches_speed_when_armed() {
+    use number_types::a16_type::a16;
+    let mut cpu = Cpu::new(super::CpuMode::CGB);
+    cpu.memory.put_d8(a16(Wrapping(0xff4d)), d8(Wrapping(0x01)));
+    cpu.process_instruction(::instructions::RawOpcode::STOP_0);
+    assert!(cpu.memory.double_speed());
+    assert_eq!(cpu.program_counter, 0x0102);
+    assert_eq!(cpu.cycle_count, 4 + 8200);
+}
+
+#[test]
+fn step_stops_for_a_halting_watch() {
+    use number_types::a16_type::a16;
+    use memory::watch::{WatchKind, WatchAction};
+    let mut cpu = Cpu::new(super::CpuMode::DMG);
+    let second = a16(Wrapping(0x0101));
+    cpu.memory.add_watch(WatchKind::Execute, second, second, |_| WatchAction::Halt);
+    assert_eq!(cpu.step(::instructions::RawOpcode::NOP), WatchAction::Continue);
+    assert_eq!(cpu.step(::instructions::RawOpcode::NOP), WatchAction::Halt);
+    assert_eq!(cpu.program_counter, 0x0102);
+    assert_eq!(cpu.step(::instructions::RawOpcode::NOP), WatchAction::Continue);
+}
@@ -1,4 +1,7 @@
+extern crate png;
+
 mod instructions;
-mod cpu;
-mod number_types;
-mod memory;
+pub mod cpu;
+pub mod number_types;
+pub mod memory;
+pub mod ppu;
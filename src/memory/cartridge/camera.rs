@@ -0,0 +1,381 @@
+use number_types::d8_type::d8;
+use std::fs::File;
+use std::io;
+use std::num::Wrapping;
+use std::path::Path;
+use png;
+use super::{Cartridge, CartridgeEvent, ram_to_bytes, load_ram_bytes, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+/*
+Pocket Camera (MAC-GBD) registers mapped over ROM:
+
+$0000-$1FFF	RAM write enable - $0A enables. RAM can be read regardless.
+$2000-$3FFF	ROM bank number, 6 bits
+$4000-$5FFF	RAM bank number, $00-$0F, or $10 to map the sensor registers
+
+With bank $10 selected, $A000-$A035 (mirrored every $80) are the M64282FP
+registers. Only $A000 can be read back; everything else reads $00.
+
+$A000	bit 0 - write 1 to start a capture, reads 1 until it's finished
+$A001	bit 7 - N, bits 5-6 - VH (edge enhancement mode), bits 0-4 - gain
+$A002	exposure time, high byte
+$A003	exposure time, low byte
+$A004	bits 4-6 - edge enhancement ratio, bit 3 - invert output
+$A005	zero point / output offset (not modelled)
+$A006-$A035	4x4 dithering matrix: three thresholds per pixel position
+
+A finished capture is a 128x112 image, 2bpp in the same layout as tiles
+in character RAM, written to RAM bank 0 starting at $A100. The RAM reads
+$00 while a capture is in progress.
+*/
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+const IMAGE_OFFSET: usize = 0x100;
+const REGISTER_BANK: usize = 0x10;
+const REGISTER_COUNT: usize = 0x36;
+const MATRIX_START: usize = 0x06;
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+// A grayscale picture for the sensor to look at, 0 is black and 255 is
+// white. It can be any size; it's scaled to the sensor's 128x112.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl CameraImage {
+    pub fn from_grayscale(width: usize, height: usize, pixels: Vec<u8>) -> Option<Self> {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return None;
+        }
+        Some(CameraImage { width, height, pixels })
+    }
+
+    pub fn from_png_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bad_data = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(bad_data)?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(bad_data)?;
+        let buf = &buf[..info.buffer_size()];
+
+        let channels = info.color_type.samples();
+        let pixels = buf.chunks(channels).map(|px| {
+            if channels >= 3 {
+                // ITU-R 601 luma, near enough for a sensor this bad
+                ((px[0] as u32 * 299 + px[1] as u32 * 587 + px[2] as u32 * 114) / 1000) as u8
+            } else {
+                px[0]
+            }
+        }).collect();
+
+        Self::from_grayscale(info.width as usize, info.height as usize, pixels)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty image"))
+    }
+
+    fn sample(&self, x: usize, y: usize) -> u8 {
+        let sx = x * self.width / CAMERA_WIDTH;
+        let sy = y * self.height / CAMERA_HEIGHT;
+        self.pixels[sy * self.width + sx]
+    }
+}
+
+pub struct Camera {
+    rom: Vec<d8>,
+    ram: Vec<d8>,
+    ram_write_enabled: bool,
+    rom_bank: usize,
+    ram_bank: usize,
+    registers: [u8; REGISTER_COUNT],
+    // T-cycles left until the capture in progress finishes
+    capture_cycles_left: u64,
+    capture_registers: [u8; REGISTER_COUNT],
+    image: Option<CameraImage>,
+    events: Vec<CartridgeEvent>,
+}
+
+impl Camera {
+    pub fn new(rom: Vec<d8>) -> Self {
+        Camera {
+            rom,
+            ram: vec![d8::ZERO; 16 * RAM_BANK_SIZE],
+            ram_write_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers: [0; REGISTER_COUNT],
+            capture_cycles_left: 0,
+            capture_registers: [0; REGISTER_COUNT],
+            image: None,
+            events: Vec::new(),
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        ::std::cmp::max(self.rom.len() / ROM_BANK_SIZE, 1)
+    }
+
+    fn capturing(&self) -> bool {
+        self.capture_cycles_left > 0
+    }
+
+    fn start_capture(&mut self) {
+        let regs = self.registers;
+        let exposure = ((regs[2] as u64) << 8) | regs[3] as u64;
+        let n = (regs[1] & 0x80) != 0;
+        // in 1MHz CPU cycles, according to the Pan Docs
+        let m_cycles = 32446 + if n { 0 } else { 512 } + 16 * exposure;
+        self.capture_cycles_left = 4 * m_cycles;
+        self.capture_registers = regs;
+    }
+
+    // brightness of each sensor pixel after exposure, gain and edge
+    // enhancement, on a 0-255 scale. This is a linear stand-in for the
+    // sensor's analog stages, not a model of them.
+    fn exposed_image(&self) -> Vec<f32> {
+        let regs = &self.capture_registers;
+        let exposure = (((regs[2] as u32) << 8) | regs[3] as u32) as f32 / 0x1000 as f32;
+        let gain = 1.0 + (regs[1] & 0x1f) as f32 / 8.0;
+
+        let mut exposed = vec![0.0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let sensed = self.image.as_ref().map(|img| img.sample(x, y)).unwrap_or(0);
+                exposed[y * CAMERA_WIDTH + x] = sensed as f32 * exposure * gain;
+            }
+        }
+
+        let edge_mode = (regs[1] >> 5) & 0b11;
+        if edge_mode != 0 {
+            let ratio = EDGE_RATIOS[((regs[4] >> 4) & 0b111) as usize];
+            let at = |x: isize, y: isize| {
+                let x = x.max(0).min(CAMERA_WIDTH as isize - 1) as usize;
+                let y = y.max(0).min(CAMERA_HEIGHT as isize - 1) as usize;
+                exposed[y * CAMERA_WIDTH + x]
+            };
+            let mut enhanced = exposed.clone();
+            for y in 0..CAMERA_HEIGHT as isize {
+                for x in 0..CAMERA_WIDTH as isize {
+                    let centre = at(x, y);
+                    let horizontal = 2.0 * centre - at(x - 1, y) - at(x + 1, y);
+                    let vertical = 2.0 * centre - at(x, y - 1) - at(x, y + 1);
+                    let edge = match edge_mode {
+                        0b01 => horizontal,
+                        0b10 => vertical,
+                        _ => horizontal + vertical,
+                    };
+                    enhanced[y as usize * CAMERA_WIDTH + x as usize] = centre + ratio * edge / 2.0;
+                }
+            }
+            exposed = enhanced;
+        }
+
+        if (regs[4] & 0x08) != 0 {
+            for px in exposed.iter_mut() {
+                *px = 255.0 - *px;
+            }
+        }
+        exposed
+    }
+
+    // each pixel is compared against the three thresholds for its position
+    // in the 4x4 matrix: below the first is black, above the third is white
+    fn dither(&self, brightness: f32, x: usize, y: usize) -> u8 {
+        let base = MATRIX_START + 3 * ((x & 3) + 4 * (y & 3));
+        let thresholds = &self.capture_registers[base..base + 3];
+        if brightness < thresholds[0] as f32 {
+            3
+        } else if brightness < thresholds[1] as f32 {
+            2
+        } else if brightness < thresholds[2] as f32 {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn finish_capture(&mut self) {
+        let exposed = self.exposed_image();
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let shade = self.dither(exposed[y * CAMERA_WIDTH + x], x, y);
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let row = IMAGE_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                for plane in 0..2 {
+                    let mask = d8(Wrapping(1 << bit));
+                    let set = d8(Wrapping(((shade >> plane) & 1) << bit));
+                    self.ram[row + plane] = (self.ram[row + plane] & !mask) | set;
+                }
+            }
+        }
+        self.registers[0] &= !1;
+        self.events.push(CartridgeEvent::SaveChanged);
+    }
+}
+
+impl Cartridge for Camera {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let bank = self.rom_bank_at(idx);
+        let offset = idx % ROM_BANK_SIZE;
+        self.rom.get(bank * ROM_BANK_SIZE + offset).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, idx: usize, d8(Wrapping(val)): d8) {
+        match idx {
+            0x0000 ... 0x1fff => self.ram_write_enabled = val == 0x0a,
+            0x2000 ... 0x3fff => self.rom_bank = (val & 0x3f) as usize,
+            0x4000 ... 0x5fff => self.ram_bank = (val & 0x1f) as usize,
+            _ => (),
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        match idx {
+            0x0000 ... 0x3fff => 0,
+            _ => self.rom_bank % self.rom_bank_count(),
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank & 0x0f
+    }
+
+    fn read_ram(&self, idx: usize) -> Option<d8> {
+        if (self.ram_bank & REGISTER_BANK) != 0 {
+            let val = match idx & 0x7f {
+                0x00 => (self.registers[0] & !1) | self.capturing() as u8,
+                _ => 0x00,
+            };
+            return Some(d8(Wrapping(val)));
+        }
+        if self.capturing() {
+            return Some(d8::ZERO);
+        }
+        Some(self.ram[(self.ram_bank & 0x0f) * RAM_BANK_SIZE + idx])
+    }
+
+    fn write_ram(&mut self, idx: usize, val: d8) -> Option<()> {
+        if (self.ram_bank & REGISTER_BANK) != 0 {
+            let reg = idx & 0x7f;
+            if reg >= REGISTER_COUNT {
+                return None;
+            }
+            let d8(Wrapping(byte)) = val;
+            self.registers[reg] = byte;
+            if reg == 0 && (byte & 1) != 0 && !self.capturing() {
+                self.start_capture();
+            }
+            return Some(());
+        }
+        if !self.ram_write_enabled || self.capturing() {
+            return None;
+        }
+        self.ram[(self.ram_bank & 0x0f) * RAM_BANK_SIZE + idx] = val;
+        Some(())
+    }
+
+    fn ram_writable(&self) -> bool {
+        (self.ram_bank & REGISTER_BANK) != 0 || (self.ram_write_enabled && !self.capturing())
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if self.capturing() {
+            if cycles >= self.capture_cycles_left {
+                self.capture_cycles_left = 0;
+                self.finish_capture();
+            } else {
+                self.capture_cycles_left -= cycles;
+            }
+        }
+    }
+
+    fn set_camera_image(&mut self, image: CameraImage) {
+        self.image = Some(image);
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        ram_to_bytes(&self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram_bytes(&mut self.ram, data);
+    }
+
+    fn take_events(&mut self) -> Vec<CartridgeEvent> {
+        ::std::mem::replace(&mut self.events, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_reg(cam: &mut Camera, reg: usize, val: u8) {
+        cam.write_ram(reg, d8(Wrapping(val)));
+    }
+
+    fn camera_with_matrix(thresholds: [u8; 3]) -> Camera {
+        let mut cam = Camera::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE]);
+        cam.write_rom(0x4000, d8(Wrapping(0x10)));
+        for i in 0..16 {
+            for j in 0..3 {
+                write_reg(&mut cam, MATRIX_START + 3 * i + j, thresholds[j]);
+            }
+        }
+        // exposure $1000, gain 0 - the sensor sees the image as-is
+        write_reg(&mut cam, 2, 0x10);
+        write_reg(&mut cam, 3, 0x00);
+        cam
+    }
+
+    fn take_photo(cam: &mut Camera) {
+        write_reg(cam, 0, 0x01);
+        assert_eq!(cam.read_ram(0), Some(d8(Wrapping(0x01))));
+        cam.tick(u64::max_value());
+        assert_eq!(cam.read_ram(0), Some(d8::ZERO));
+        cam.write_rom(0x4000, d8::ZERO);
+    }
+
+    #[test]
+    fn capture_takes_time() {
+        let mut cam = camera_with_matrix([0x40, 0x80, 0xc0]);
+        write_reg(&mut cam, 0, 0x01);
+        cam.tick(4 * 32446);
+        assert_eq!(cam.read_ram(0), Some(d8(Wrapping(0x01))));
+        cam.tick(4 * (512 + 16 * 0x1000));
+        assert_eq!(cam.read_ram(0), Some(d8::ZERO));
+    }
+
+    #[test]
+    fn gray_levels_through_matrix() {
+        let mut cam = camera_with_matrix([0x40, 0x80, 0xc0]);
+        // a 4-wide image: one column of each shade's brightness
+        let image = CameraImage::from_grayscale(4, 1, vec![0x00, 0x60, 0xa0, 0xff]).unwrap();
+        cam.set_camera_image(image);
+        take_photo(&mut cam);
+
+        // the first tile row is 8 pixels of black (3), since image column
+        // 0 covers sensor columns 0-31
+        assert_eq!(cam.read_ram(IMAGE_OFFSET), Some(d8(Wrapping(0xff))));
+        assert_eq!(cam.read_ram(IMAGE_OFFSET + 1), Some(d8(Wrapping(0xff))));
+        // tile 4 covers columns 32-39: shade 2
+        assert_eq!(cam.read_ram(IMAGE_OFFSET + 4 * 16), Some(d8::ZERO));
+        assert_eq!(cam.read_ram(IMAGE_OFFSET + 4 * 16 + 1), Some(d8(Wrapping(0xff))));
+        // tile 8: shade 1
+        assert_eq!(cam.read_ram(IMAGE_OFFSET + 8 * 16), Some(d8(Wrapping(0xff))));
+        assert_eq!(cam.read_ram(IMAGE_OFFSET + 8 * 16 + 1), Some(d8::ZERO));
+        // tile 12: white
+        assert_eq!(cam.read_ram(IMAGE_OFFSET + 12 * 16), Some(d8::ZERO));
+        assert_eq!(cam.read_ram(IMAGE_OFFSET + 12 * 16 + 1), Some(d8::ZERO));
+    }
+
+    #[test]
+    fn image_must_match_dimensions() {
+        assert!(CameraImage::from_grayscale(2, 2, vec![0; 3]).is_none());
+        assert!(CameraImage::from_grayscale(0, 0, vec![]).is_none());
+    }
+}
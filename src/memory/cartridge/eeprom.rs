@@ -0,0 +1,170 @@
+/*
+The 93LC56 is a 2Kbit serial EEPROM, organized here as 128 16-bit words.
+It's bit-banged through a single register: every rising edge of CLK while
+CS is high shifts one bit in from DI, or one bit out onto DO.
+
+Every command starts with a 1 bit, followed by a 2-bit opcode and an 8-bit
+address field (the top address bit is ignored):
+
+1 10 xAAAAAAA	READ - shifts out a dummy 0, then the 16-bit word, MSB first
+1 01 xAAAAAAA	WRITE - followed by 16 data bits, MSB first
+1 11 xAAAAAAA	ERASE - sets the word to $FFFF
+1 00 11xxxxxx	EWEN - enable writes and erases
+1 00 00xxxxxx	EWDS - disable writes and erases
+1 00 10xxxxxx	ERAL - erase every word
+1 00 01xxxxxx	WRAL - followed by 16 data bits, written to every word
+
+Writes are ignored until an EWEN. Once a write or erase completes, DO
+reads 1 to signal that the chip is ready.
+*/
+pub const EEPROM_WORDS: usize = 128;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum WriteTarget {
+    Word(usize),
+    All,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    Command { bits: u16, count: u8 },
+    WriteData { target: WriteTarget, bits: u16, count: u8 },
+    Reading { word: u16, remaining: u8 },
+    Done,
+}
+
+pub struct Eeprom93LC56 {
+    words: [u16; EEPROM_WORDS],
+    cs: bool,
+    clk: bool,
+    di: bool,
+    data_out: bool,
+    write_enabled: bool,
+    state: State,
+}
+
+impl Eeprom93LC56 {
+    pub fn new() -> Self {
+        Eeprom93LC56 {
+            // an erased EEPROM reads as all ones
+            words: [0xffff; EEPROM_WORDS],
+            cs: false,
+            clk: false,
+            di: false,
+            data_out: true,
+            write_enabled: false,
+            state: State::Idle,
+        }
+    }
+
+    pub fn words(&self) -> &[u16; EEPROM_WORDS] {
+        &self.words
+    }
+
+    pub fn words_mut(&mut self) -> &mut [u16; EEPROM_WORDS] {
+        &mut self.words
+    }
+
+    // the pin state as seen through the MBC7 register:
+    // bit 7 - CS, bit 6 - CLK, bit 1 - DI, bit 0 - DO
+    pub fn read_pins(&self) -> u8 {
+        ((self.cs as u8) << 7)
+            | ((self.clk as u8) << 6)
+            | ((self.di as u8) << 1)
+            | (self.data_out as u8)
+    }
+
+    pub fn write_pins(&mut self, val: u8) {
+        let cs = (val & 0x80) != 0;
+        let clk = (val & 0x40) != 0;
+        self.di = (val & 0x02) != 0;
+
+        if !cs {
+            // dropping CS aborts whatever was in progress
+            self.state = State::Idle;
+        } else if clk && !self.clk {
+            self.rising_edge();
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    fn rising_edge(&mut self) {
+        let di = self.di as u16;
+        self.state = match self.state {
+            State::Idle if di == 1 => State::Command { bits: 0, count: 0 },
+            State::Idle => State::Idle,
+            State::Command { bits, count } => {
+                let bits = (bits << 1) | di;
+                if count + 1 == 10 {
+                    self.decode(bits)
+                } else {
+                    State::Command { bits, count: count + 1 }
+                }
+            }
+            State::WriteData { target, bits, count } => {
+                let bits = (bits << 1) | di;
+                if count + 1 == 16 {
+                    self.write(target, bits);
+                    State::Done
+                } else {
+                    State::WriteData { target, bits, count: count + 1 }
+                }
+            }
+            State::Reading { word, remaining } => {
+                self.data_out = (word & (1 << (remaining - 1))) != 0;
+                if remaining == 1 {
+                    State::Done
+                } else {
+                    State::Reading { word, remaining: remaining - 1 }
+                }
+            }
+            State::Done => State::Done,
+        }
+    }
+
+    fn decode(&mut self, bits: u16) -> State {
+        let opcode = (bits >> 8) & 0b11;
+        let addr = (bits & 0x7f) as usize;
+        match opcode {
+            0b10 => {
+                self.data_out = false;
+                State::Reading { word: self.words[addr], remaining: 16 }
+            }
+            0b01 => State::WriteData { target: WriteTarget::Word(addr), bits: 0, count: 0 },
+            0b11 => {
+                self.write(WriteTarget::Word(addr), 0xffff);
+                State::Done
+            }
+            _ => match (bits >> 6) & 0b11 {
+                0b11 => {
+                    self.write_enabled = true;
+                    State::Done
+                }
+                0b00 => {
+                    self.write_enabled = false;
+                    State::Done
+                }
+                0b10 => {
+                    self.write(WriteTarget::All, 0xffff);
+                    State::Done
+                }
+                _ => State::WriteData { target: WriteTarget::All, bits: 0, count: 0 },
+            },
+        }
+    }
+
+    fn write(&mut self, target: WriteTarget, val: u16) {
+        if self.write_enabled {
+            match target {
+                WriteTarget::Word(addr) => self.words[addr] = val,
+                WriteTarget::All => for word in self.words.iter_mut() {
+                    *word = val;
+                },
+            }
+        }
+        self.data_out = true;
+    }
+}
@@ -0,0 +1,149 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use memory::infrared::{InfraredEndpoint, Disconnected};
+use super::{Cartridge, ram_to_bytes, load_ram_bytes, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+/*
+HuC-1 registers mapped over ROM:
+
+$0000-$1FFF	$0E maps the IR port into $A000-$BFFF; anything else maps RAM
+$2000-$3FFF	ROM bank number, 6 bits
+$4000-$5FFF	RAM bank number, 2 bits
+
+In IR mode, reading $A000-$BFFF gives $C1 while the sensor sees light and
+$C0 otherwise, and bit 0 of a write switches the LED.
+*/
+const IR_MODE: u8 = 0x0e;
+
+pub struct Huc1 {
+    rom: Vec<d8>,
+    ram: Vec<d8>,
+    ir_mode: bool,
+    rom_bank: usize,
+    ram_bank: usize,
+    infrared: Box<dyn InfraredEndpoint>,
+}
+
+impl Huc1 {
+    pub fn new(rom: Vec<d8>, ram_size: usize) -> Self {
+        Huc1 {
+            rom,
+            ram: vec![d8::ZERO; ram_size],
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            infrared: Box::new(Disconnected),
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        ::std::cmp::max(self.rom.len() / ROM_BANK_SIZE, 1)
+    }
+
+    fn ram_index(&self, idx: usize) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        Some((self.ram_bank * RAM_BANK_SIZE + idx) % self.ram.len())
+    }
+}
+
+impl Cartridge for Huc1 {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let bank = self.rom_bank_at(idx);
+        let offset = idx % ROM_BANK_SIZE;
+        self.rom.get(bank * ROM_BANK_SIZE + offset).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, idx: usize, d8(Wrapping(val)): d8) {
+        match idx {
+            0x0000 ... 0x1fff => self.ir_mode = val == IR_MODE,
+            0x2000 ... 0x3fff => self.rom_bank = (val & 0x3f) as usize,
+            0x4000 ... 0x5fff => self.ram_bank = (val & 0x03) as usize,
+            _ => (),
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        match idx {
+            0x0000 ... 0x3fff => 0,
+            _ => self.rom_bank % self.rom_bank_count(),
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn read_ram(&self, idx: usize) -> Option<d8> {
+        if self.ir_mode {
+            return Some(d8(Wrapping(0xc0 | self.infrared.receiving() as u8)));
+        }
+        self.ram_index(idx).map(|i| self.ram[i])
+    }
+
+    fn write_ram(&mut self, idx: usize, val: d8) -> Option<()> {
+        if self.ir_mode {
+            let d8(Wrapping(val)) = val;
+            self.infrared.set_led((val & 1) != 0);
+            return Some(());
+        }
+        let i = self.ram_index(idx)?;
+        self.ram[i] = val;
+        Some(())
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        ram_to_bytes(&self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram_bytes(&mut self.ram, data);
+    }
+
+    fn connect_infrared(&mut self, endpoint: Box<dyn InfraredEndpoint>) {
+        self.infrared = endpoint;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use memory::infrared;
+
+    #[test]
+    fn ir_mode_reads_the_sensor_and_drives_the_led() {
+        let (a, b) = infrared::link();
+        let mut cart = Huc1::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE);
+        let mut other = Huc1::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE);
+        cart.connect_infrared(Box::new(a));
+        other.connect_infrared(Box::new(b));
+        cart.write_rom(0x0000, d8(Wrapping(IR_MODE)));
+        other.write_rom(0x1fff, d8(Wrapping(IR_MODE)));
+
+        assert_eq!(cart.read_ram(0), Some(d8(Wrapping(0xc0))));
+        other.write_ram(0x1000, d8(Wrapping(0x01)));
+        assert_eq!(cart.read_ram(0), Some(d8(Wrapping(0xc1))));
+        other.write_ram(0, d8(Wrapping(0xfe)));
+        assert_eq!(cart.read_ram(0), Some(d8(Wrapping(0xc0))));
+
+        // IR writes don't go to RAM
+        cart.write_ram(0, d8(Wrapping(0x01)));
+        cart.write_rom(0x0000, d8::ZERO);
+        assert_eq!(cart.read_ram(0), Some(d8::ZERO));
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut cart = Huc1::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], 4 * RAM_BANK_SIZE);
+        cart.write_ram(0x0010, d8(Wrapping(0x11)));
+        cart.write_rom(0x4000, d8(Wrapping(0x03)));
+        assert_eq!(cart.ram_bank(), 3);
+        assert_eq!(cart.read_ram(0x0010), Some(d8::ZERO));
+        cart.write_ram(0x0010, d8(Wrapping(0x33)));
+        // only two bits of bank number
+        cart.write_rom(0x4000, d8(Wrapping(0x04)));
+        assert_eq!(cart.read_ram(0x0010), Some(d8(Wrapping(0x11))));
+        assert_eq!(cart.save_data()[3 * RAM_BANK_SIZE + 0x0010], 0x33);
+    }
+}
@@ -0,0 +1,434 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use memory::infrared::{InfraredEndpoint, Disconnected};
+use memory::rtc::{self, RtcFooter, RtcRegisters, unix_now};
+use super::{Cartridge, ram_to_bytes, load_ram_bytes, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+/*
+HuC-3 registers mapped over ROM:
+
+$0000-$1FFF	what $A000-$BFFF talks to, by the low nibble:
+		$0 - RAM, read-only
+		$A - RAM, read/write
+		$B - RTC command (write)
+		$C - RTC response (read)
+		$D - RTC semaphore, reads 1 when the RTC is ready
+		$E - IR port
+$2000-$3FFF	ROM bank number, 7 bits
+$4000-$5FFF	RAM bank number, 2 bits
+
+An RTC command byte has the command in the high nibble and its argument in
+the low nibble. The RTC has a small nibble-addressed memory; the first
+three nibbles are the minute of the day and the next four the day counter:
+
+$1x	read the nibble at the address into the response, then increment
+$2x	write x to the nibble at the address
+$3x	write x to the nibble at the address, then increment
+$4x	set the low nibble of the address
+$5x	set the high nibble of the address
+$6x	set the access flags; $62 makes the response read 1
+
+The speaker is driven through the same command interface, but rgb has no
+sound output so those commands only update the alarm state.
+
+Saves carry the clock in the 48-byte footer from rtc.rs, the same one MBC3
+carts use, so they move between emulators. The footer only has room for 9
+bits of the day counter, so past 511 days the day-carry bit is set and the
+count wraps, and there's no room for the alarm, which resets on load.
+Saves with the older 17-byte SameBoy HuC-3 footer still load.
+*/
+const MINUTES_PER_DAY: u64 = 60 * 24;
+pub const HUC3_RTC_FOOTER_LEN: usize = 17;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Huc3Clock {
+    // the UNIX time `minutes` and `days` were last brought up to date
+    pub last_rtc_second: u64,
+    pub minutes: u16,
+    pub days: u16,
+    pub alarm_minutes: u16,
+    pub alarm_days: u16,
+    pub alarm_enabled: bool,
+}
+
+impl Huc3Clock {
+    pub fn new(now: u64) -> Self {
+        Huc3Clock {
+            last_rtc_second: now,
+            minutes: 0,
+            days: 0,
+            alarm_minutes: 0,
+            alarm_days: 0,
+            alarm_enabled: false,
+        }
+    }
+
+    pub fn catch_up(&mut self, now: u64) {
+        if now <= self.last_rtc_second {
+            return;
+        }
+        let elapsed_minutes = (now - self.last_rtc_second) / 60;
+        // keep the leftover seconds around so they count towards the next minute
+        self.last_rtc_second += elapsed_minutes * 60;
+        let total = self.minutes as u64 + elapsed_minutes;
+        self.minutes = (total % MINUTES_PER_DAY) as u16;
+        self.days = self.days.wrapping_add((total / MINUTES_PER_DAY) as u16);
+    }
+
+    // HuC-3 has no latch, so both copies of the registers hold the live time
+    pub fn to_footer(&self) -> RtcFooter {
+        let days_high = ((self.days >> 8) & 1) as u8 | if self.days >= 512 { 0x80 } else { 0 };
+        let regs = RtcRegisters {
+            seconds: 0,
+            minutes: (self.minutes % 60) as u8,
+            hours: (self.minutes / 60) as u8,
+            days_low: self.days as u8,
+            days_high,
+        };
+        RtcFooter {
+            live: regs,
+            latched: regs,
+            timestamp: self.last_rtc_second,
+        }
+    }
+
+    pub fn from_footer(footer: &RtcFooter) -> Self {
+        let live = footer.live;
+        let mut clock = Huc3Clock::new(footer.timestamp.saturating_sub(live.seconds as u64));
+        clock.minutes = ((live.hours as u64 * 60 + live.minutes as u64) % MINUTES_PER_DAY) as u16;
+        clock.days = live.days();
+        clock
+    }
+
+    // the little-endian layout SameBoy appends to HuC-3 saves
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HUC3_RTC_FOOTER_LEN);
+        for i in 0..8 {
+            bytes.push((self.last_rtc_second >> (8 * i)) as u8);
+        }
+        for &half in &[self.minutes, self.days, self.alarm_minutes, self.alarm_days] {
+            bytes.push(half as u8);
+            bytes.push((half >> 8) as u8);
+        }
+        bytes.push(self.alarm_enabled as u8);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HUC3_RTC_FOOTER_LEN {
+            return None;
+        }
+        let mut last_rtc_second = 0u64;
+        for i in 0..8 {
+            last_rtc_second |= (bytes[i] as u64) << (8 * i);
+        }
+        let half = |i: usize| (bytes[i] as u16) | ((bytes[i + 1] as u16) << 8);
+        Some(Huc3Clock {
+            last_rtc_second,
+            minutes: half(8),
+            days: half(10),
+            alarm_minutes: half(12),
+            alarm_days: half(14),
+            alarm_enabled: (bytes[16] & 1) != 0,
+        })
+    }
+}
+
+fn set_nibble(val: u16, nibble: u8, x: u8) -> u16 {
+    let shift = 4 * nibble as u16;
+    (val & !(0xf << shift)) | ((x as u16 & 0xf) << shift)
+}
+
+pub struct Huc3 {
+    rom: Vec<d8>,
+    ram: Vec<d8>,
+    mode: u8,
+    rom_bank: usize,
+    ram_bank: usize,
+    clock: Huc3Clock,
+    access_index: u8,
+    access_flags: u8,
+    response: u8,
+    infrared: Box<dyn InfraredEndpoint>,
+}
+
+impl Huc3 {
+    pub fn new(rom: Vec<d8>, ram_size: usize) -> Self {
+        Huc3 {
+            rom,
+            ram: vec![d8::ZERO; ram_size],
+            mode: 0,
+            rom_bank: 1,
+            ram_bank: 0,
+            clock: Huc3Clock::new(unix_now()),
+            access_index: 0,
+            access_flags: 0,
+            response: 0,
+            infrared: Box::new(Disconnected),
+        }
+    }
+
+    pub fn clock(&self) -> &Huc3Clock {
+        &self.clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut Huc3Clock {
+        &mut self.clock
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        ::std::cmp::max(self.rom.len() / ROM_BANK_SIZE, 1)
+    }
+
+    fn ram_index(&self, idx: usize) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        Some((self.ram_bank * RAM_BANK_SIZE + idx) % self.ram.len())
+    }
+
+    fn read_rtc_nibble(&self) -> u8 {
+        let i = self.access_index;
+        match i {
+            0x00 ... 0x02 => (self.clock.minutes >> (4 * i)) as u8 & 0xf,
+            0x03 ... 0x06 => (self.clock.days >> (4 * (i - 3))) as u8 & 0xf,
+            _ => 0,
+        }
+    }
+
+    fn write_rtc_nibble(&mut self, x: u8) {
+        let i = self.access_index;
+        match i {
+            0x00 ... 0x02 => self.clock.minutes = set_nibble(self.clock.minutes, i, x),
+            0x03 ... 0x06 => self.clock.days = set_nibble(self.clock.days, i - 3, x),
+            0x58 ... 0x5a => {
+                self.clock.alarm_minutes = set_nibble(self.clock.alarm_minutes, i - 0x58, x)
+            }
+            0x5b ... 0x5e => {
+                self.clock.alarm_days = set_nibble(self.clock.alarm_days, i - 0x5b, x)
+            }
+            0x5f => self.clock.alarm_enabled = (x & 1) != 0,
+            _ => (),
+        }
+    }
+
+    fn rtc_command(&mut self, val: u8) {
+        self.clock.catch_up(unix_now());
+        let arg = val & 0x0f;
+        match val >> 4 {
+            0x1 => {
+                self.response = self.read_rtc_nibble();
+                self.access_index = self.access_index.wrapping_add(1);
+            }
+            0x2 => self.write_rtc_nibble(arg),
+            0x3 => {
+                self.write_rtc_nibble(arg);
+                self.access_index = self.access_index.wrapping_add(1);
+            }
+            0x4 => self.access_index = (self.access_index & 0xf0) | arg,
+            0x5 => self.access_index = (self.access_index & 0x0f) | (arg << 4),
+            0x6 => self.access_flags = arg,
+            _ => (),
+        }
+    }
+}
+
+impl Cartridge for Huc3 {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let bank = self.rom_bank_at(idx);
+        let offset = idx % ROM_BANK_SIZE;
+        self.rom.get(bank * ROM_BANK_SIZE + offset).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, idx: usize, d8(Wrapping(val)): d8) {
+        match idx {
+            0x0000 ... 0x1fff => self.mode = val & 0x0f,
+            0x2000 ... 0x3fff => self.rom_bank = (val & 0x7f) as usize,
+            0x4000 ... 0x5fff => self.ram_bank = (val & 0x03) as usize,
+            _ => (),
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        match idx {
+            0x0000 ... 0x3fff => 0,
+            _ => self.rom_bank % self.rom_bank_count(),
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn read_ram(&self, idx: usize) -> Option<d8> {
+        let val = match self.mode {
+            0x0 | 0xa => return self.ram_index(idx).map(|i| self.ram[i]),
+            0xc if self.access_flags == 0x2 => 0x01,
+            0xc => self.response,
+            0xe => 0xc0 | self.infrared.receiving() as u8,
+            _ => 0x01,
+        };
+        Some(d8(Wrapping(val)))
+    }
+
+    fn write_ram(&mut self, idx: usize, val: d8) -> Option<()> {
+        let d8(Wrapping(byte)) = val;
+        match self.mode {
+            0xa => {
+                let i = self.ram_index(idx)?;
+                self.ram[i] = val;
+            }
+            0xb => self.rtc_command(byte),
+            0xe => self.infrared.set_led((byte & 1) != 0),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    // mode 0 maps RAM read-only
+    fn ram_writable(&self) -> bool {
+        matches!(self.mode, 0xa | 0xb | 0xe)
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = ram_to_bytes(&self.ram);
+        let mut clock = self.clock;
+        clock.catch_up(unix_now());
+        data.extend(clock.to_footer().to_bytes());
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let (ram, footer) = rtc::split_footer(data, self.ram.len());
+        load_ram_bytes(&mut self.ram, ram);
+        let clock = match footer {
+            Some(footer) => Some(Huc3Clock::from_footer(&footer)),
+            None if data.len() == self.ram.len() + HUC3_RTC_FOOTER_LEN => {
+                Huc3Clock::from_bytes(&data[self.ram.len()..])
+            }
+            None => None,
+        };
+        if let Some(mut clock) = clock {
+            clock.catch_up(unix_now());
+            self.clock = clock;
+        }
+    }
+
+    fn connect_infrared(&mut self, endpoint: Box<dyn InfraredEndpoint>) {
+        self.infrared = endpoint;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use memory::infrared;
+
+    fn command(cart: &mut Huc3, val: u8) {
+        cart.write_ram(0, d8(Wrapping(val)));
+    }
+
+    #[test]
+    fn clock_catches_up() {
+        let mut clock = Huc3Clock::new(1000);
+        clock.minutes = MINUTES_PER_DAY as u16 - 1;
+        clock.catch_up(1000 + 2 * 60 + 30);
+        assert_eq!(clock.minutes, 1);
+        assert_eq!(clock.days, 1);
+        assert_eq!(clock.last_rtc_second, 1000 + 2 * 60);
+    }
+
+    #[test]
+    fn clock_round_trips_through_bytes() {
+        let mut clock = Huc3Clock::new(0x0123456789);
+        clock.minutes = 0x2a1;
+        clock.days = 0x1234;
+        clock.alarm_enabled = true;
+        let bytes = clock.to_bytes();
+        assert_eq!(bytes.len(), HUC3_RTC_FOOTER_LEN);
+        assert_eq!(Huc3Clock::from_bytes(&bytes), Some(clock));
+    }
+
+    #[test]
+    fn save_has_standard_footer() {
+        let mut cart = Huc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE);
+        cart.clock_mut().minutes = 61;
+        cart.clock_mut().days = 3;
+        let data = cart.save_data();
+        assert_eq!(data.len(), RAM_BANK_SIZE + rtc::RTC_FOOTER_LEN);
+
+        let footer = RtcFooter::from_bytes(&data[RAM_BANK_SIZE..]).unwrap();
+        assert_eq!((footer.live.hours, footer.live.minutes, footer.live.days()), (1, 1, 3));
+
+        let mut loaded = Huc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE);
+        loaded.load_save_data(&data);
+        assert_eq!(loaded.clock().minutes, 61);
+        assert_eq!(loaded.clock().days, 3);
+    }
+
+    #[test]
+    fn long_day_counts_set_the_carry() {
+        let mut cart = Huc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE);
+        cart.clock_mut().days = 0x0a17;
+        let data = cart.save_data();
+        assert_eq!(data.len(), RAM_BANK_SIZE + rtc::RTC_FOOTER_LEN);
+        let footer = RtcFooter::from_bytes(&data[RAM_BANK_SIZE..]).unwrap();
+        assert_eq!(footer.live.days(), 0x017);
+        assert_eq!(footer.live.days_high & 0x80, 0x80);
+    }
+
+    #[test]
+    fn loads_old_sameboy_footer() {
+        let mut clock = Huc3Clock::new(unix_now());
+        clock.days = 9;
+        let mut data = vec![0; RAM_BANK_SIZE];
+        data.extend(clock.to_bytes());
+        let mut cart = Huc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE);
+        cart.load_save_data(&data);
+        assert_eq!(cart.clock().days, 9);
+    }
+
+    #[test]
+    fn ram_is_read_only_in_mode_0() {
+        let mut cart = Huc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE);
+        assert!(cart.read_ram(0).is_some());
+        assert!(!cart.ram_writable());
+        cart.write_rom(0x0000, d8(Wrapping(0x0a)));
+        assert!(cart.ram_writable());
+    }
+
+    #[test]
+    fn rtc_write_and_read_minutes() {
+        let mut cart = Huc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE);
+        cart.write_rom(0x0000, d8(Wrapping(0x0b)));
+        // address 0, then write 5, 3, 2 → minute 0x235
+        command(&mut cart, 0x40);
+        command(&mut cart, 0x50);
+        command(&mut cart, 0x35);
+        command(&mut cart, 0x33);
+        command(&mut cart, 0x32);
+        assert_eq!(cart.clock().minutes, 0x235);
+
+        command(&mut cart, 0x40);
+        command(&mut cart, 0x10);
+        cart.write_rom(0x0000, d8(Wrapping(0x0c)));
+        assert_eq!(cart.read_ram(0), Some(d8(Wrapping(0x05))));
+    }
+
+    #[test]
+    fn infrared_between_two_carts() {
+        let (a, b) = infrared::link();
+        let mut sender = Huc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], 0);
+        let mut receiver = Huc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], 0);
+        sender.connect_infrared(Box::new(a));
+        receiver.connect_infrared(Box::new(b));
+        sender.write_rom(0x0000, d8(Wrapping(0x0e)));
+        receiver.write_rom(0x0000, d8(Wrapping(0x0e)));
+
+        assert_eq!(receiver.read_ram(0), Some(d8(Wrapping(0xc0))));
+        sender.write_ram(0, d8(Wrapping(0x01)));
+        assert_eq!(receiver.read_ram(0), Some(d8(Wrapping(0xc1))));
+        sender.write_ram(0, d8::ZERO);
+        assert_eq!(receiver.read_ram(0), Some(d8(Wrapping(0xc0))));
+    }
+}
@@ -0,0 +1,131 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use super::{Cartridge, ram_to_bytes, load_ram_bytes, ROM_BANK_SIZE};
+
+/*
+MBC2 registers, mapped over $0000-$3FFF; address bit 8 picks which one a
+write goes to:
+
+bit 8 clear	RAM enable - $A in the low nibble enables, anything else
+		disables
+bit 8 set	ROM bank number, 4 bits; 0 becomes 1
+
+The RAM is built into the MBC: 512 half-bytes, at $A000-$A1FF and mirrored
+through $BFFF. Only the low nibble of each byte is stored, and the high
+nibble reads as 1s. Saves are the 512 bytes, one nibble to a byte.
+*/
+const RAM_SIZE: usize = 0x200;
+const ROM_BANK_SELECT: usize = 0x0100;
+
+pub struct Mbc2 {
+    rom: Vec<d8>,
+    ram: Vec<d8>,
+    ram_enabled: bool,
+    rom_bank: usize,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<d8>) -> Self {
+        Mbc2 {
+            rom,
+            ram: vec![d8::ZERO; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        ::std::cmp::max(self.rom.len() / ROM_BANK_SIZE, 1)
+    }
+}
+
+impl Cartridge for Mbc2 {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let bank = self.rom_bank_at(idx);
+        let offset = idx % ROM_BANK_SIZE;
+        self.rom.get(bank * ROM_BANK_SIZE + offset).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, idx: usize, d8(Wrapping(val)): d8) {
+        match idx {
+            0x0000 ... 0x3fff if (idx & ROM_BANK_SELECT) != 0 => {
+                let bank = (val & 0x0f) as usize;
+                self.rom_bank = if bank == 0 { 1 } else { bank };
+            }
+            0x0000 ... 0x3fff => self.ram_enabled = (val & 0x0f) == 0x0a,
+            _ => (),
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        match idx {
+            0x0000 ... 0x3fff => 0,
+            _ => self.rom_bank % self.rom_bank_count(),
+        }
+    }
+
+    fn read_ram(&self, idx: usize) -> Option<d8> {
+        if !self.ram_enabled {
+            return None;
+        }
+        Some(self.ram[idx % RAM_SIZE] | d8(Wrapping(0xf0)))
+    }
+
+    fn write_ram(&mut self, idx: usize, val: d8) -> Option<()> {
+        if !self.ram_enabled {
+            return None;
+        }
+        self.ram[idx % RAM_SIZE] = val & d8(Wrapping(0x0f));
+        Some(())
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        ram_to_bytes(&self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram_bytes(&mut self.ram, data);
+        for byte in self.ram.iter_mut() {
+            *byte &= d8(Wrapping(0x0f));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn address_bit_8_picks_the_register() {
+        let mut rom = vec![d8::ZERO; 16 * ROM_BANK_SIZE];
+        rom[5 * ROM_BANK_SIZE] = d8(Wrapping(5));
+        let mut cart = Mbc2::new(rom);
+        cart.write_rom(0x2100, d8(Wrapping(0x05)));
+        assert_eq!(cart.read_rom(0x4000), 0x05);
+        // RAM enable, not a bank switch
+        cart.write_rom(0x2000, d8(Wrapping(0x0a)));
+        assert_eq!(cart.rom_bank_at(0x4000), 5);
+        assert!(cart.ram_enabled);
+        cart.write_rom(0x0100, d8::ZERO);
+        assert_eq!(cart.rom_bank_at(0x4000), 1);
+    }
+
+    #[test]
+    fn nibble_ram_saves() {
+        let mut cart = Mbc2::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE]);
+        assert_eq!(cart.read_ram(0), None);
+        cart.write_rom(0x0000, d8(Wrapping(0x0a)));
+        cart.write_ram(0x0003, d8(Wrapping(0x5c)));
+        assert_eq!(cart.read_ram(0x0003), Some(d8(Wrapping(0xfc))));
+        // mirrored every 512 bytes
+        assert_eq!(cart.read_ram(0x1203), Some(d8(Wrapping(0xfc))));
+
+        let data = cart.save_data();
+        assert_eq!(data.len(), RAM_SIZE);
+        assert_eq!(data[3], 0x0c);
+        let mut loaded = Mbc2::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE]);
+        loaded.load_save_data(&[0xff, 0x12]);
+        loaded.write_rom(0x0000, d8(Wrapping(0x0a)));
+        assert_eq!(loaded.ram[..2], [d8(Wrapping(0x0f)), d8(Wrapping(0x02))]);
+    }
+}
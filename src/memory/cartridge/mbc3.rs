@@ -0,0 +1,243 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use memory::rtc::{self, RtcFooter, RtcRegisters, unix_now};
+use super::{Cartridge, ram_to_bytes, load_ram_bytes, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+/*
+MBC3 registers (all write-only, mapped over ROM):
+
+$0000-$1FFF	RAM and clock enable - $0A enables, anything else disables
+$2000-$3FFF	ROM bank number, 7 bits; 0 becomes 1
+$4000-$5FFF	$00-$03 maps a RAM bank into $A000-$BFFF, $08-$0C a clock
+		register: seconds, minutes, hours, days low, days high
+$6000-$7FFF	writing $00 and then $01 latches the clock
+
+Clock registers read back what was last latched; writing one sets the
+running clock. The clock runs on the host's wall clock, like HuC-3's, and
+goes in the `.sav` as the 48-byte footer from rtc.rs.
+*/
+const RTC_SECONDS: u8 = 0x08;
+const RTC_DAYS_HIGH: u8 = 0x0c;
+
+pub struct Mbc3 {
+    rom: Vec<d8>,
+    ram: Vec<d8>,
+    has_clock: bool,
+    enabled: bool,
+    rom_bank: usize,
+    // a RAM bank, or one of the clock registers
+    ram_select: u8,
+    latch_primed: bool,
+    clock: RtcFooter,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<d8>, ram_size: usize, has_clock: bool) -> Self {
+        Mbc3 {
+            rom,
+            ram: vec![d8::ZERO; ram_size],
+            has_clock,
+            enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            latch_primed: false,
+            clock: RtcFooter {
+                live: RtcRegisters::default(),
+                latched: RtcRegisters::default(),
+                timestamp: unix_now(),
+            },
+        }
+    }
+
+    pub fn clock(&self) -> &RtcFooter {
+        &self.clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut RtcFooter {
+        &mut self.clock
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        ::std::cmp::max(self.rom.len() / ROM_BANK_SIZE, 1)
+    }
+
+    fn ram_index(&self, idx: usize) -> Option<usize> {
+        if !self.enabled || self.ram.is_empty() || self.ram_select >= RTC_SECONDS {
+            return None;
+        }
+        Some((self.ram_select as usize * RAM_BANK_SIZE + idx) % self.ram.len())
+    }
+
+    fn clock_register(&self) -> Option<u8> {
+        if !self.enabled || !self.has_clock {
+            return None;
+        }
+        match self.ram_select {
+            RTC_SECONDS ... RTC_DAYS_HIGH => Some(self.ram_select),
+            _ => None,
+        }
+    }
+}
+
+impl Cartridge for Mbc3 {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let bank = self.rom_bank_at(idx);
+        let offset = idx % ROM_BANK_SIZE;
+        self.rom.get(bank * ROM_BANK_SIZE + offset).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, idx: usize, d8(Wrapping(val)): d8) {
+        match idx {
+            0x0000 ... 0x1fff => self.enabled = (val & 0x0f) == 0x0a,
+            0x2000 ... 0x3fff => {
+                let bank = (val & 0x7f) as usize;
+                self.rom_bank = if bank == 0 { 1 } else { bank };
+            }
+            0x4000 ... 0x5fff => self.ram_select = val & 0x0f,
+            0x6000 ... 0x7fff => {
+                if self.latch_primed && val == 0x01 {
+                    self.clock.catch_up(unix_now());
+                    self.clock.latched = self.clock.live;
+                }
+                self.latch_primed = val == 0x00;
+            }
+            _ => (),
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        match idx {
+            0x0000 ... 0x3fff => 0,
+            _ => self.rom_bank % self.rom_bank_count(),
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_select as usize
+    }
+
+    fn read_ram(&self, idx: usize) -> Option<d8> {
+        if let Some(reg) = self.clock_register() {
+            let latched = self.clock.latched;
+            let val = match reg {
+                RTC_SECONDS => latched.seconds,
+                0x09 => latched.minutes,
+                0x0a => latched.hours,
+                0x0b => latched.days_low,
+                _ => latched.days_high,
+            };
+            return Some(d8(Wrapping(val)));
+        }
+        self.ram_index(idx).map(|i| self.ram[i])
+    }
+
+    fn write_ram(&mut self, idx: usize, val: d8) -> Option<()> {
+        if let Some(reg) = self.clock_register() {
+            // bring the clock up to now first, so the time until now
+            // counts with the old value and not the new
+            self.clock.catch_up(unix_now());
+            let d8(Wrapping(val)) = val;
+            let live = &mut self.clock.live;
+            match reg {
+                RTC_SECONDS => live.seconds = val & 0x3f,
+                0x09 => live.minutes = val & 0x3f,
+                0x0a => live.hours = val & 0x1f,
+                0x0b => live.days_low = val,
+                _ => live.days_high = val & 0xc1,
+            }
+            return Some(());
+        }
+        let i = self.ram_index(idx)?;
+        self.ram[i] = val;
+        Some(())
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = ram_to_bytes(&self.ram);
+        if self.has_clock {
+            let mut clock = self.clock;
+            clock.catch_up(unix_now());
+            data.extend(clock.to_bytes());
+        }
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let (ram, footer) = rtc::split_footer(data, self.ram.len());
+        load_ram_bytes(&mut self.ram, ram);
+        if let Some(mut footer) = footer {
+            footer.catch_up(unix_now());
+            self.clock = footer;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(cart: &mut Mbc3, idx: usize, val: u8) {
+        cart.write_rom(idx, d8(Wrapping(val)));
+    }
+
+    fn latch(cart: &mut Mbc3) {
+        write(cart, 0x6000, 0x00);
+        write(cart, 0x6000, 0x01);
+    }
+
+    #[test]
+    fn rom_and_ram_banks() {
+        let mut rom = vec![d8::ZERO; 128 * ROM_BANK_SIZE];
+        rom[0x7f * ROM_BANK_SIZE] = d8(Wrapping(0x7f));
+        let mut cart = Mbc3::new(rom, 4 * RAM_BANK_SIZE, false);
+        write(&mut cart, 0x2000, 0xff);
+        assert_eq!(cart.read_rom(0x4000), 0x7f);
+        write(&mut cart, 0x2000, 0x00);
+        assert_eq!(cart.rom_bank_at(0x4000), 1);
+
+        write(&mut cart, 0x0000, 0x0a);
+        write(&mut cart, 0x4000, 0x03);
+        cart.write_ram(0x0010, d8(Wrapping(0x33)));
+        write(&mut cart, 0x4000, 0x00);
+        assert_eq!(cart.read_ram(0x0010), Some(d8::ZERO));
+        // no clock on this one
+        write(&mut cart, 0x4000, RTC_SECONDS);
+        assert_eq!(cart.read_ram(0), None);
+    }
+
+    #[test]
+    fn clock_reads_what_was_latched() {
+        let mut cart = Mbc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE, true);
+        write(&mut cart, 0x0000, 0x0a);
+        cart.clock_mut().timestamp = unix_now() - (2 * 60 + 5);
+        write(&mut cart, 0x4000, 0x09);
+        assert_eq!(cart.read_ram(0), Some(d8::ZERO));
+        latch(&mut cart);
+        assert_eq!(cart.read_ram(0), Some(d8(Wrapping(2))));
+
+        // halt it, and set the hours
+        write(&mut cart, 0x4000, RTC_DAYS_HIGH);
+        cart.write_ram(0, d8(Wrapping(0x40)));
+        write(&mut cart, 0x4000, 0x0a);
+        cart.write_ram(0, d8(Wrapping(0xf7)));
+        cart.clock_mut().timestamp -= 3600;
+        latch(&mut cart);
+        assert_eq!(cart.read_ram(0), Some(d8(Wrapping(0x17))));
+    }
+
+    #[test]
+    fn save_has_standard_footer() {
+        let mut cart = Mbc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE, true);
+        cart.clock_mut().live.days_low = 42;
+        cart.clock_mut().live.days_high = 0x40;
+        let data = cart.save_data();
+        assert_eq!(data.len(), RAM_BANK_SIZE + rtc::RTC_FOOTER_LEN);
+
+        let mut loaded = Mbc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE, true);
+        loaded.load_save_data(&data);
+        assert_eq!(loaded.clock().live.days(), 42);
+
+        let plain = Mbc3::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE], RAM_BANK_SIZE, false);
+        assert_eq!(plain.save_data().len(), RAM_BANK_SIZE);
+    }
+}
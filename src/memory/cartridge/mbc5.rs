@@ -0,0 +1,184 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use super::{Cartridge, CartridgeEvent, ram_to_bytes, load_ram_bytes, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+/*
+MBC5 registers (all write-only, mapped over ROM):
+
+$0000-$1FFF	RAM enable - $0A enables, anything else disables
+$2000-$2FFF	ROM bank number, low 8 bits
+$3000-$3FFF	ROM bank number, bit 8
+$4000-$5FFF	RAM bank number, $00-$0F
+
+Unlike MBC1, bank 0 can be mapped into $4000-$7FFF. On rumble carts, bit 3
+of the RAM bank register drives the motor instead of selecting a bank, so
+those only get 8 RAM banks.
+*/
+const RUMBLE_BIT: u8 = 0b1000;
+
+pub struct Mbc5 {
+    rom: Vec<d8>,
+    ram: Vec<d8>,
+    ram_enabled: bool,
+    rom_bank: usize,
+    ram_bank: usize,
+    has_rumble: bool,
+    rumble_on: bool,
+    events: Vec<CartridgeEvent>,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<d8>, ram_size: usize, has_rumble: bool) -> Self {
+        Mbc5 {
+            rom,
+            ram: vec![d8::ZERO; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble_on: false,
+            events: Vec::new(),
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        ::std::cmp::max(self.rom.len() / ROM_BANK_SIZE, 1)
+    }
+
+    fn ram_bank_count(&self) -> usize {
+        ::std::cmp::max(self.ram.len() / RAM_BANK_SIZE, 1)
+    }
+
+    fn ram_index(&self, idx: usize) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let bank = self.ram_bank % self.ram_bank_count();
+        // carts with 2KiB of RAM mirror it across the whole window
+        Some((bank * RAM_BANK_SIZE + idx) % self.ram.len())
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let bank = self.rom_bank_at(idx);
+        let offset = idx % ROM_BANK_SIZE;
+        self.rom.get(bank * ROM_BANK_SIZE + offset).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, idx: usize, d8(Wrapping(val)): d8) {
+        match idx {
+            0x0000 ... 0x1fff => self.ram_enabled = val == 0x0a,
+            0x2000 ... 0x2fff => self.rom_bank = (self.rom_bank & 0x100) | val as usize,
+            0x3000 ... 0x3fff => {
+                self.rom_bank = (self.rom_bank & 0xff) | (((val & 1) as usize) << 8)
+            }
+            0x4000 ... 0x5fff => if self.has_rumble {
+                let rumble_on = (val & RUMBLE_BIT) != 0;
+                if rumble_on != self.rumble_on {
+                    self.rumble_on = rumble_on;
+                    self.events.push(CartridgeEvent::Rumble(rumble_on));
+                }
+                self.ram_bank = (val & 0x07) as usize;
+            } else {
+                self.ram_bank = (val & 0x0f) as usize;
+            },
+            _ => (),
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        match idx {
+            0x0000 ... 0x3fff => 0,
+            _ => self.rom_bank % self.rom_bank_count(),
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank % self.ram_bank_count()
+    }
+
+    fn read_ram(&self, idx: usize) -> Option<d8> {
+        self.ram_index(idx).map(|i| self.ram[i])
+    }
+
+    fn write_ram(&mut self, idx: usize, val: d8) -> Option<()> {
+        let i = self.ram_index(idx)?;
+        self.ram[i] = val;
+        Some(())
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        ram_to_bytes(&self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram_bytes(&mut self.ram, data);
+    }
+
+    fn take_events(&mut self) -> Vec<CartridgeEvent> {
+        ::std::mem::replace(&mut self.events, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn numbered_rom(banks: usize) -> Vec<d8> {
+        // the first byte of each bank holds the low byte of its number,
+        // and the second byte the high byte
+        let mut rom = vec![d8::ZERO; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = d8(Wrapping(bank as u8));
+            rom[bank * ROM_BANK_SIZE + 1] = d8(Wrapping((bank >> 8) as u8));
+        }
+        rom
+    }
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut cart = Mbc5::new(numbered_rom(512), 0, false);
+        cart.write_rom(0x2000, d8(Wrapping(0x23)));
+        cart.write_rom(0x3000, d8(Wrapping(0x01)));
+        assert_eq!(cart.read_rom(0x4000), 0x23);
+        assert_eq!(cart.read_rom(0x4001), 0x01);
+        assert_eq!(cart.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn bank_zero_in_switchable_region() {
+        let mut cart = Mbc5::new(numbered_rom(4), 0, false);
+        cart.write_rom(0x2000, d8::ZERO);
+        assert_eq!(cart.read_rom(0x4000), 0x00);
+    }
+
+    #[test]
+    fn sixteen_ram_banks() {
+        let mut cart = Mbc5::new(numbered_rom(2), 16 * RAM_BANK_SIZE, false);
+        assert_eq!(cart.read_ram(0), None);
+        cart.write_rom(0x0000, d8(Wrapping(0x0a)));
+        for bank in 0..16 {
+            cart.write_rom(0x4000, d8(Wrapping(bank)));
+            cart.write_ram(0x10, d8(Wrapping(bank + 0x80)));
+        }
+        cart.write_rom(0x4000, d8(Wrapping(0x0f)));
+        assert_eq!(cart.read_ram(0x10), Some(d8(Wrapping(0x8f))));
+        cart.write_rom(0x4000, d8(Wrapping(0x03)));
+        assert_eq!(cart.read_ram(0x10), Some(d8(Wrapping(0x83))));
+    }
+
+    #[test]
+    fn rumble_bit() {
+        let mut cart = Mbc5::new(numbered_rom(2), 8 * RAM_BANK_SIZE, true);
+        cart.write_rom(0x4000, d8(Wrapping(0x0b)));
+        cart.write_rom(0x4000, d8(Wrapping(0x0a)));
+        cart.write_rom(0x4000, d8(Wrapping(0x02)));
+        assert_eq!(cart.ram_bank, 2);
+        assert_eq!(
+            cart.take_events(),
+            vec![CartridgeEvent::Rumble(true), CartridgeEvent::Rumble(false)]
+        );
+        assert!(cart.take_events().is_empty());
+    }
+}
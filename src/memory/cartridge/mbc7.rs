@@ -0,0 +1,267 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use super::{Cartridge, ROM_BANK_SIZE};
+use super::eeprom::Eeprom93LC56;
+
+/*
+MBC7 registers mapped over ROM:
+
+$0000-$1FFF	RAM enable 1 - $0A enables
+$2000-$3FFF	ROM bank number
+$4000-$5FFF	RAM enable 2 - $40 enables
+
+Both enables must be set before anything in $A000-$AFFF responds. There's
+no RAM; instead bits 4-7 of the address pick one of these registers, which
+are mirrored throughout $A000-$AFFF:
+
+$Ax0x	write $55 to erase the latched accelerometer values
+$Ax1x	write $AA to latch the accelerometer (only after an erase)
+$Ax2x	latched X, low byte
+$Ax3x	latched X, high byte
+$Ax4x	latched Y, low byte
+$Ax5x	latched Y, high byte
+$Ax6x	always $00
+$Ax8x	93LC56 EEPROM pins, see eeprom.rs
+
+Everything else, including all of $B000-$BFFF, reads $FF.
+*/
+const ACCELEROMETER_CENTER: f32 = 0x81d0 as f32;
+const ACCELEROMETER_PER_G: f32 = 0x70 as f32;
+const ERASED_LATCH: u16 = 0x8000;
+
+pub struct Mbc7 {
+    rom: Vec<d8>,
+    ram_enable_1: bool,
+    ram_enable_2: bool,
+    rom_bank: usize,
+    // the current host-provided tilt, in g; +x is right and +y is towards
+    // the player
+    tilt: (f32, f32),
+    latch_erased: bool,
+    x_latch: u16,
+    y_latch: u16,
+    eeprom: Eeprom93LC56,
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<d8>) -> Self {
+        Mbc7 {
+            rom,
+            ram_enable_1: false,
+            ram_enable_2: false,
+            rom_bank: 1,
+            tilt: (0.0, 0.0),
+            latch_erased: false,
+            x_latch: ERASED_LATCH,
+            y_latch: ERASED_LATCH,
+            eeprom: Eeprom93LC56::new(),
+        }
+    }
+
+    pub fn eeprom(&self) -> &Eeprom93LC56 {
+        &self.eeprom
+    }
+
+    pub fn eeprom_mut(&mut self) -> &mut Eeprom93LC56 {
+        &mut self.eeprom
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        ::std::cmp::max(self.rom.len() / ROM_BANK_SIZE, 1)
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.ram_enable_1 && self.ram_enable_2
+    }
+
+    fn latch_accelerometer(&mut self) {
+        let (x, y) = self.tilt;
+        self.x_latch = (ACCELEROMETER_CENTER + ACCELEROMETER_PER_G * x) as u16;
+        self.y_latch = (ACCELEROMETER_CENTER + ACCELEROMETER_PER_G * y) as u16;
+    }
+}
+
+impl Cartridge for Mbc7 {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let bank = self.rom_bank_at(idx);
+        let offset = idx % ROM_BANK_SIZE;
+        self.rom.get(bank * ROM_BANK_SIZE + offset).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, idx: usize, d8(Wrapping(val)): d8) {
+        match idx {
+            0x0000 ... 0x1fff => self.ram_enable_1 = val == 0x0a,
+            0x2000 ... 0x3fff => self.rom_bank = (val & 0x7f) as usize,
+            0x4000 ... 0x5fff => self.ram_enable_2 = val == 0x40,
+            _ => (),
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        match idx {
+            0x0000 ... 0x3fff => 0,
+            _ => self.rom_bank % self.rom_bank_count(),
+        }
+    }
+
+    fn read_ram(&self, idx: usize) -> Option<d8> {
+        if !self.registers_enabled() || idx >= 0x1000 {
+            return Some(d8(Wrapping(0xff)));
+        }
+        let val = match (idx >> 4) & 0x0f {
+            0x2 => self.x_latch as u8,
+            0x3 => (self.x_latch >> 8) as u8,
+            0x4 => self.y_latch as u8,
+            0x5 => (self.y_latch >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read_pins(),
+            _ => 0xff,
+        };
+        Some(d8(Wrapping(val)))
+    }
+
+    // the accelerometer latch and the EEPROM pins, when enabled
+    fn ram_writable(&self) -> bool {
+        self.registers_enabled()
+    }
+
+    fn write_ram(&mut self, idx: usize, d8(Wrapping(val)): d8) -> Option<()> {
+        if !self.registers_enabled() || idx >= 0x1000 {
+            return None;
+        }
+        match (idx >> 4) & 0x0f {
+            0x0 => if val == 0x55 {
+                self.latch_erased = true;
+                self.x_latch = ERASED_LATCH;
+                self.y_latch = ERASED_LATCH;
+            },
+            0x1 => if val == 0xaa && self.latch_erased {
+                self.latch_erased = false;
+                self.latch_accelerometer();
+            },
+            0x8 => self.eeprom.write_pins(val),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    // the EEPROM's 128 words, each stored little-endian
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(2 * self.eeprom.words().len());
+        for &word in self.eeprom.words().iter() {
+            data.push(word as u8);
+            data.push((word >> 8) as u8);
+        }
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        for (word, bytes) in self.eeprom.words_mut().iter_mut().zip(data.chunks(2)) {
+            if bytes.len() == 2 {
+                *word = bytes[0] as u16 | ((bytes[1] as u16) << 8);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn enabled_cart() -> Mbc7 {
+        let mut cart = Mbc7::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE]);
+        cart.write_rom(0x0000, d8(Wrapping(0x0a)));
+        cart.write_rom(0x4000, d8(Wrapping(0x40)));
+        cart
+    }
+
+    fn read(cart: &Mbc7, idx: usize) -> u8 {
+        let d8(Wrapping(val)) = cart.read_ram(idx).unwrap();
+        val
+    }
+
+    fn clock_bit(cart: &mut Mbc7, bit: bool) -> bool {
+        let di = (bit as u8) << 1;
+        cart.write_ram(0x80, d8(Wrapping(0x80 | di)));
+        cart.write_ram(0x80, d8(Wrapping(0xc0 | di)));
+        (read(cart, 0x80) & 1) != 0
+    }
+
+    fn send(cart: &mut Mbc7, bits: u32, count: u32) {
+        for i in (0..count).rev() {
+            clock_bit(cart, (bits >> i) & 1 != 0);
+        }
+    }
+
+    fn deselect(cart: &mut Mbc7) {
+        cart.write_ram(0x80, d8::ZERO);
+    }
+
+    #[test]
+    fn registers_need_both_enables() {
+        let mut cart = Mbc7::new(vec![d8::ZERO; 2 * ROM_BANK_SIZE]);
+        cart.write_rom(0x0000, d8(Wrapping(0x0a)));
+        assert_eq!(read(&cart, 0x60), 0xff);
+        cart.write_rom(0x4000, d8(Wrapping(0x40)));
+        assert_eq!(read(&cart, 0x60), 0x00);
+    }
+
+    #[test]
+    fn accelerometer_latch() {
+        let mut cart = enabled_cart();
+        cart.set_tilt(1.0, -1.0);
+        // latching without erasing first does nothing
+        cart.write_ram(0x10, d8(Wrapping(0xaa)));
+        assert_eq!(read(&cart, 0x20), 0x00);
+        assert_eq!(read(&cart, 0x30), 0x80);
+
+        cart.write_ram(0x00, d8(Wrapping(0x55)));
+        cart.write_ram(0x10, d8(Wrapping(0xaa)));
+        assert_eq!(read(&cart, 0x20), 0x40);
+        assert_eq!(read(&cart, 0x30), 0x82);
+        assert_eq!(read(&cart, 0x40), 0x60);
+        assert_eq!(read(&cart, 0x50), 0x81);
+
+        // the latch holds until the next erase, whatever the tilt does
+        cart.set_tilt(0.0, 0.0);
+        cart.write_ram(0x10, d8(Wrapping(0xaa)));
+        assert_eq!(read(&cart, 0x20), 0x40);
+    }
+
+    #[test]
+    fn eeprom_write_then_read() {
+        let mut cart = enabled_cart();
+        // EWEN
+        send(&mut cart, 0b1_00_11000000, 11);
+        deselect(&mut cart);
+        // WRITE $BEEF to word 5
+        send(&mut cart, 0b1_01_00000101, 11);
+        send(&mut cart, 0xbeef, 16);
+        assert_eq!(read(&cart, 0x80) & 1, 1);
+        deselect(&mut cart);
+        assert_eq!(cart.eeprom().words()[5], 0xbeef);
+
+        // READ word 5
+        send(&mut cart, 0b1_10_00000101, 11);
+        assert_eq!(read(&cart, 0x80) & 1, 0);
+        let mut word = 0u16;
+        for _ in 0..16 {
+            word = (word << 1) | clock_bit(&mut cart, false) as u16;
+        }
+        deselect(&mut cart);
+        assert_eq!(word, 0xbeef);
+    }
+
+    #[test]
+    fn eeprom_write_protected_by_default() {
+        let mut cart = enabled_cart();
+        send(&mut cart, 0b1_01_00000001, 11);
+        send(&mut cart, 0x1234, 16);
+        deselect(&mut cart);
+        assert_eq!(cart.eeprom().words()[1], 0xffff);
+    }
+}
@@ -0,0 +1,250 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use super::{Cartridge, ram_to_bytes, load_ram_bytes, boots, ROM_BANK_SIZE, RAM_BANK_SIZE};
+use super::CARTRIDGE_TYPE_ADDR;
+
+/*
+The MMM01 is a multicart controller: it boots into a menu stored in the
+last 32KiB of the ROM, and the menu then picks a game and locks the
+mapping so the game sees an ordinary MBC1-style cart covering its own
+slice of the ROM.
+
+$0000-$1FFF	bits 0-3 - RAM enable ($A), bit 6 - lock the mapping
+$2000-$3FFF	bits 0-4 - ROM bank low, bits 5-6 - ROM bank mid*
+$4000-$5FFF	bits 0-1 - RAM bank low, bits 2-3 - RAM bank high*,
+		bits 4-5 - ROM bank high*
+$6000-$7FFF	bit 0 - MBC1 banking mode, bits 2-5 - ROM bank mask*
+
+* only writable before the mapping is locked. Once it is, the bits of the
+ROM bank low register covered by the mask (mask bit n covers bank bit n+1)
+stay as the menu left them, which is how the menu carves out games
+smaller than 512KiB.
+
+As on MBC1, the RAM bank low bits only count in banking mode 1; in mode 0
+$A000-$BFFF shows RAM bank 0 of whatever the RAM bank high bits select.
+*/
+const LOCK_BIT: u8 = 0x40;
+
+pub struct Mmm01 {
+    rom: Vec<d8>,
+    ram: Vec<d8>,
+    locked: bool,
+    ram_enabled: bool,
+    rom_low: u8,
+    rom_mid: u8,
+    rom_high: u8,
+    ram_low: u8,
+    ram_high: u8,
+    rom_mask: u8,
+    banking_mode: u8,
+}
+
+impl Mmm01 {
+    pub fn new(rom: Vec<d8>, ram_size: usize) -> Self {
+        Mmm01 {
+            rom,
+            ram: vec![d8::ZERO; ram_size],
+            locked: false,
+            ram_enabled: false,
+            rom_low: 0,
+            rom_mid: 0,
+            rom_high: 0,
+            ram_low: 0,
+            ram_high: 0,
+            rom_mask: 0,
+            banking_mode: 0,
+        }
+    }
+
+    // the menu's header is at the start of the last 32KiB, and that's the
+    // one that admits to being an MMM01. The header at $0100 is usually
+    // the first game's. The menu has to boot, so its header has the logo
+    // and a good checksum, which keeps ordinary ROMs with the right byte
+    // there by chance from being taken for one.
+    pub fn looks_like(rom: &[d8]) -> bool {
+        if rom.len() < 2 * 2 * ROM_BANK_SIZE {
+            return false;
+        }
+        let menu = &rom[rom.len() - 2 * ROM_BANK_SIZE..];
+        let d8(Wrapping(cartridge_type)) = menu[CARTRIDGE_TYPE_ADDR];
+        matches!(cartridge_type, 0x0b ... 0x0d) && boots(menu)
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        ::std::cmp::max(self.rom.len() / ROM_BANK_SIZE, 2)
+    }
+
+    fn writable_low_bits(&self) -> u8 {
+        if self.locked {
+            0x1f & !(self.rom_mask << 1)
+        } else {
+            0x1f
+        }
+    }
+
+    fn rom_bank(&self, idx: usize) -> usize {
+        let count = self.rom_bank_count();
+        if !self.locked {
+            return match idx {
+                0x0000 ... 0x3fff => count - 2,
+                _ => count - 1,
+            };
+        }
+        let writable = self.writable_low_bits();
+        let full = ((self.rom_high as usize) << 7)
+            | ((self.rom_mid as usize) << 5)
+            | self.rom_low as usize;
+        let base = full & !(writable as usize);
+        match idx {
+            0x0000 ... 0x3fff => base,
+            _ => {
+                let low = self.rom_low & writable;
+                base | if low == 0 { 1 } else { low as usize }
+            }
+        }
+    }
+
+    fn ram_index(&self, idx: usize) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        Some((self.ram_bank() * RAM_BANK_SIZE + idx) % self.ram.len())
+    }
+}
+
+impl Cartridge for Mmm01 {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let bank = self.rom_bank_at(idx);
+        let offset = idx % ROM_BANK_SIZE;
+        self.rom.get(bank * ROM_BANK_SIZE + offset).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, idx: usize, d8(Wrapping(val)): d8) {
+        match idx {
+            0x0000 ... 0x1fff => {
+                self.ram_enabled = (val & 0x0f) == 0x0a;
+                if (val & LOCK_BIT) != 0 {
+                    self.locked = true;
+                }
+            }
+            0x2000 ... 0x3fff => {
+                let writable = self.writable_low_bits();
+                self.rom_low = (self.rom_low & !writable) | (val & writable);
+                if !self.locked {
+                    self.rom_mid = (val >> 5) & 0b11;
+                }
+            }
+            0x4000 ... 0x5fff => {
+                self.ram_low = val & 0b11;
+                if !self.locked {
+                    self.ram_high = (val >> 2) & 0b11;
+                    self.rom_high = (val >> 4) & 0b11;
+                }
+            }
+            0x6000 ... 0x7fff => {
+                self.banking_mode = val & 1;
+                if !self.locked {
+                    self.rom_mask = (val >> 2) & 0x0f;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        self.rom_bank(idx) % self.rom_bank_count()
+    }
+
+    fn ram_bank(&self) -> usize {
+        let low = if self.banking_mode == 1 { self.ram_low } else { 0 };
+        ((self.ram_high << 2) | low) as usize
+    }
+
+    fn read_ram(&self, idx: usize) -> Option<d8> {
+        self.ram_index(idx).map(|i| self.ram[i])
+    }
+
+    fn write_ram(&mut self, idx: usize, val: d8) -> Option<()> {
+        let i = self.ram_index(idx)?;
+        self.ram[i] = val;
+        Some(())
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        ram_to_bytes(&self.ram)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram_bytes(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use memory::cartridge::{NINTENDO_LOGO, LOGO_ADDR, header_checksum};
+
+    fn numbered_rom(banks: usize) -> Vec<d8> {
+        let mut rom = vec![d8::ZERO; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = d8(Wrapping(bank as u8));
+        }
+        rom
+    }
+
+    #[test]
+    fn boots_into_last_32k() {
+        let cart = Mmm01::new(numbered_rom(16), 0);
+        assert_eq!(cart.read_rom(0x0000), 14);
+        assert_eq!(cart.read_rom(0x4000), 15);
+    }
+
+    #[test]
+    fn menu_locks_a_game() {
+        let mut cart = Mmm01::new(numbered_rom(16), 0);
+        // the game lives in banks 8-15: a base of 8, and the mask keeps
+        // bits 3 and 4 of the bank number fixed
+        cart.write_rom(0x2000, d8(Wrapping(0x08)));
+        cart.write_rom(0x6000, d8(Wrapping(0b1100 << 2)));
+        cart.write_rom(0x0000, d8(Wrapping(LOCK_BIT)));
+        assert_eq!(cart.read_rom(0x0000), 8);
+        assert_eq!(cart.read_rom(0x4000), 9);
+
+        // the game can only move within its own banks
+        cart.write_rom(0x2000, d8(Wrapping(0x03)));
+        assert_eq!(cart.read_rom(0x4000), 11);
+        cart.write_rom(0x2000, d8(Wrapping(0x12)));
+        assert_eq!(cart.read_rom(0x4000), 10);
+    }
+
+    #[test]
+    fn ram_banks_need_mode_1() {
+        let mut cart = Mmm01::new(numbered_rom(16), 4 * RAM_BANK_SIZE);
+        cart.write_rom(0x0000, d8(Wrapping(0x0a)));
+        cart.write_rom(0x4000, d8(Wrapping(0x02)));
+        assert_eq!(cart.ram_bank(), 0);
+        cart.write_ram(0, d8(Wrapping(0x11)));
+
+        cart.write_rom(0x6000, d8(Wrapping(0x01)));
+        assert_eq!(cart.ram_bank(), 2);
+        assert_eq!(cart.read_ram(0), Some(d8::ZERO));
+        cart.write_rom(0x6000, d8(Wrapping(0x00)));
+        assert_eq!(cart.read_ram(0), Some(d8(Wrapping(0x11))));
+    }
+
+    #[test]
+    fn detected_by_menu_header() {
+        let mut rom = numbered_rom(8);
+        assert!(!Mmm01::looks_like(&rom));
+        let menu = rom.len() - 2 * ROM_BANK_SIZE;
+        rom[menu + CARTRIDGE_TYPE_ADDR] = d8(Wrapping(0x0b));
+        // not without a header the boot ROM would take
+        assert!(!Mmm01::looks_like(&rom));
+        for (i, &b) in NINTENDO_LOGO.iter().enumerate() {
+            rom[menu + LOGO_ADDR + i] = d8(Wrapping(b));
+        }
+        assert!(!Mmm01::looks_like(&rom));
+        rom[menu + 0x014d] = d8(Wrapping(header_checksum(&rom[menu..])));
+        assert!(Mmm01::looks_like(&rom));
+    }
+}
@@ -0,0 +1,233 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use memory::infrared::InfraredEndpoint;
+
+mod rom_only;
+pub use self::rom_only::RomOnly;
+
+mod mbc2;
+pub use self::mbc2::Mbc2;
+
+mod mbc3;
+pub use self::mbc3::Mbc3;
+
+mod mbc5;
+pub use self::mbc5::Mbc5;
+
+mod eeprom;
+pub use self::eeprom::Eeprom93LC56;
+
+mod mbc7;
+pub use self::mbc7::Mbc7;
+
+mod huc1;
+pub use self::huc1::Huc1;
+
+mod huc3;
+pub use self::huc3::{Huc3, Huc3Clock};
+
+mod camera;
+pub use self::camera::{Camera, CameraImage, CAMERA_WIDTH, CAMERA_HEIGHT};
+
+mod mmm01;
+pub use self::mmm01::Mmm01;
+
+mod wisdom_tree;
+pub use self::wisdom_tree::WisdomTree;
+
+mod sachen;
+pub use self::sachen::{Sachen, SachenKind};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+/*
+The cartridge header lives at $0100-$014F. The bytes we care about for
+mapping are:
+
+$0104	Nintendo's logo, which the boot ROM checks
+$0147	Cartridge type (which MBC, and whether there's RAM/battery/rumble)
+$0149	RAM size - see `ram_size_from_header`
+$014D	Header checksum, over $0134-$014C, which the boot ROM also checks
+
+The ROM size byte at $0148 isn't used: mappers go by how much ROM there
+actually is, and plenty of unlicensed carts have junk there.
+*/
+pub const LOGO_ADDR: usize = 0x0104;
+pub const CARTRIDGE_TYPE_ADDR: usize = 0x0147;
+const RAM_SIZE_ADDR: usize = 0x0149;
+const HEADER_CHECKSUM_ADDR: usize = 0x014d;
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0c, 0x00, 0x0d, 0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e,
+    0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99, 0xbb, 0xbb, 0x67, 0x63,
+    0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CartridgeEvent {
+    // the MBC5 rumble motor was switched on (true) or off (false)
+    Rumble(bool),
+    // the cart changed its own battery-backed RAM, like the Pocket Camera
+    // does when it takes a photo; `Memory` marks the save dirty
+    SaveChanged,
+}
+
+pub trait Cartridge {
+    // `idx` is the raw address, $0000-$7FFF
+    fn read_rom(&self, idx: usize) -> d8;
+    // what `read_rom` would return, without the side effects some mappers
+    // have on reads; for debuggers, and for looking at the header
+    fn peek_rom(&self, idx: usize) -> d8 {
+        self.read_rom(idx)
+    }
+    // writes into ROM space don't write anything; they talk to the MBC
+    fn write_rom(&mut self, idx: usize, val: d8);
+    // `idx` is relative to $A000
+    fn read_ram(&self, idx: usize) -> Option<d8>;
+    fn write_ram(&mut self, idx: usize, val: d8) -> Option<()>;
+    // whether writes to $A000-$BFFF would land right now, for debuggers;
+    // carts where RAM can be read but not written override this
+    fn ram_writable(&self) -> bool {
+        self.read_ram(0).is_some()
+    }
+
+    // the ROM bank mapped at `idx` and the RAM bank mapped at $A000-$BFFF,
+    // for debuggers; neither has any effect on the cart
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        idx / ROM_BANK_SIZE
+    }
+
+    fn ram_bank(&self) -> usize {
+        0
+    }
+
+    // events which have happened since the last call, for `Memory` to
+    // hand out to whoever has subscribed
+    fn take_events(&mut self) -> Vec<CartridgeEvent> {
+        Vec::new()
+    }
+
+    // host input for carts with an accelerometer, in g. Everything
+    // else ignores it.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    // battery-backed state, laid out the way it goes on disk: the RAM
+    // image, followed by any clock state
+    fn save_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    // only carts with an IR LED/sensor do anything with this
+    fn connect_infrared(&mut self, _endpoint: Box<dyn InfraredEndpoint>) {}
+
+    // what the Pocket Camera's sensor sees
+    fn set_camera_image(&mut self, _image: CameraImage) {}
+
+    // called with the number of T-cycles that have passed, for carts
+    // that do things in the background
+    fn tick(&mut self, _cycles: u64) {}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub cartridge_type: u8,
+    pub ram_size: usize,
+}
+
+impl Header {
+    // whether the cart keeps its RAM (or EEPROM, or clock) alive with a
+    // battery, so it needs saving between sessions. Only types `from_rom`
+    // has a mapper for are listed.
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x06 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xfc | 0xfe | 0xff
+        )
+    }
+
+    pub fn parse(rom: &[d8]) -> Option<Self> {
+        if rom.len() <= RAM_SIZE_ADDR {
+            return None;
+        }
+        let d8(Wrapping(cartridge_type)) = rom[CARTRIDGE_TYPE_ADDR];
+        let d8(Wrapping(ram_size)) = rom[RAM_SIZE_ADDR];
+        Some(Header {
+            cartridge_type,
+            ram_size: ram_size_from_header(ram_size),
+        })
+    }
+}
+
+pub fn header_checksum(rom: &[d8]) -> u8 {
+    rom[0x0134..HEADER_CHECKSUM_ADDR]
+        .iter()
+        .fold(0u8, |sum, &d8(Wrapping(b))| sum.wrapping_sub(b).wrapping_sub(1))
+}
+
+// whether the boot ROM would accept the header in `rom`, which starts at
+// what would be $0000: the logo has to be there and the checksum right
+pub fn boots(rom: &[d8]) -> bool {
+    if rom.len() <= HEADER_CHECKSUM_ADDR {
+        return false;
+    }
+    let logo = &rom[LOGO_ADDR..LOGO_ADDR + NINTENDO_LOGO.len()];
+    let d8(Wrapping(checksum)) = rom[HEADER_CHECKSUM_ADDR];
+    logo.iter().zip(NINTENDO_LOGO.iter()).all(|(&d8(Wrapping(a)), &b)| a == b)
+        && header_checksum(rom) == checksum
+}
+
+fn ram_size_from_header(byte: u8) -> usize {
+    match byte {
+        0x01 => 0x800,
+        0x02 => RAM_BANK_SIZE,
+        0x03 => 4 * RAM_BANK_SIZE,
+        0x04 => 16 * RAM_BANK_SIZE,
+        0x05 => 8 * RAM_BANK_SIZE,
+        _ => 0,
+    }
+}
+
+// cartridge RAM as it goes in a `.sav` file: just the bytes, all banks in order
+pub fn ram_to_bytes(ram: &[d8]) -> Vec<u8> {
+    ram.iter().map(|&d8(Wrapping(b))| b).collect()
+}
+
+pub fn load_ram_bytes(ram: &mut [d8], data: &[u8]) {
+    for (dst, &src) in ram.iter_mut().zip(data) {
+        *dst = d8(Wrapping(src));
+    }
+}
+
+pub fn from_rom(rom: Vec<d8>) -> Option<Box<dyn Cartridge>> {
+    let header = Header::parse(&rom)?;
+
+    // unlicensed and multicart mappers can't be trusted to have a
+    // meaningful cartridge type, so look for them first
+    if Mmm01::looks_like(&rom) {
+        return Some(Box::new(Mmm01::new(rom, header.ram_size)));
+    }
+    if WisdomTree::looks_like(&rom) {
+        return Some(Box::new(WisdomTree::new(rom)));
+    }
+    if let Some(kind) = Sachen::detect(&rom) {
+        return Some(Box::new(Sachen::new(rom, kind)));
+    }
+
+    match header.cartridge_type {
+        0x00 => Some(Box::new(RomOnly::new(rom))),
+        0x05 | 0x06 => Some(Box::new(Mbc2::new(rom))),
+        0x0b | 0x0c | 0x0d => Some(Box::new(Mmm01::new(rom, header.ram_size))),
+        0x0f | 0x10 => Some(Box::new(Mbc3::new(rom, header.ram_size, true))),
+        0x11 | 0x12 | 0x13 => Some(Box::new(Mbc3::new(rom, header.ram_size, false))),
+        0x19 | 0x1a | 0x1b => Some(Box::new(Mbc5::new(rom, header.ram_size, false))),
+        0x1c | 0x1d | 0x1e => Some(Box::new(Mbc5::new(rom, header.ram_size, true))),
+        0x22 => Some(Box::new(Mbc7::new(rom))),
+        0xfc => Some(Box::new(Camera::new(rom))),
+        0xfe => Some(Box::new(Huc3::new(rom, header.ram_size))),
+        0xff => Some(Box::new(Huc1::new(rom, header.ram_size))),
+        _ => None,
+    }
+}
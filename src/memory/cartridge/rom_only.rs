@@ -0,0 +1,30 @@
+use number_types::d8_type::d8;
+use super::Cartridge;
+
+pub struct RomOnly {
+    rom: Vec<d8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<d8>) -> Self {
+        RomOnly { rom }
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn read_rom(&self, idx: usize) -> d8 {
+        self.rom.get(idx).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, _idx: usize, _val: d8) {
+        // there's no MBC to talk to, so these go nowhere
+    }
+
+    fn read_ram(&self, _idx: usize) -> Option<d8> {
+        None
+    }
+
+    fn write_ram(&mut self, _idx: usize, _val: d8) -> Option<()> {
+        None
+    }
+}
@@ -0,0 +1,238 @@
+use number_types::d8_type::d8;
+use std::cell::Cell;
+use std::num::Wrapping;
+use super::{Cartridge, ROM_BANK_SIZE, NINTENDO_LOGO, LOGO_ADDR};
+
+/*
+Sachen's mappers get their carts past the boot ROM's logo check without
+storing Nintendo's logo where a casual look would find it. While the
+mapper is "locked", reads from $0100-$01FF have address bit 7 forced on,
+so the boot ROM reads the real logo from $0184 while the game's own logo
+sits at $0104. Every read from $01xx also has its address scrambled (bits
+0 and 6 swapped, and bits 1 and 4). The lock comes off after $31 reads
+from $01xx, which is just past the end of the boot ROM's logo check.
+
+The MMC2 is the same idea, with a second lock stage for the CGB boot ROM,
+which checks the logo a second time. (On hardware the first stage ends
+early if the CPU reads from $C000 and up; rgb only counts header reads.)
+
+Banking:
+
+$0000-$1FFF	base ROM bank, only writable while the bank register has
+		bits 4 and 5 set. $0000-$3FFF shows base & mask.
+$2000-$3FFF	ROM bank number; 0 becomes 1
+$4000-$5FFF	bank mask, with the same restriction as the base bank
+
+$4000-$7FFF shows (bank & !mask) | (base & mask).
+*/
+const UNLOCK_READS: u8 = 0x31;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SachenKind {
+    Mmc1,
+    Mmc2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Lock {
+    // the first of the MMC2's two stages, for the DMG boot ROM's check
+    Dmg,
+    // the only stage on the MMC1, and the MMC2's second, for the CGB boot
+    // ROM's second check
+    Final,
+    Unlocked,
+}
+
+fn unscramble(idx: usize) -> usize {
+    (idx & 0xffac)
+        | ((idx & 0x40) >> 6)
+        | ((idx & 0x10) >> 3)
+        | ((idx & 0x02) << 3)
+        | ((idx & 0x01) << 6)
+}
+
+pub struct Sachen {
+    rom: Vec<d8>,
+    kind: SachenKind,
+    // reads change the lock state, and `read_rom` only gets `&self`
+    lock: Cell<Lock>,
+    header_reads: Cell<u8>,
+    base_bank: u8,
+    bank: u8,
+    mask: u8,
+}
+
+impl Sachen {
+    pub fn new(rom: Vec<d8>, kind: SachenKind) -> Self {
+        Sachen {
+            rom,
+            kind,
+            lock: Cell::new(match kind {
+                SachenKind::Mmc1 => Lock::Final,
+                SachenKind::Mmc2 => Lock::Dmg,
+            }),
+            header_reads: Cell::new(0),
+            base_bank: 0,
+            bank: 1,
+            mask: 0,
+        }
+    }
+
+    // Nintendo's logo isn't at $0104, but is where the boot ROM will find
+    // it while the mapper is locked. MMC2 carts work on the CGB, so the
+    // (unscrambled) CGB flag tells them apart.
+    pub fn detect(rom: &[d8]) -> Option<SachenKind> {
+        if rom.len() < 2 * ROM_BANK_SIZE {
+            return None;
+        }
+        let byte = |idx: usize| {
+            let d8(Wrapping(b)) = rom[idx];
+            b
+        };
+        let logo_at = |addr: &dyn Fn(usize) -> usize| {
+            NINTENDO_LOGO.iter().enumerate().all(|(i, &b)| byte(addr(LOGO_ADDR + i)) == b)
+        };
+        if logo_at(&|idx| idx) || !logo_at(&|idx| unscramble(idx | 0x80)) {
+            return None;
+        }
+        if (byte(unscramble(0x0143)) & 0x80) != 0 {
+            Some(SachenKind::Mmc2)
+        } else {
+            Some(SachenKind::Mmc1)
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        ::std::cmp::max(self.rom.len() / ROM_BANK_SIZE, 1)
+    }
+
+    fn registers_writable(&self) -> bool {
+        (self.bank & 0x30) == 0x30
+    }
+
+    // where a read from $01xx goes; the read that unlocks already sees
+    // the real address
+    fn header_address(&self, idx: usize) -> usize {
+        let locked = self.lock.get() != Lock::Unlocked
+            && self.header_reads.get() + 1 < UNLOCK_READS;
+        unscramble(if locked { idx | 0x80 } else { idx })
+    }
+
+    fn count_header_read(&self) {
+        let lock = self.lock.get();
+        if lock == Lock::Unlocked {
+            return;
+        }
+        let reads = self.header_reads.get() + 1;
+        if reads == UNLOCK_READS {
+            self.header_reads.set(0);
+            self.lock.set(match (self.kind, lock) {
+                (SachenKind::Mmc2, Lock::Dmg) => Lock::Final,
+                _ => Lock::Unlocked,
+            });
+        } else {
+            self.header_reads.set(reads);
+        }
+    }
+
+    fn rom_byte(&self, idx: usize) -> d8 {
+        let bank = self.rom_bank_at(idx);
+        let offset = idx % ROM_BANK_SIZE;
+        self.rom.get(bank * ROM_BANK_SIZE + offset).cloned().unwrap_or(d8::ZERO)
+    }
+}
+
+impl Cartridge for Sachen {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let byte = self.peek_rom(idx);
+        if let 0x0100 ... 0x01ff = idx {
+            self.count_header_read();
+        }
+        byte
+    }
+
+    fn peek_rom(&self, idx: usize) -> d8 {
+        match idx {
+            0x0100 ... 0x01ff => self.rom_byte(self.header_address(idx)),
+            _ => self.rom_byte(idx),
+        }
+    }
+
+    fn write_rom(&mut self, idx: usize, d8(Wrapping(val)): d8) {
+        match idx {
+            0x0000 ... 0x1fff if self.registers_writable() => self.base_bank = val,
+            0x2000 ... 0x3fff => self.bank = if val == 0 { 1 } else { val },
+            0x4000 ... 0x5fff if self.registers_writable() => self.mask = val,
+            _ => (),
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        let bank = match idx {
+            0x0000 ... 0x3fff => self.base_bank & self.mask,
+            _ => (self.bank & !self.mask) | (self.base_bank & self.mask),
+        };
+        bank as usize % self.rom_bank_count()
+    }
+
+    fn read_ram(&self, _idx: usize) -> Option<d8> {
+        None
+    }
+
+    fn write_ram(&mut self, _idx: usize, _val: d8) -> Option<()> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sachen_rom(cgb: bool) -> Vec<d8> {
+        let mut rom = vec![d8::ZERO; 2 * ROM_BANK_SIZE];
+        for (i, &b) in NINTENDO_LOGO.iter().enumerate() {
+            rom[unscramble((LOGO_ADDR + i) | 0x80)] = d8(Wrapping(b));
+        }
+        if cgb {
+            rom[unscramble(0x0143)] = d8(Wrapping(0x80));
+        }
+        rom
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!(Sachen::detect(&sachen_rom(false)), Some(SachenKind::Mmc1));
+        assert_eq!(Sachen::detect(&sachen_rom(true)), Some(SachenKind::Mmc2));
+        assert_eq!(Sachen::detect(&vec![d8::ZERO; 2 * ROM_BANK_SIZE]), None);
+    }
+
+    #[test]
+    fn logo_visible_until_unlocked() {
+        let cart = Sachen::new(sachen_rom(false), SachenKind::Mmc1);
+        for i in 0..(UNLOCK_READS as usize - 1) {
+            let b = cart.read_rom(LOGO_ADDR + i % NINTENDO_LOGO.len());
+            assert_eq!(b, NINTENDO_LOGO[i % NINTENDO_LOGO.len()]);
+        }
+        assert_eq!(cart.read_rom(LOGO_ADDR), 0x00);
+        assert_eq!(cart.lock.get(), Lock::Unlocked);
+    }
+
+    #[test]
+    fn peeks_leave_the_lock_alone() {
+        let cart = Sachen::new(sachen_rom(false), SachenKind::Mmc1);
+        for _ in 0..UNLOCK_READS {
+            assert_eq!(cart.peek_rom(LOGO_ADDR), NINTENDO_LOGO[0]);
+        }
+        assert_eq!(cart.header_reads.get(), 0);
+    }
+
+    #[test]
+    fn mmc2_locks_twice() {
+        let cart = Sachen::new(sachen_rom(true), SachenKind::Mmc2);
+        for _ in 0..UNLOCK_READS {
+            cart.read_rom(LOGO_ADDR);
+        }
+        assert_eq!(cart.lock.get(), Lock::Final);
+        assert_eq!(cart.read_rom(LOGO_ADDR), NINTENDO_LOGO[0]);
+    }
+}
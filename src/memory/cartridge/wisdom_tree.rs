@@ -0,0 +1,113 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use super::{Cartridge, ROM_BANK_SIZE};
+
+/*
+Wisdom Tree's mapper swaps the whole of $0000-$7FFF in 32KiB chunks. Any
+write to $0000-$3FFF selects the chunk, and it's the low byte of the
+*address* that picks it; the value written is ignored. There's no RAM.
+
+The header claims to be ROM-only, so these are spotted by the publisher's
+name, which every one of their games has in the first bank.
+*/
+const CHUNK_SIZE: usize = 2 * ROM_BANK_SIZE;
+
+pub struct WisdomTree {
+    rom: Vec<d8>,
+    chunk: usize,
+}
+
+impl WisdomTree {
+    pub fn new(rom: Vec<d8>) -> Self {
+        WisdomTree { rom, chunk: 0 }
+    }
+
+    pub fn looks_like(rom: &[d8]) -> bool {
+        if rom.len() <= CHUNK_SIZE {
+            return false;
+        }
+        let d8(Wrapping(cartridge_type)) = rom[0x0147];
+        if cartridge_type != 0x00 && cartridge_type != 0xc0 {
+            return false;
+        }
+        let first_bank: Vec<u8> = rom[..ROM_BANK_SIZE].iter().map(|&d8(Wrapping(b))| b).collect();
+        contains(&first_bank, b"WISDOM TREE") || contains(&first_bank, b"WISDOM\x00TREE")
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+impl Cartridge for WisdomTree {
+    fn read_rom(&self, idx: usize) -> d8 {
+        let chunk_count = ::std::cmp::max(self.rom.len() / CHUNK_SIZE, 1);
+        let chunk = self.chunk % chunk_count;
+        self.rom.get(chunk * CHUNK_SIZE + idx).cloned().unwrap_or(d8::ZERO)
+    }
+
+    fn write_rom(&mut self, idx: usize, _val: d8) {
+        if idx < 0x4000 {
+            self.chunk = idx & 0xff;
+        }
+    }
+
+    fn rom_bank_at(&self, idx: usize) -> usize {
+        let chunk_count = ::std::cmp::max(self.rom.len() / CHUNK_SIZE, 1);
+        2 * (self.chunk % chunk_count) + idx / ROM_BANK_SIZE
+    }
+
+    fn read_ram(&self, _idx: usize) -> Option<d8> {
+        None
+    }
+
+    fn write_ram(&mut self, _idx: usize, _val: d8) -> Option<()> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // each 32KiB chunk starts with its own number
+    fn numbered_rom(chunks: usize) -> Vec<d8> {
+        let mut rom = vec![d8::ZERO; chunks * CHUNK_SIZE];
+        for chunk in 0..chunks {
+            rom[chunk * CHUNK_SIZE] = d8(Wrapping(chunk as u8));
+        }
+        rom
+    }
+
+    #[test]
+    fn address_picks_the_chunk() {
+        let mut cart = WisdomTree::new(numbered_rom(4));
+        assert_eq!(cart.read_rom(0x0000), d8(Wrapping(0)));
+        cart.write_rom(0x0102, d8(Wrapping(0x01)));
+        assert_eq!(cart.read_rom(0x0000), d8(Wrapping(2)));
+        assert_eq!(cart.rom_bank_at(0x4000), 5);
+        // writes from $4000 up do nothing
+        cart.write_rom(0x4001, d8(Wrapping(0x02)));
+        assert_eq!(cart.read_rom(0x0000), d8(Wrapping(2)));
+        // past the end of the ROM wraps around
+        cart.write_rom(0x0005, d8::ZERO);
+        assert_eq!(cart.read_rom(0x0000), d8(Wrapping(1)));
+    }
+
+    #[test]
+    fn detected_by_publisher_name() {
+        let mut rom = numbered_rom(2);
+        assert!(!WisdomTree::looks_like(&rom));
+        for (i, &b) in b"WISDOM TREE".iter().enumerate() {
+            rom[0x0200 + i] = d8(Wrapping(b));
+        }
+        assert!(WisdomTree::looks_like(&rom));
+        // a cart that admits to having a mapper isn't one
+        rom[0x0147] = d8(Wrapping(0x01));
+        assert!(!WisdomTree::looks_like(&rom));
+        // nor is a 32KiB ROM-only cart
+        let mut small = rom[..CHUNK_SIZE].to_vec();
+        small[0x0147] = d8::ZERO;
+        assert!(!WisdomTree::looks_like(&small));
+    }
+}
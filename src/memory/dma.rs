@@ -0,0 +1,119 @@
+use number_types::d8_type::d8;
+
+/*
+OAM DMA, started by writing the high byte of the source address to $FF46.
+
+After a one M-cycle startup delay, one byte is copied to $FE00-$FE9F every
+M-cycle, 160 bytes in 640 T-cycles. While it's copying, the CPU can only use
+$FF00-$FFFF (I/O, HRAM and IE); anything else reads the byte the DMA just
+moved and ignores writes. Starting another transfer while one is running
+lets the running one carry on copying through the new one's startup delay,
+so the bus is never released in between.
+
+Sources of $E000 and above read the echo of work RAM, so $FE00 and $FF00
+copy from $DE00 and $DF00.
+*/
+pub const OAM_DMA_LENGTH: usize = 0xa0;
+const CYCLES_PER_BYTE: u64 = 4;
+const STARTUP_CYCLES: u64 = 4;
+
+struct Pending {
+    source: u16,
+    delay: u64,
+}
+
+pub struct OamDma {
+    source: u16,
+    next_byte: usize,
+    running: bool,
+    byte_cycles: u64,
+    pending: Option<Pending>,
+    last_byte: d8,
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        OamDma {
+            source: 0,
+            next_byte: 0,
+            running: false,
+            byte_cycles: 0,
+            pending: None,
+            last_byte: d8::ZERO,
+        }
+    }
+
+    pub fn start(&mut self, high_byte: u8) {
+        let mut source = (high_byte as u16) << 8;
+        if source >= 0xe000 {
+            source -= 0x2000;
+        }
+        self.pending = Some(Pending { source, delay: STARTUP_CYCLES });
+    }
+
+    // whether the CPU is locked out of everything but $FF00-$FFFF
+    pub fn is_blocking(&self) -> bool {
+        self.running
+    }
+
+    // what a blocked CPU read sees
+    pub fn last_byte(&self) -> d8 {
+        self.last_byte
+    }
+
+    pub fn set_last_byte(&mut self, byte: d8) {
+        self.last_byte = byte;
+    }
+
+    // spends up to `budget` cycles, returning the next (source address,
+    // OAM index) pair to copy as soon as one is due. Call it until it
+    // returns `None` to use up the whole budget.
+    pub fn next_transfer(&mut self, budget: &mut u64) -> Option<(u16, usize)> {
+        loop {
+            let needed = CYCLES_PER_BYTE - self.byte_cycles;
+            let takeover = self.pending.as_ref().map(|pending| pending.delay);
+            // a running copy goes on until a restart takes over
+            let byte_due = self.running && match takeover {
+                Some(delay) => needed <= delay,
+                None => true,
+            };
+            if byte_due && needed <= *budget {
+                *budget -= needed;
+                if let Some(ref mut pending) = self.pending {
+                    pending.delay -= needed;
+                }
+                return Some(self.copy_byte());
+            }
+            match takeover {
+                Some(delay) if delay <= *budget => {
+                    *budget -= delay;
+                    let pending = self.pending.take().unwrap();
+                    self.source = pending.source;
+                    self.next_byte = 0;
+                    self.byte_cycles = 0;
+                    self.running = true;
+                }
+                _ => {
+                    if self.running {
+                        self.byte_cycles += *budget;
+                    }
+                    if let Some(ref mut pending) = self.pending {
+                        pending.delay -= *budget;
+                    }
+                    *budget = 0;
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn copy_byte(&mut self) -> (u16, usize) {
+        self.byte_cycles = 0;
+        let idx = self.next_byte;
+        self.next_byte += 1;
+        if self.next_byte == OAM_DMA_LENGTH {
+            self.running = false;
+        }
+        (self.source + idx as u16, idx)
+    }
+}
@@ -0,0 +1,118 @@
+/*
+CGB VRAM DMA, $FF51-$FF55:
+
+$FF51	source, high byte
+$FF52	source, low byte - bits 0-3 ignored
+$FF53	destination, high byte - bits 5-7 ignored, it's always in VRAM
+$FF54	destination, low byte - bits 0-3 ignored
+$FF55	write: bit 7 - 0 for general DMA, 1 for HBlank DMA
+		bits 0-6 - length in 16-byte blocks, minus 1
+	read: bit 7 - 0 while an HBlank DMA is running, otherwise 1
+		bits 0-6 - blocks left, minus 1 ($FF once finished)
+
+General DMA copies everything at once, halting the CPU for 32 T-cycles a
+block (64 in double speed, where the CPU's cycles are half as long). HBlank
+DMA copies one block at the start of each HBlank, halting the CPU for the
+same time per block. Writing $FF55 with bit 7 clear during an HBlank DMA
+cancels it, leaving the count of blocks it didn't get to.
+
+The source and destination registers are counters, which the transfer
+moves along, so a second transfer carries on from where the first
+stopped unless they're written again.
+*/
+pub const HDMA_BLOCK_LENGTH: u16 = 0x10;
+const CYCLES_PER_BLOCK: u64 = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HdmaMode {
+    General,
+    HBlank,
+}
+
+pub struct Hdma {
+    source: u16,
+    dest: u16,
+    remaining_blocks: usize,
+    hblank_active: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            source: 0,
+            dest: 0x8000,
+            remaining_blocks: 0,
+            hblank_active: false,
+        }
+    }
+
+    pub fn set_source_high(&mut self, val: u8) {
+        self.source = (self.source & 0x00ff) | ((val as u16) << 8);
+    }
+
+    pub fn set_source_low(&mut self, val: u8) {
+        self.source = (self.source & 0xff00) | (val & 0xf0) as u16;
+    }
+
+    pub fn set_dest_high(&mut self, val: u8) {
+        self.dest = 0x8000 | (self.dest & 0x00ff) | (((val & 0x1f) as u16) << 8);
+    }
+
+    pub fn set_dest_low(&mut self, val: u8) {
+        self.dest = (self.dest & 0xff00) | (val & 0xf0) as u16;
+    }
+
+    pub fn hblank_active(&self) -> bool {
+        self.hblank_active
+    }
+
+    // what $FF55 reads
+    pub fn status(&self) -> u8 {
+        let blocks = (self.remaining_blocks.wrapping_sub(1) & 0x7f) as u8;
+        if self.hblank_active {
+            blocks
+        } else {
+            0x80 | blocks
+        }
+    }
+
+    // a write to $FF55. Returns the kind of transfer it started, if it
+    // started one rather than cancelling one.
+    pub fn write_control(&mut self, val: u8) -> Option<HdmaMode> {
+        if self.hblank_active && (val & 0x80) == 0 {
+            self.hblank_active = false;
+            return None;
+        }
+        self.remaining_blocks = (val & 0x7f) as usize + 1;
+        if (val & 0x80) != 0 {
+            self.hblank_active = true;
+            Some(HdmaMode::HBlank)
+        } else {
+            Some(HdmaMode::General)
+        }
+    }
+
+    // the (source, destination) of the next block, moving the counters
+    // past it
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        if self.remaining_blocks == 0 {
+            return None;
+        }
+        let block = (self.source, self.dest);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_LENGTH);
+        self.dest = 0x8000 | (self.dest.wrapping_add(HDMA_BLOCK_LENGTH) & 0x1ff0);
+        self.remaining_blocks -= 1;
+        if self.remaining_blocks == 0 {
+            self.hblank_active = false;
+        }
+        Some(block)
+    }
+
+    pub fn cycles_per_block(double_speed: bool) -> u64 {
+        if double_speed {
+            2 * CYCLES_PER_BLOCK
+        } else {
+            CYCLES_PER_BLOCK
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Something on the other side of an IR LED/sensor pair. Whatever owns the
+// LED calls `set_led`, and polls `receiving` to see whether light is
+// coming in from the other side.
+pub trait InfraredEndpoint {
+    fn set_led(&mut self, on: bool);
+    fn receiving(&self) -> bool;
+}
+
+// An endpoint with nothing pointed at it: never sees any light.
+pub struct Disconnected;
+
+impl InfraredEndpoint for Disconnected {
+    fn set_led(&mut self, _on: bool) {}
+    fn receiving(&self) -> bool {
+        false
+    }
+}
+
+// One end of an in-process link. Each end sees the other's LED, so two
+// emulator instances (possibly on different threads) can talk to each
+// other. Make a pair with `link`.
+pub struct LinkedEndpoint {
+    leds: Arc<[AtomicBool; 2]>,
+    side: usize,
+}
+
+pub fn link() -> (LinkedEndpoint, LinkedEndpoint) {
+    let leds = Arc::new([AtomicBool::new(false), AtomicBool::new(false)]);
+    (
+        LinkedEndpoint { leds: leds.clone(), side: 0 },
+        LinkedEndpoint { leds, side: 1 },
+    )
+}
+
+impl InfraredEndpoint for LinkedEndpoint {
+    fn set_led(&mut self, on: bool) {
+        self.leds[self.side].store(on, Ordering::SeqCst);
+    }
+
+    fn receiving(&self) -> bool {
+        self.leds[1 - self.side].load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,297 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use cpu::CpuMode;
+
+/*
+Hardware I/O registers, $FF00-$FF7F.
+
+Each register belongs to one peripheral, and writes go through that
+peripheral's handler. Every register also has a read mask and a write
+mask: bits outside the read mask don't exist and read 1, and bits outside
+the write mask are read-only and keep whatever the owning peripheral put
+there. Addresses with no register read $FF and ignore writes, as do the
+CGB registers on DMG, MGB and SGB.
+
+Most handlers just apply the write mask. The timer's resets the divider
+on any write to DIV, and the sound one follows NR52's power bit: turning
+the APU off clears $FF10-$FF25, and they ignore writes until it's back
+on. Wave RAM is left alone either way. The APU starts on, as the boot ROM
+leaves it.
+
+No register has a side effect on being read, so reads and `peek` are the
+same thing; debuggers should use `peek` anyway, so that doesn't have to
+stay true.
+*/
+pub const P1: usize = 0x00;
+pub const SB: usize = 0x01;
+pub const SC: usize = 0x02;
+pub const DIV: usize = 0x04;
+pub const TIMA: usize = 0x05;
+pub const TMA: usize = 0x06;
+pub const TAC: usize = 0x07;
+pub const IF: usize = 0x0f;
+pub const NR10: usize = 0x10;
+pub const NR51: usize = 0x25;
+pub const NR52: usize = 0x26;
+pub const LCDC: usize = 0x40;
+pub const STAT: usize = 0x41;
+pub const SCY: usize = 0x42;
+pub const SCX: usize = 0x43;
+pub const LY: usize = 0x44;
+pub const LYC: usize = 0x45;
+pub const DMA: usize = 0x46;
+pub const BGP: usize = 0x47;
+pub const OBP0: usize = 0x48;
+pub const OBP1: usize = 0x49;
+pub const WY: usize = 0x4a;
+pub const WX: usize = 0x4b;
+pub const KEY0: usize = 0x4c;
+pub const KEY1: usize = 0x4d;
+pub const VBK: usize = 0x4f;
+pub const BOOT: usize = 0x50;
+pub const HDMA1: usize = 0x51;
+pub const HDMA2: usize = 0x52;
+pub const HDMA3: usize = 0x53;
+pub const HDMA4: usize = 0x54;
+pub const HDMA5: usize = 0x55;
+pub const RP: usize = 0x56;
+pub const BCPS: usize = 0x68;
+pub const BCPD: usize = 0x69;
+pub const OCPS: usize = 0x6a;
+pub const OCPD: usize = 0x6b;
+pub const OPRI: usize = 0x6c;
+pub const SVBK: usize = 0x70;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Peripheral {
+    Joypad,
+    Serial,
+    Timer,
+    Interrupts,
+    Sound,
+    Lcd,
+    Cgb,
+    Unmapped,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegisterSpec {
+    pub owner: Peripheral,
+    pub read_mask: u8,
+    pub write_mask: u8,
+}
+
+const APU_ON: u8 = 0x80;
+
+const UNMAPPED: RegisterSpec = RegisterSpec {
+    owner: Peripheral::Unmapped,
+    read_mask: 0x00,
+    write_mask: 0x00,
+};
+
+fn reg(owner: Peripheral, read_mask: u8, write_mask: u8) -> RegisterSpec {
+    RegisterSpec { owner, read_mask, write_mask }
+}
+
+pub fn spec(idx: usize, model: CpuMode) -> RegisterSpec {
+    use self::Peripheral::*;
+    let cgb = match model {
+        CpuMode::CGB => true,
+        CpuMode::DMG | CpuMode::MGB | CpuMode::SGB => false,
+    };
+    match idx {
+        // the low nibble is the key matrix; see `memory::joypad`
+        P1 => reg(Joypad, 0x30, 0x30),
+        SB => reg(Serial, 0xff, 0xff),
+        SC if cgb => reg(Serial, 0x83, 0x83),
+        SC => reg(Serial, 0x81, 0x81),
+        DIV | TIMA | TMA => reg(Timer, 0xff, 0xff),
+        TAC => reg(Timer, 0x07, 0x07),
+        IF => reg(Interrupts, 0x1f, 0x1f),
+
+        NR10 => reg(Sound, 0x7f, 0x7f),
+        0x11 | 0x16 => reg(Sound, 0xc0, 0xff),
+        0x12 | 0x17 | 0x21 | 0x22 | 0x24 | NR51 => reg(Sound, 0xff, 0xff),
+        0x13 | 0x18 | 0x1b | 0x1d => reg(Sound, 0x00, 0xff),
+        0x14 | 0x19 | 0x1e => reg(Sound, 0x40, 0xc7),
+        0x1a => reg(Sound, 0x80, 0x80),
+        0x1c => reg(Sound, 0x60, 0x60),
+        0x20 => reg(Sound, 0x00, 0x3f),
+        0x23 => reg(Sound, 0x40, 0xc0),
+        NR52 => reg(Sound, 0x8f, 0x80),
+        0x30 ... 0x3f => reg(Sound, 0xff, 0xff),
+
+        LCDC | SCY | SCX | LYC | DMA | BGP | OBP0 | OBP1 | WY | WX => reg(Lcd, 0xff, 0xff),
+        // the mode and coincidence bits are the PPU's
+        STAT => reg(Lcd, 0x7f, 0x78),
+        LY => reg(Lcd, 0xff, 0x00),
+        BOOT => reg(Lcd, 0x00, 0x01),
+
+        // only the boot ROM gets to write it; see `ppu::colorize`
+        KEY0 if cgb => reg(Cgb, 0x0c, 0x00),
+        // bit 0 arms a speed switch for STOP, which flips bit 7; see
+        // `Memory::switch_speed`
+        KEY1 if cgb => reg(Cgb, 0x81, 0x01),
+        VBK if cgb => reg(Cgb, 0x01, 0x01),
+        HDMA1 | HDMA3 if cgb => reg(Cgb, 0x00, 0xff),
+        HDMA2 | HDMA4 if cgb => reg(Cgb, 0x00, 0xf0),
+        HDMA5 if cgb => reg(Cgb, 0xff, 0xff),
+        RP if cgb => reg(Cgb, 0xc3, 0xc1),
+        BCPS | OCPS if cgb => reg(Cgb, 0xbf, 0xbf),
+        BCPD | OCPD if cgb => reg(Cgb, 0xff, 0xff),
+        OPRI if cgb => reg(Cgb, 0x01, 0x01),
+        SVBK if cgb => reg(Cgb, 0x07, 0x07),
+
+        _ => UNMAPPED,
+    }
+}
+
+pub struct IoRegisters {
+    model: CpuMode,
+    regs: [d8; 0x80],
+    // DIV is the high byte of this, which counts T-cycles
+    divider: u16,
+}
+
+impl IoRegisters {
+    pub fn new(model: CpuMode) -> Self {
+        let mut regs = [d8::ZERO; 0x80];
+        regs[NR52] = d8(Wrapping(APU_ON));
+        IoRegisters {
+            model,
+            regs,
+            divider: 0,
+        }
+    }
+
+    pub fn set_model(&mut self, model: CpuMode) {
+        self.model = model;
+    }
+
+    pub fn peek(&self, idx: usize) -> d8 {
+        let spec = spec(idx, self.model);
+        let raw = match idx {
+            DIV => d8(Wrapping((self.divider >> 8) as u8)),
+            _ => self.regs[idx],
+        };
+        (raw & d8(Wrapping(spec.read_mask))) | d8(Wrapping(!spec.read_mask))
+    }
+
+    pub fn read(&self, idx: usize) -> d8 {
+        self.peek(idx)
+    }
+
+    pub fn write(&mut self, idx: usize, val: d8) {
+        let spec = spec(idx, self.model);
+        match spec.owner {
+            Peripheral::Timer => self.write_timer(idx, val, spec),
+            Peripheral::Sound => self.write_sound(idx, val, spec),
+            Peripheral::Joypad
+            | Peripheral::Serial
+            | Peripheral::Interrupts
+            | Peripheral::Lcd
+            | Peripheral::Cgb => self.write_masked(idx, val, spec),
+            Peripheral::Unmapped => (),
+        }
+    }
+
+    // sets a register's value as the owning peripheral sees it, read-only
+    // bits included
+    pub fn set(&mut self, idx: usize, val: d8) {
+        self.regs[idx] = val;
+    }
+
+    // the stored value, without the read mask applied
+    pub fn get(&self, idx: usize) -> d8 {
+        self.regs[idx]
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        self.divider = self.divider.wrapping_add(cycles as u16);
+    }
+
+    fn write_masked(&mut self, idx: usize, val: d8, spec: RegisterSpec) {
+        let write_mask = d8(Wrapping(spec.write_mask));
+        self.regs[idx] = (self.regs[idx] & !write_mask) | (val & write_mask);
+    }
+
+    fn write_timer(&mut self, idx: usize, val: d8, spec: RegisterSpec) {
+        match idx {
+            // any write clears the whole divider
+            DIV => self.divider = 0,
+            _ => self.write_masked(idx, val, spec),
+        }
+    }
+
+    fn write_sound(&mut self, idx: usize, val: d8, spec: RegisterSpec) {
+        let d8(Wrapping(nr52)) = self.regs[NR52];
+        match idx {
+            NR52 => {
+                let d8(Wrapping(byte)) = val;
+                if (byte & APU_ON) == 0 {
+                    for reg in &mut self.regs[NR10..NR52] {
+                        *reg = d8::ZERO;
+                    }
+                }
+                self.write_masked(idx, val, spec);
+            }
+            NR10 ... NR51 if (nr52 & APU_ON) == 0 => (),
+            _ => self.write_masked(idx, val, spec),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn byte(val: u8) -> d8 {
+        d8(Wrapping(val))
+    }
+
+    #[test]
+    fn div_counts_and_resets() {
+        let mut io = IoRegisters::new(CpuMode::DMG);
+        io.tick(0x300);
+        assert_eq!(io.read(DIV), byte(0x03));
+        io.write(DIV, byte(0x7f));
+        assert_eq!(io.read(DIV), byte(0x00));
+    }
+
+    #[test]
+    fn stat_read_only_and_unused_bits() {
+        let mut io = IoRegisters::new(CpuMode::DMG);
+        io.set(STAT, byte(0x02));
+        io.write(STAT, byte(0xff));
+        assert_eq!(io.read(STAT), byte(0xfa));
+        io.write(LY, byte(0x12));
+        assert_eq!(io.read(LY), byte(0x00));
+    }
+
+    #[test]
+    fn apu_power_clears_sound_registers() {
+        let mut io = IoRegisters::new(CpuMode::DMG);
+        io.write(NR51, byte(0xf3));
+        io.write(0x30, byte(0x5a));
+        io.write(NR52, byte(0x00));
+        assert_eq!(io.read(NR51), byte(0x00));
+        assert_eq!(io.read(NR52), byte(0x70));
+        io.write(NR51, byte(0xf3));
+        assert_eq!(io.read(NR51), byte(0x00));
+        assert_eq!(io.read(0x30), byte(0x5a));
+        io.write(NR52, byte(0x80));
+        io.write(NR51, byte(0xf3));
+        assert_eq!(io.read(NR51), byte(0xf3));
+    }
+
+    #[test]
+    fn cgb_registers_only_on_cgb() {
+        let mut io = IoRegisters::new(CpuMode::DMG);
+        io.write(SVBK, byte(0x03));
+        assert_eq!(io.read(SVBK), byte(0xff));
+        io.set_model(CpuMode::CGB);
+        io.write(SVBK, byte(0x03));
+        assert_eq!(io.read(SVBK), byte(0xfb));
+        assert_eq!(io.read(0x7f), byte(0xff));
+    }
+}
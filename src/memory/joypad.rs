@@ -0,0 +1,65 @@
+/*
+The joypad, read through P1 ($FF00):
+
+bit 5	0 - select the buttons
+bit 4	0 - select the d-pad
+bit 3	Down or Start, 0 if pressed
+bit 2	Up or Select
+bit 1	Left or B
+bit 0	Right or A
+
+With both rows selected, a key reads pressed if either of its two is. With
+neither, the low nibble reads $F, except on a Super Game Boy with more than
+one controller, where it's $F minus the controller being read; see
+`memory::sgb`.
+*/
+pub const RIGHT: u8 = 0x01;
+pub const LEFT: u8 = 0x02;
+pub const UP: u8 = 0x04;
+pub const DOWN: u8 = 0x08;
+pub const A: u8 = 0x10;
+pub const B: u8 = 0x20;
+pub const SELECT: u8 = 0x40;
+pub const START: u8 = 0x80;
+
+// as many controllers as a Super Game Boy takes
+pub const PLAYERS: usize = 4;
+
+const SELECT_DPAD: u8 = 0x10;
+const SELECT_BUTTONS: u8 = 0x20;
+
+pub struct Joypad {
+    // the keys held on each controller, 1 for held
+    pressed: [u8; PLAYERS],
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad { pressed: [0; PLAYERS] }
+    }
+
+    pub fn set_pressed(&mut self, player: usize, keys: u8) {
+        self.pressed[player] = keys;
+    }
+
+    pub fn pressed(&self, player: usize) -> u8 {
+        self.pressed[player]
+    }
+
+    // the low nibble of P1, given its select bits and which controller
+    // is wired through
+    pub fn read(&self, select: u8, player: usize) -> u8 {
+        if (select & (SELECT_DPAD | SELECT_BUTTONS)) == (SELECT_DPAD | SELECT_BUTTONS) {
+            return 0x0f - player as u8;
+        }
+        let keys = self.pressed[player];
+        let mut held = 0;
+        if (select & SELECT_DPAD) == 0 {
+            held |= keys & 0x0f;
+        }
+        if (select & SELECT_BUTTONS) == 0 {
+            held |= keys >> 4;
+        }
+        !held & 0x0f
+    }
+}
@@ -1,13 +1,118 @@
 use number_types::d8_type::d8;
 use number_types::d16_type::d16;
 use number_types::a16_type::a16;
+use number_types::banked_address::{BankedAddress, MemoryRegion};
+use cpu::CpuMode;
+use ppu::{Ppu, Renderer, VideoMemory};
+use ppu::vram_view::Image;
+use ppu::palette::CgbPalettes;
+use ppu::colorize::{self, PaletteOverride};
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{self, Read};
 use std::num::Wrapping;
+use std::path::Path;
+use std::time::Duration;
 
-type CartRomBank0 = [d8; 0x3eb0];
-type CartRomBankN = [d8; 0x4000];
-type BackgroundMapData = [d8; 0x400];
+pub mod cartridge;
+use self::cartridge::{Cartridge, CartridgeEvent, CameraImage, RomOnly};
+
+pub mod infrared;
+use self::infrared::InfraredEndpoint;
+
+pub mod save;
+use self::save::SaveFile;
+
+pub mod rtc;
+
+pub mod io_regs;
+use self::io_regs::IoRegisters;
+
+pub mod dma;
+use self::dma::OamDma;
+
+pub mod hdma;
+use self::hdma::{Hdma, HdmaMode};
+
+pub mod watch;
+use self::watch::{Watchpoints, WatchKind, WatchAction, WatchEvent, WatchId};
+
+pub mod joypad;
+use self::joypad::Joypad;
+
+pub mod sgb;
+use self::sgb::Sgb;
+
+#[cfg(test)]
+mod test;
+
+// one entry of `Memory::regions`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegionInfo {
+    pub region: MemoryRegion,
+    pub name: &'static str,
+    // first and last address, inclusive
+    pub range: (u16, u16),
+    pub bank: usize,
+    pub writable: bool,
+}
+
+// how long the CPU sits stopped while a CGB speed switch settles
+const SPEED_SWITCH_CYCLES: u64 = 8200;
+
+pub type BackgroundMapData = [d8; 0x400];
 type InternalRamBank = [d8; 0x1000];
 
+// one bank of $8000-$9FFF. On CGB, bank 1's map areas hold the BG attributes
+// for the tile numbers in bank 0.
+#[derive(Copy, Clone)]
+pub struct VideoRamBank {
+    character_ram: [d8; 0x1800],
+    background_data_0: BackgroundMapData,
+    background_data_1: BackgroundMapData,
+}
+
+impl VideoRamBank {
+    fn new() -> Self {
+        VideoRamBank {
+            character_ram: [d8::ZERO; 0x1800],
+            background_data_0: [d8::ZERO; 0x400],
+            background_data_1: [d8::ZERO; 0x400],
+        }
+    }
+
+    // `idx` is relative to $8000
+    pub fn get(&self, idx: usize) -> d8 {
+        match idx {
+            0x0000 ... 0x17ff => self.character_ram[idx],
+            0x1800 ... 0x1bff => self.background_data_0[idx - 0x1800],
+            _ => self.background_data_1[idx - 0x1c00],
+        }
+    }
+
+    // the 384 tiles at $8000-$97FF
+    pub fn character_ram(&self) -> &[d8; 0x1800] {
+        &self.character_ram
+    }
+
+    // the map at $9800 for 0, $9C00 for 1
+    pub fn background_map(&self, map: usize) -> &BackgroundMapData {
+        if map == 0 {
+            &self.background_data_0
+        } else {
+            &self.background_data_1
+        }
+    }
+
+    fn get_mut(&mut self, idx: usize) -> &mut d8 {
+        match idx {
+            0x0000 ... 0x17ff => &mut self.character_ram[idx],
+            0x1800 ... 0x1bff => &mut self.background_data_0[idx - 0x1800],
+            _ => &mut self.background_data_1[idx - 0x1c00],
+        }
+    }
+}
+
 /*
 http://gameboy.mongenel.com/dmg/asmmemmap.html :
 
@@ -18,113 +123,733 @@ $FF00-$FF7F	Hardware I/O Registers
 $FEA0-$FEFF	Unusable Memory
 $FE00-$FE9F	OAM - Object Attribute Memory
 $E000-$FDFF	Echo RAM - Reserved, Do Not Use
-$D000-$DFFF	Internal RAM - Bank 1-7 (switchable - CGB only)
+$D000-$DFFF	Internal RAM - Bank 1-7 (switchable - CGB only, via $FF70)
 $C000-$CFFF	Internal RAM - Bank 0 (fixed)
 $A000-$BFFF	Cartridge RAM (If Available)
 $9C00-$9FFF	BG Map Data 2
 $9800-$9BFF	BG Map Data 1
 $8000-$97FF	Character RAM
+		(all of $8000-$9FFF has two banks on CGB, via $FF4F)
 $4000-$7FFF	Cartridge ROM - Switchable Banks 1-xx
 $0150-$3FFF	Cartridge ROM - Bank 0 (fixed)
 $0100-$014F	Cartridge Header Area
 $0000-$00FF	Restart and Interrupt Vectors
+
+Echo RAM mirrors $C000-$DDFF, reads and writes both. Writes to the unusable
+region are ignored, and what it reads depends on the model: DMG and MGB
+read $00, and CGB reads the high nibble of the low address byte twice
+($FEA0-$FEAF read $AA, $FEB0-$FEBF read $BB, and so on). Cartridge RAM that
+isn't there or isn't enabled floats, and reads $FF.
+
+While the LCD is on, the PPU has VRAM to itself in mode 3, and OAM in modes
+2 and 3. The CPU reads $FF from them then, and its writes are dropped.
 */
 
 pub struct Memory {
-    restart_and_int_vectors: [d8; 0x100],
-    cartridge_header: [d8; 0x50],
-    cart_rom_bank_0: CartRomBank0,
-    other_cart_rom_banks: Vec<CartRomBankN>,
-    active_rom_bank_index: usize,
-    character_ram: [d8; 0x800],
-    background_data_0: BackgroundMapData,
-    background_data_1: BackgroundMapData,
-    cart_ram: Option<[d8; 0x2000]>,
+    model: CpuMode,
+    cartridge: Box<dyn Cartridge>,
+    cartridge_listeners: Vec<Box<dyn FnMut(CartridgeEvent)>>,
+    has_battery: bool,
+    save_file: Option<SaveFile>,
+    save_dirty: bool,
+    video_ram: [VideoRamBank; 2],
     internal_ram_bank_0: InternalRamBank,
+    // banks 1-7; only CGB can switch away from bank 1
     other_internal_ram_banks: Vec<InternalRamBank>,
-    active_ram_bank_index: usize,
     object_attribute_memory: [d8; 0xa0],
-    hardware_io_regs: [d8; 0x80],
+    io: IoRegisters,
+    oam_dma: OamDma,
+    hdma: Hdma,
+    // cycles the CPU owes to a VRAM DMA or speed switch, see
+    // `take_stall_cycles`
+    stall_cycles: u64,
+    // reads only get `&self`, and still have to run watch callbacks
+    watches: RefCell<Watchpoints>,
+    // checked before anything else, so unwatched accesses cost one branch
+    watching: bool,
+    watch_halt: Cell<bool>,
+    pc: a16,
+    // lets debuggers at VRAM and OAM whatever the PPU is doing
+    ppu_lock_bypass: bool,
+    ppu: Ppu,
+    palettes: CgbPalettes,
+    palette_override: Option<PaletteOverride>,
+    joypad: Joypad,
+    sgb: Sgb,
+    // in double speed, the odd cycle left over from the last tick
+    ppu_half_dot: u64,
+    high_ram: [d8; 0x7f],
     enable_interrupt_flag: d8,
 }
 
 impl Memory {
     pub fn new_zeros() -> Self {
+        Self::with_cartridge(Box::new(RomOnly::new(vec![d8::ZERO; 0x8000])))
+    }
+
+    pub fn from_rom(rom: Vec<d8>) -> Option<Self> {
+        let has_battery = cartridge::Header::parse(&rom)?.has_battery();
+        let mut memory = Self::with_cartridge(cartridge::from_rom(rom)?);
+        memory.has_battery = has_battery;
+        Some(memory)
+    }
+
+    // loads a ROM image, and if the cart has a battery, its `.sav` file
+    pub fn open_rom_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path.as_ref())?.read_to_end(&mut bytes)?;
+        let rom = bytes.into_iter().map(|b| d8(Wrapping(b))).collect();
+        let mut memory = Self::from_rom(rom).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "unsupported cartridge")
+        })?;
+        if memory.has_battery {
+            memory.attach_save_file(SaveFile::next_to_rom(path))?;
+        }
+        Ok(memory)
+    }
+
+    pub fn with_cartridge(cartridge: Box<dyn Cartridge>) -> Self {
         Self {
-            restart_and_int_vectors: [d8::ZERO; 0x100],
-            cartridge_header: [d8::ZERO; 0x50],
-            cart_rom_bank_0: [d8::ZERO; 0x3eb0],
-            other_cart_rom_banks: vec![[d8::ZERO; 0x4000]],
-            active_rom_bank_index: 0,
-            character_ram: [d8::ZERO; 0x800],
-            background_data_0: [d8::ZERO; 0x400],
-            background_data_1: [d8::ZERO; 0x400],
-            cart_ram: None,
+            model: CpuMode::DMG,
+            cartridge,
+            cartridge_listeners: Vec::new(),
+            has_battery: false,
+            save_file: None,
+            save_dirty: false,
+            video_ram: [VideoRamBank::new(), VideoRamBank::new()],
             internal_ram_bank_0: [d8::ZERO; 0x1000],
-            other_internal_ram_banks: vec![[d8::ZERO; 0x1000]],
-            active_ram_bank_index: 0,
+            other_internal_ram_banks: vec![[d8::ZERO; 0x1000]; 7],
             object_attribute_memory: [d8::ZERO; 0xa0],
-            hardware_io_regs: [d8::ZERO; 0x80],
+            io: IoRegisters::new(CpuMode::DMG),
+            oam_dma: OamDma::new(),
+            hdma: Hdma::new(),
+            stall_cycles: 0,
+            watches: RefCell::new(Watchpoints::new()),
+            watching: false,
+            watch_halt: Cell::new(false),
+            pc: a16(Wrapping(0)),
+            ppu_lock_bypass: false,
+            ppu: Ppu::new(),
+            palettes: CgbPalettes::new(),
+            palette_override: None,
+            joypad: Joypad::new(),
+            sgb: Sgb::new(),
+            ppu_half_dot: 0,
+            high_ram: [d8::ZERO; 0x7f],
             enable_interrupt_flag: d8::ZERO,
         }
     }
-    
-    pub fn read_d8(&self, a16(Wrapping(idx)): a16) -> Option<d8> {
+
+    pub fn model(&self) -> CpuMode {
+        self.model
+    }
+
+    pub fn set_model(&mut self, model: CpuMode) {
+        self.model = model;
+        self.io.set_model(model);
+        self.io.set(io_regs::HDMA5, d8(Wrapping(self.hdma.status())));
+        self.colorize_dmg_game();
+        let sgb = self.is_sgb()
+            && sgb::cart_supports_sgb(
+                self.peek_rom(sgb::SGB_FLAG_ADDR),
+                self.peek_rom(sgb::OLD_LICENSEE_ADDR),
+            );
+        self.sgb.set_enabled(sgb);
+    }
+
+    // does what the CGB boot ROM does for a DMG game: DMG compatibility
+    // mode, with palettes picked for the game. See `ppu::colorize`.
+    fn colorize_dmg_game(&mut self) {
+        let mut key0 = 0;
+        if self.is_cgb() {
+            let header: Vec<u8> = (colorize::HEADER_START..colorize::HEADER_END)
+                .map(|idx| self.peek_rom(idx))
+                .collect();
+            if colorize::needs_colorization(&header) {
+                colorize::colorization(&header, self.palette_override).load(&mut self.palettes);
+                key0 = colorize::KEY0_DMG_COMPAT;
+            }
+        }
+        self.io.set(io_regs::KEY0, d8(Wrapping(key0)));
+    }
+
+    // a ROM byte without the side effects reads can have on the mapper
+    fn peek_rom(&self, idx: usize) -> u8 {
+        let d8(Wrapping(byte)) = self.cartridge.peek_rom(idx);
+        byte
+    }
+
+    // the buttons held while the CGB boot logo is up, which pick a DMG
+    // game's palettes instead of the boot ROM
+    pub fn set_palette_override(&mut self, choice: Option<PaletteOverride>) {
+        self.palette_override = choice;
+        self.colorize_dmg_game();
+    }
+
+    // reads an I/O register, $00-$7F, without disturbing anything
+    pub fn peek_io(&self, reg: usize) -> d8 {
+        self.io.peek(reg)
+    }
+
+    pub fn io(&self) -> &IoRegisters {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut IoRegisters {
+        &mut self.io
+    }
+
+    fn is_cgb(&self) -> bool {
+        match self.model {
+            CpuMode::CGB => true,
+            CpuMode::DMG | CpuMode::MGB | CpuMode::SGB => false,
+        }
+    }
+
+    fn is_sgb(&self) -> bool {
+        match self.model {
+            CpuMode::SGB => true,
+            CpuMode::DMG | CpuMode::MGB | CpuMode::CGB => false,
+        }
+    }
+
+    // VBK bit 0 picks the bank the CPU sees at $8000-$9FFF
+    pub fn active_vram_bank(&self) -> usize {
+        if self.is_cgb() {
+            let d8(Wrapping(vbk)) = self.io.get(io_regs::VBK);
+            (vbk & 1) as usize
+        } else {
+            0
+        }
+    }
+
+    // SVBK bits 0-2 pick the bank at $D000-$DFFF, with 0 meaning 1
+    pub fn active_ram_bank(&self) -> usize {
+        if self.is_cgb() {
+            let d8(Wrapping(svbk)) = self.io.get(io_regs::SVBK);
+            ::std::cmp::max((svbk & 7) as usize, 1)
+        } else {
+            1
+        }
+    }
+
+    // VRAM as the PPU sees it, regardless of which bank the CPU has mapped
+    pub fn vram_bank(&self, bank: usize) -> &VideoRamBank {
+        &self.video_ram[bank]
+    }
+
+    // everything the PPU reads, past any CPU-side locks
+    pub fn video<'a>(&'a self) -> VideoMemory<'a> {
+        VideoMemory {
+            model: self.model,
+            io: &self.io,
+            vram: &self.video_ram,
+            oam: &self.object_attribute_memory,
+            palettes: &self.palettes,
+        }
+    }
+
+    // the PPU mode is STAT bits 0-1
+    fn ppu_locks(&self, idx: usize) -> bool {
+        let locked_in = |modes: &[u8]| {
+            if self.ppu_lock_bypass || !self.lcd_enabled() {
+                return false;
+            }
+            let d8(Wrapping(stat)) = self.io.get(io_regs::STAT);
+            modes.contains(&(stat & 0x03))
+        };
+        match idx {
+            0x8000 ... 0x9fff => locked_in(&[3]),
+            0xfe00 ... 0xfe9f => locked_in(&[2, 3]),
+            0xff69 | 0xff6b => locked_in(&[3]),
+            _ => false,
+        }
+    }
+
+    // the bank currently mapped in `region`; 0 for regions without banks
+    pub fn current_bank(&self, region: MemoryRegion) -> usize {
+        match region {
+            MemoryRegion::RomBank0 => self.cartridge.rom_bank_at(0x0000),
+            MemoryRegion::RomBankN => self.cartridge.rom_bank_at(0x4000),
+            MemoryRegion::VideoRam => self.active_vram_bank(),
+            MemoryRegion::CartridgeRam => self.cartridge.ram_bank(),
+            MemoryRegion::WorkRamBankN => self.active_ram_bank(),
+            _ => 0,
+        }
+    }
+
+    pub fn banked_address(&self, addr: a16) -> BankedAddress {
+        let region = MemoryRegion::containing(addr);
+        let a16(Wrapping(idx)) = addr;
+        BankedAddress {
+            region,
+            bank: self.current_bank(region),
+            offset: idx - region.range().0,
+        }
+    }
+
+    // what's mapped where right now, for debuggers and trace logs
+    pub fn regions(&self) -> impl Iterator<Item = RegionInfo> + '_ {
+        MemoryRegion::ALL.iter().map(move |&region| {
+            let writable = match region {
+                MemoryRegion::RomBank0 | MemoryRegion::RomBankN | MemoryRegion::Unusable => false,
+                MemoryRegion::CartridgeRam => self.cartridge.ram_writable(),
+                _ => true,
+            };
+            RegionInfo {
+                region,
+                name: region.name(),
+                range: region.range(),
+                bank: self.current_bank(region),
+                writable,
+            }
+        })
+    }
+
+    pub fn set_ppu_lock_bypass(&mut self, bypass: bool) {
+        self.ppu_lock_bypass = bypass;
+    }
+
+    fn read_unusable(&self, idx: usize) -> d8 {
+        match self.model {
+            CpuMode::DMG | CpuMode::MGB | CpuMode::SGB => d8::ZERO,
+            CpuMode::CGB => {
+                let nibble = ((idx >> 4) & 0x0f) as u8;
+                d8(Wrapping(nibble << 4 | nibble))
+            }
+        }
+    }
+
+    pub fn read_d8(&self, addr: a16) -> Option<d8> {
+        let a16(Wrapping(idx)) = addr;
+        let val = if self.oam_dma.is_blocking() && idx < 0xff00 {
+            Some(self.oam_dma.last_byte())
+        } else if self.ppu_locks(idx as usize) {
+            Some(d8(Wrapping(0xff)))
+        } else {
+            self.read_bus(addr)
+        };
+        if self.watching {
+            let val = val.unwrap_or(d8::ZERO);
+            self.fire_watch(WatchKind::Read, addr, val, val);
+        }
+        val
+    }
+
+    // `read_bus` without the side effects reads can have on the mapper,
+    // for watchpoints
+    fn peek_bus(&self, addr: a16) -> Option<d8> {
+        let a16(Wrapping(idx)) = addr;
+        match idx {
+            0x0000 ... 0x7fff => Some(self.cartridge.peek_rom(idx as usize)),
+            _ => self.read_bus(addr),
+        }
+    }
+
+    // a read as the DMA engines see it, without their own bus conflicts
+    fn read_bus(&self, a16(Wrapping(idx)): a16) -> Option<d8> {
         let idx = idx as usize;
         match idx {
-            0x0000 ... 0x00ff => Some(self.restart_and_int_vectors[idx]),
-            0x0100 ... 0x014f => Some(self.cartridge_header[idx - 0x0100]),
-            0x0150 ... 0x3fff => Some(self.cart_rom_bank_0[idx - 0x0150]),
-            0x4000 ... 0x7fff => Some(self.other_cart_rom_banks[
-                self.active_rom_bank_index
-            ][idx - 0x4000]),
-            0x8000 ... 0x97ff => Some(self.character_ram[idx - 0x8000]),
-            0x9800 ... 0x9bff => Some(self.background_data_0[idx - 0x9800]),
-            0x9c00 ... 0x9fff => Some(self.background_data_1[idx - 0x9c00]),
-            0xa000 ... 0xbfff => self.cart_ram.as_ref().and_then(|mem| {
-                Some(mem[idx - 0xa000])
-            }),
+            0x0000 ... 0x7fff => Some(self.cartridge.read_rom(idx)),
+            0x8000 ... 0x9fff => Some(self.video_ram[self.active_vram_bank()].get(idx - 0x8000)),
+            0xa000 ... 0xbfff => Some(
+                self.cartridge.read_ram(idx - 0xa000).unwrap_or(d8(Wrapping(0xff)))
+            ),
             0xc000 ... 0xcfff => Some(self.internal_ram_bank_0[idx - 0xc000]),
             0xd000 ... 0xdfff => Some(self.other_internal_ram_banks[
-                self.active_ram_bank_index
+                self.active_ram_bank() - 1
             ][idx - 0xd000]),
-            0xe000 ... 0xfdff => None,
+            0xe000 ... 0xfdff => self.read_bus(a16(Wrapping((idx - 0x2000) as u16))),
             0xfe00 ... 0xfe9f => Some(self.object_attribute_memory[idx - 0xfe00]),
-            0xfea0 ... 0xfeff => None,
-            0xff00 ... 0xff7f => Some(self.hardware_io_regs[idx - 0xff00]),
-            0xff80 ... 0xfffe => Some(d8::ZERO),
+            0xfea0 ... 0xfeff => Some(self.read_unusable(idx)),
+            0xff00 ... 0xff7f => Some(self.read_io(idx - 0xff00)),
+            0xff80 ... 0xfffe => Some(self.high_ram[idx - 0xff80]),
             0xffff => Some(self.enable_interrupt_flag),
             _ => unreachable!(),
         }
     }
 
-    pub fn put_d8(&mut self, a16(Wrapping(idx)): a16, val: d8) -> Option<()> {
+    pub fn put_d8(&mut self, addr: a16, val: d8) -> Option<()> {
+        if !self.watching {
+            return self.write_bus(addr, val);
+        }
+        let a16(Wrapping(idx)) = addr;
+        let watched = self.watches.borrow().covers(WatchKind::Write, idx);
+        let old = if watched {
+            self.peek_bus(addr).unwrap_or(d8::ZERO)
+        } else {
+            d8::ZERO
+        };
+        let written = self.write_bus(addr, val);
+        if watched {
+            self.fire_watch(WatchKind::Write, addr, old, val);
+        }
+        written
+    }
+
+    fn write_bus(&mut self, a16(Wrapping(idx)): a16, val: d8) -> Option<()> {
         let idx = idx as usize;
+        if (self.oam_dma.is_blocking() && idx < 0xff00) || self.ppu_locks(idx) {
+            return None;
+        }
         match idx {
-            0x0000 ... 0x00ff => Some(self.restart_and_int_vectors[idx] = val),
-            0x0100 ... 0x014f => None,
-            0x0150 ... 0x3fff => None,
-            0x4000 ... 0x7fff => None,
-            0x8000 ... 0x97ff => Some(self.character_ram[idx - 0x8000] = val),
-            0x9800 ... 0x9bff => Some(self.background_data_0[idx - 0x9800] = val),
-            0x9c00 ... 0x9fff => Some(self.background_data_1[idx - 0x9c00] = val),
-            0xa000 ... 0xbfff => self.cart_ram.as_mut().and_then(|mem| {
-                Some(mem[idx - 0xa000] = val)
-            }),
+            0x0000 ... 0x7fff => {
+                self.cartridge.write_rom(idx, val);
+                self.dispatch_cartridge_events();
+                Some(())
+            }
+            0x8000 ... 0x9fff => {
+                let bank = self.active_vram_bank();
+                Some(*self.video_ram[bank].get_mut(idx - 0x8000) = val)
+            }
+            0xa000 ... 0xbfff => {
+                let written = self.cartridge.write_ram(idx - 0xa000, val);
+                self.save_dirty |= written.is_some();
+                written
+            }
             0xc000 ... 0xcfff => Some(self.internal_ram_bank_0[idx - 0xc000] = val),
-            0xd000 ... 0xdfff => Some(self.other_internal_ram_banks[
-                self.active_ram_bank_index
-            ][idx - 0xd000] = val),
-            0xe000 ... 0xfdff => None,
+            0xd000 ... 0xdfff => {
+                let bank = self.active_ram_bank() - 1;
+                Some(self.other_internal_ram_banks[bank][idx - 0xd000] = val)
+            }
+            0xe000 ... 0xfdff => self.write_bus(a16(Wrapping((idx - 0x2000) as u16)), val),
             0xfe00 ... 0xfe9f => Some(self.object_attribute_memory[idx - 0xfe00] = val),
-            0xfea0 ... 0xfeff => None,
-            0xff00 ... 0xff7f => Some(self.hardware_io_regs[idx - 0xff00] = val),
-            0xff80 ... 0xfffe => None,
+            0xfea0 ... 0xfeff => Some(()),
+            0xff00 ... 0xff7f => Some(self.write_io(idx - 0xff00, val)),
+            0xff80 ... 0xfffe => Some(self.high_ram[idx - 0xff80] = val),
             0xffff => Some(self.enable_interrupt_flag = val),
             _ => unreachable!(),
         }
     }
 
+    // calls `callback` for every `kind` of access to `start`-`end`, inclusive
+    pub fn add_watch<F>(&mut self, kind: WatchKind, start: a16, end: a16, callback: F) -> WatchId
+        where F: FnMut(&WatchEvent) -> WatchAction + 'static
+    {
+        self.watching = true;
+        self.watches.get_mut().add(kind, start, end, Box::new(callback))
+    }
+
+    pub fn remove_watch(&mut self, id: WatchId) -> bool {
+        let removed = self.watches.get_mut().remove(id);
+        self.watching = !self.watches.get_mut().is_empty();
+        removed
+    }
+
+    // the CPU calls this as it starts each instruction, with the address
+    // of its opcode
+    pub fn begin_instruction(&mut self, pc: a16) {
+        self.pc = pc;
+        if self.watching {
+            let opcode = self.peek_bus(pc).unwrap_or(d8::ZERO);
+            self.fire_watch(WatchKind::Execute, pc, opcode, opcode);
+        }
+    }
+
+    // whether a watch callback has asked to halt since the last call
+    pub fn take_watch_halt(&mut self) -> bool {
+        self.watch_halt.replace(false)
+    }
+
+    fn fire_watch(&self, kind: WatchKind, address: a16, old_value: d8, new_value: d8) {
+        let a16(Wrapping(idx)) = address;
+        let mut watches = self.watches.borrow_mut();
+        if !watches.covers(kind, idx) {
+            return;
+        }
+        let event = WatchEvent {
+            kind,
+            pc: self.pc,
+            address,
+            old_value,
+            new_value,
+            rom_bank: self.cartridge.rom_bank_at(0x4000),
+            ram_bank: self.cartridge.ram_bank(),
+        };
+        if watches.fire(&event) == WatchAction::Halt {
+            self.watch_halt.set(true);
+        }
+    }
+
+    pub fn subscribe_cartridge_events<F>(&mut self, listener: F)
+        where F: FnMut(CartridgeEvent) + 'static
+    {
+        self.cartridge_listeners.push(Box::new(listener));
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cartridge.set_tilt(x, y);
+    }
+
+    pub fn connect_infrared(&mut self, endpoint: Box<dyn InfraredEndpoint>) {
+        self.cartridge.connect_infrared(endpoint);
+    }
+
+    pub fn set_camera_image(&mut self, image: CameraImage) {
+        self.cartridge.set_camera_image(image);
+    }
+
+    // I/O reads that reach beyond the register file
+    fn read_io(&self, reg: usize) -> d8 {
+        match reg {
+            io_regs::P1 => {
+                let d8(Wrapping(p1)) = self.io.get(io_regs::P1);
+                let select = p1 & 0x30;
+                d8(Wrapping(0xc0 | select | self.joypad.read(select, self.sgb.player())))
+            }
+            io_regs::BCPD | io_regs::OCPD if self.is_cgb() => {
+                self.palettes.read_data(reg - 1, self.io.get(reg - 1))
+            }
+            _ => self.io.read(reg),
+        }
+    }
+
+    // I/O writes that reach beyond the register file
+    fn write_io(&mut self, reg: usize, val: d8) {
+        self.io.write(reg, val);
+        let d8(Wrapping(byte)) = val;
+        self.ppu.record_write(reg, byte);
+        match reg {
+            io_regs::P1 => self.sgb.write_joypad(byte, self.ppu.framebuffer()),
+            io_regs::DMA => self.oam_dma.start(byte),
+            io_regs::HDMA1 ... io_regs::HDMA5 if self.is_cgb() => self.write_hdma(reg, byte),
+            // the index register is the one just before each data register
+            io_regs::BCPD | io_regs::OCPD if self.is_cgb() => {
+                let spec = self.palettes.write_data(reg - 1, self.io.get(reg - 1), val);
+                self.io.set(reg - 1, spec);
+            }
+            _ => (),
+        }
+    }
+
+    // palette RAM as the PPU sees it, and for boot ROM stand-ins and
+    // debuggers to fill in directly
+    pub fn palettes(&self) -> &CgbPalettes {
+        &self.palettes
+    }
+
+    pub fn palettes_mut(&mut self) -> &mut CgbPalettes {
+        &mut self.palettes
+    }
+
+    fn write_hdma(&mut self, reg: usize, byte: u8) {
+        match reg {
+            io_regs::HDMA1 => self.hdma.set_source_high(byte),
+            io_regs::HDMA2 => self.hdma.set_source_low(byte),
+            io_regs::HDMA3 => self.hdma.set_dest_high(byte),
+            io_regs::HDMA4 => self.hdma.set_dest_low(byte),
+            _ => match self.hdma.write_control(byte) {
+                Some(HdmaMode::General) => while self.copy_hdma_block() {},
+                // with the LCD off there's no HBlank to wait for, so the
+                // first block goes straight away
+                Some(HdmaMode::HBlank) if !self.lcd_enabled() => {
+                    self.copy_hdma_block();
+                }
+                _ => (),
+            },
+        }
+        self.io.set(io_regs::HDMA5, d8(Wrapping(self.hdma.status())));
+    }
+
+    fn copy_hdma_block(&mut self) -> bool {
+        let (source, dest) = match self.hdma.next_block() {
+            Some(block) => block,
+            None => return false,
+        };
+        let bank = self.active_vram_bank();
+        for i in 0..hdma::HDMA_BLOCK_LENGTH {
+            let byte = self.read_bus(a16(Wrapping(source.wrapping_add(i))))
+                .unwrap_or(d8(Wrapping(0xff)));
+            *self.video_ram[bank].get_mut((dest + i - 0x8000) as usize) = byte;
+        }
+        self.stall_cycles += Hdma::cycles_per_block(self.double_speed());
+        true
+    }
+
+    // called as the PPU enters HBlank, for HBlank DMA
+    pub fn enter_hblank(&mut self) {
+        if self.hdma.hblank_active() {
+            self.copy_hdma_block();
+            self.io.set(io_regs::HDMA5, d8(Wrapping(self.hdma.status())));
+        }
+    }
+
+    // the cycles the CPU has to sit out while VRAM DMA or a speed switch
+    // runs. The CPU should spend them, ticking memory, before its next
+    // instruction.
+    pub fn take_stall_cycles(&mut self) -> u64 {
+        ::std::mem::replace(&mut self.stall_cycles, 0)
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        let d8(Wrapping(lcdc)) = self.io.get(io_regs::LCDC);
+        (lcdc & 0x80) != 0
+    }
+
+    // KEY1 bit 7, which the speed switch sets
+    pub fn double_speed(&self) -> bool {
+        let d8(Wrapping(key1)) = self.io.get(io_regs::KEY1);
+        self.is_cgb() && (key1 & 0x80) != 0
+    }
+
+    // what STOP does on CGB once a program has set KEY1 bit 0: flip the
+    // speed, disarm the switch, reset DIV, and stall the CPU while the
+    // clock settles. Returns false, changing nothing, if no switch was armed.
+    pub fn switch_speed(&mut self) -> bool {
+        let d8(Wrapping(key1)) = self.io.get(io_regs::KEY1);
+        if !self.is_cgb() || (key1 & 0x01) == 0 {
+            return false;
+        }
+        self.io.set(io_regs::KEY1, d8(Wrapping((key1 ^ 0x80) & 0x80)));
+        self.io.write(io_regs::DIV, d8::ZERO);
+        self.stall_cycles += SPEED_SWITCH_CYCLES;
+        true
+    }
+
+    fn tick_oam_dma(&mut self, cycles: u64) {
+        let mut budget = cycles;
+        while let Some((source, oam_idx)) = self.oam_dma.next_transfer(&mut budget) {
+            let byte = self.read_bus(a16(Wrapping(source))).unwrap_or(d8(Wrapping(0xff)));
+            self.object_attribute_memory[oam_idx] = byte;
+            self.oam_dma.set_last_byte(byte);
+        }
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    // picks how the PPU draws; meant for when the machine is built, as it
+    // starts the PPU over
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        let dmg_palette = self.ppu.dmg_palette();
+        self.ppu = Ppu::with_renderer(renderer);
+        self.ppu.set_dmg_palette(dmg_palette);
+    }
+
+    // the keys held on controller `player`, 0-3, as `joypad::A` and so on.
+    // Only a Super Game Boy reads past the first.
+    pub fn set_buttons(&mut self, player: usize, keys: u8) {
+        self.joypad.set_pressed(player, keys);
+    }
+
+    pub fn sgb(&self) -> &Sgb {
+        &self.sgb
+    }
+
+    // the Super Game Boy's whole 256x224 picture, border and all, from
+    // the last frame finished
+    pub fn sgb_screen(&self) -> Image {
+        self.sgb.render(self.ppu.framebuffer())
+    }
+
+    fn tick_ppu(&mut self, cycles: u64) {
+        let dots = if self.double_speed() {
+            let total = cycles + self.ppu_half_dot;
+            self.ppu_half_dot = total % 2;
+            total / 2
+        } else {
+            cycles
+        };
+        let frames = self.ppu.frame_count();
+        let hblanks = self.ppu.step(
+            dots,
+            &mut self.io,
+            &self.video_ram,
+            &self.object_attribute_memory,
+            &self.palettes,
+            self.model,
+        );
+        for _ in 0..hblanks {
+            self.enter_hblank();
+        }
+        if self.ppu.frame_count() != frames {
+            let video = VideoMemory {
+                model: self.model,
+                io: &self.io,
+                vram: &self.video_ram,
+                oam: &self.object_attribute_memory,
+                palettes: &self.palettes,
+            };
+            self.sgb.finish_frame(&video);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        self.io.tick(cycles);
+        self.tick_oam_dma(cycles);
+        self.tick_ppu(cycles);
+        self.cartridge.tick(cycles);
+        self.dispatch_cartridge_events();
+        let autosave = match self.save_file {
+            Some(ref mut save) => save.autosave_due(cycles),
+            None => false,
+        };
+        if autosave {
+            // there's nobody to report a failure to from here; the save
+            // stays dirty, so the next autosave or the final flush will
+            // try again
+            let _ = self.flush_save();
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.has_battery
+    }
+
+    // loads whatever's already in the save file, and writes back to it
+    // from then on
+    pub fn attach_save_file(&mut self, save: SaveFile) -> io::Result<()> {
+        if let Some(data) = save.read()? {
+            self.cartridge.load_save_data(&data);
+        }
+        self.save_file = Some(save);
+        self.save_dirty = false;
+        Ok(())
+    }
+
+    pub fn set_autosave_interval(&mut self, interval: Option<Duration>) {
+        if let Some(ref mut save) = self.save_file {
+            save.set_autosave_interval(interval);
+        }
+    }
+
+    // writes the save file, if there is one and the cart RAM has been
+    // written to since the last flush
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        if !self.save_dirty {
+            return Ok(());
+        }
+        if let Some(ref mut save) = self.save_file {
+            save.write(&self.cartridge.save_data())?;
+        }
+        self.save_dirty = false;
+        Ok(())
+    }
+
+    pub fn save_data(&self) -> Vec<u8> {
+        self.cartridge.save_data()
+    }
+
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.cartridge.load_save_data(data);
+    }
+
+    fn dispatch_cartridge_events(&mut self) {
+        for event in self.cartridge.take_events() {
+            if event == CartridgeEvent::SaveChanged {
+                self.save_dirty = true;
+            }
+            for listener in self.cartridge_listeners.iter_mut() {
+                listener(event);
+            }
+        }
+    }
+
     pub fn read_d16(&self, idx: a16) -> Option<d16> {
         Some([
             self.read_d8(idx).unwrap_or(d8::ZERO),
@@ -140,3 +865,9 @@ impl Memory {
         lsb.and(msb)
     }
 }
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        let _ = self.flush_save();
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+The MBC3-style real time clock, and the footer BGB, VBA and SameBoy
+append to `.sav` files after the RAM image to carry it between sessions.
+
+The footer is ten 32-bit little-endian values, only the low byte of each
+meaningful:
+
+0	seconds
+4	minutes
+8	hours
+12	days, low 8 bits
+16	days high: bit 0 - day bit 8, bit 6 - halt, bit 7 - day carry
+20-39	the same five registers, as last latched
+40	UNIX timestamp of when the file was written, 32-bit (44-byte
+	footer) or 64-bit (48-byte footer)
+
+We write the 48-byte form and accept either.
+*/
+pub const RTC_FOOTER_LEN: usize = 48;
+pub const RTC_FOOTER_LEN_SHORT: usize = 44;
+
+const HALT_BIT: u8 = 0x40;
+const CARRY_BIT: u8 = 0x80;
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+const MAX_DAYS: u64 = 512;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub days_low: u8,
+    pub days_high: u8,
+}
+
+impl RtcRegisters {
+    pub fn days(&self) -> u16 {
+        self.days_low as u16 | (((self.days_high & 1) as u16) << 8)
+    }
+
+    pub fn halted(&self) -> bool {
+        (self.days_high & HALT_BIT) != 0
+    }
+
+    fn as_array(&self) -> [u8; 5] {
+        [self.seconds, self.minutes, self.hours, self.days_low, self.days_high]
+    }
+
+    fn from_array(regs: [u8; 5]) -> Self {
+        RtcRegisters {
+            seconds: regs[0],
+            minutes: regs[1],
+            hours: regs[2],
+            days_low: regs[3],
+            days_high: regs[4],
+        }
+    }
+
+    // counts `elapsed` seconds forwards, setting the carry bit if the day
+    // counter overflows. A halted clock doesn't move.
+    pub fn advance(&mut self, elapsed: u64) {
+        if self.halted() || elapsed == 0 {
+            return;
+        }
+        let total = self.seconds as u64
+            + 60 * self.minutes as u64
+            + 60 * 60 * self.hours as u64
+            + SECONDS_PER_DAY * self.days() as u64
+            + elapsed;
+        let days = total / SECONDS_PER_DAY;
+        let in_day = total % SECONDS_PER_DAY;
+
+        self.seconds = (in_day % 60) as u8;
+        self.minutes = ((in_day / 60) % 60) as u8;
+        self.hours = (in_day / (60 * 60)) as u8;
+        self.days_low = days as u8;
+        self.days_high = (self.days_high & !1) | ((days >> 8) & 1) as u8;
+        if days >= MAX_DAYS {
+            self.days_high |= CARRY_BIT;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RtcFooter {
+    pub live: RtcRegisters,
+    pub latched: RtcRegisters,
+    pub timestamp: u64,
+}
+
+impl RtcFooter {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RTC_FOOTER_LEN);
+        for &reg in self.live.as_array().iter().chain(self.latched.as_array().iter()) {
+            bytes.extend(&[reg, 0, 0, 0]);
+        }
+        for i in 0..8 {
+            bytes.push((self.timestamp >> (8 * i)) as u8);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let timestamp_len = match bytes.len() {
+            RTC_FOOTER_LEN => 8,
+            RTC_FOOTER_LEN_SHORT => 4,
+            _ => return None,
+        };
+        let reg = |i: usize| bytes[4 * i];
+        let mut timestamp = 0u64;
+        for i in 0..timestamp_len {
+            timestamp |= (bytes[40 + i] as u64) << (8 * i);
+        }
+        Some(RtcFooter {
+            live: RtcRegisters::from_array([reg(0), reg(1), reg(2), reg(3), reg(4)]),
+            latched: RtcRegisters::from_array([reg(5), reg(6), reg(7), reg(8), reg(9)]),
+            timestamp,
+        })
+    }
+
+    // the live registers brought forward by however long it's been since
+    // the footer was written
+    pub fn catch_up(&mut self, now: u64) {
+        if now > self.timestamp {
+            self.live.advance(now - self.timestamp);
+        }
+        self.timestamp = now;
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// splits a `.sav` image into the RAM and an RTC footer, if it has one
+pub fn split_footer(data: &[u8], ram_len: usize) -> (&[u8], Option<RtcFooter>) {
+    if data.len() <= ram_len {
+        return (data, None);
+    }
+    let (ram, footer) = data.split_at(ram_len);
+    (ram, RtcFooter::from_bytes(footer))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn footer() -> RtcFooter {
+        RtcFooter {
+            live: RtcRegisters { seconds: 59, minutes: 59, hours: 23, days_low: 0xff, days_high: 1 },
+            latched: RtcRegisters { seconds: 1, minutes: 2, hours: 3, days_low: 4, days_high: 0 },
+            timestamp: 1_500_000_000,
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let bytes = footer().to_bytes();
+        assert_eq!(bytes.len(), RTC_FOOTER_LEN);
+        assert_eq!(RtcFooter::from_bytes(&bytes), Some(footer()));
+    }
+
+    #[test]
+    fn reads_short_footer() {
+        let bytes = footer().to_bytes();
+        let short = RtcFooter::from_bytes(&bytes[..RTC_FOOTER_LEN_SHORT]).unwrap();
+        assert_eq!(short, footer());
+    }
+
+    #[test]
+    fn catch_up_carries_days() {
+        let mut f = footer();
+        f.catch_up(f.timestamp + 1);
+        assert_eq!(f.live.seconds, 0);
+        assert_eq!(f.live.hours, 0);
+        assert_eq!(f.live.days(), 0);
+        assert_eq!(f.live.days_high & CARRY_BIT, CARRY_BIT);
+        // the latched copy is whatever the game last latched
+        assert_eq!(f.latched, footer().latched);
+    }
+
+    #[test]
+    fn halted_clock_stands_still() {
+        let mut f = footer();
+        f.live.days_high |= HALT_BIT;
+        f.catch_up(f.timestamp + 1000);
+        assert_eq!(f.live.seconds, 59);
+    }
+}
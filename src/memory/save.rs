@@ -0,0 +1,183 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/*
+Battery-backed cartridge state lives in a `.sav` file next to the ROM,
+in the same layout other emulators use: the raw cartridge RAM, every bank
+in order, followed by any clock state the cartridge has (see
+`Cartridge::save_data`). Clocks use the 44/48-byte footer in rtc.rs.
+
+Saves are written to a temporary file in the same directory, synced, and
+then renamed over the old save, so a crash part-way through leaves either
+the old save or the new one, never a truncated file.
+*/
+
+// T-cycles per second, for turning autosave intervals into emulated time
+pub const CLOCK_SPEED: u64 = 4_194_304;
+
+pub struct SaveFile {
+    path: PathBuf,
+    autosave_interval: Option<u64>,
+    cycles_since_flush: u64,
+}
+
+impl SaveFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        SaveFile {
+            path: path.into(),
+            autosave_interval: None,
+            cycles_since_flush: 0,
+        }
+    }
+
+    // `game.gb` saves to `game.sav`
+    pub fn next_to_rom<P: AsRef<Path>>(rom_path: P) -> Self {
+        Self::new(rom_path.as_ref().with_extension("sav"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // the interval is in emulated time, so a game running faster than
+    // real time also autosaves more often
+    pub fn set_autosave_interval(&mut self, interval: Option<Duration>) {
+        self.autosave_interval = interval.map(|d| {
+            d.as_secs() * CLOCK_SPEED + (d.subsec_nanos() as u64 * CLOCK_SPEED) / 1_000_000_000
+        });
+        self.cycles_since_flush = 0;
+    }
+
+    // advances the autosave timer, and says whether it's gone off. The
+    // timer starts again either way, so a failing disk isn't retried on
+    // every instruction.
+    pub fn autosave_due(&mut self, cycles: u64) -> bool {
+        match self.autosave_interval {
+            Some(interval) => {
+                self.cycles_since_flush += cycles;
+                if self.cycles_since_flush >= interval {
+                    self.cycles_since_flush = 0;
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
+        }
+    }
+
+    // `Ok(None)` if there's no save yet
+    pub fn read(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Some(data))
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut tmp_name = self.path.clone().into_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(data)?;
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        self.cycles_since_flush = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    fn scratch_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("rgb-save-test-{}-{}", name, ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn missing_save_reads_none() {
+        let save = SaveFile::new(scratch_path("missing"));
+        assert_eq!(save.read().unwrap(), None);
+    }
+
+    #[test]
+    fn write_then_read() {
+        let path = scratch_path("roundtrip");
+        let mut save = SaveFile::new(path.clone());
+        save.write(&[1, 2, 3]).unwrap();
+        save.write(&[4, 5]).unwrap();
+        assert_eq!(save.read().unwrap(), Some(vec![4, 5]));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn memory_only_flushes_when_dirty() {
+        use memory::Memory;
+        use memory::cartridge::Mbc5;
+        use number_types::d8_type::d8;
+        use number_types::a16_type::a16;
+        use std::num::Wrapping;
+
+        let path = scratch_path("dirty");
+        let mut memory = Memory::with_cartridge(Box::new(Mbc5::new(
+            vec![d8::ZERO; 0x8000],
+            0x2000,
+            false,
+        )));
+        memory.attach_save_file(SaveFile::new(path.clone())).unwrap();
+        memory.flush_save().unwrap();
+        assert!(!path.exists());
+
+        memory.put_d8(a16(Wrapping(0x0000)), d8(Wrapping(0x0a)));
+        memory.put_d8(a16(Wrapping(0xa001)), d8(Wrapping(0x42)));
+        memory.flush_save().unwrap();
+        let data = SaveFile::new(path.clone()).read().unwrap().unwrap();
+        assert_eq!(data.len(), 0x2000);
+        assert_eq!(data[1], 0x42);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn camera_photos_dirty_the_save() {
+        use memory::Memory;
+        use memory::cartridge::Camera;
+        use number_types::d8_type::d8;
+        use number_types::a16_type::a16;
+        use std::num::Wrapping;
+
+        let path = scratch_path("camera");
+        let mut memory = Memory::with_cartridge(Box::new(Camera::new(vec![d8::ZERO; 0x8000])));
+        memory.attach_save_file(SaveFile::new(path.clone())).unwrap();
+        // start a capture through the camera's registers
+        memory.put_d8(a16(Wrapping(0x4000)), d8(Wrapping(0x10)));
+        memory.put_d8(a16(Wrapping(0xa000)), d8(Wrapping(0x01)));
+        memory.flush_save().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        memory.tick(CLOCK_SPEED / 8);
+        memory.flush_save().unwrap();
+        assert!(path.exists());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn autosave_interval_in_emulated_time() {
+        let mut save = SaveFile::new(scratch_path("interval"));
+        assert!(!save.autosave_due(CLOCK_SPEED * 100));
+        save.set_autosave_interval(Some(Duration::from_millis(500)));
+        assert!(!save.autosave_due(CLOCK_SPEED / 4));
+        assert!(save.autosave_due(CLOCK_SPEED / 4));
+    }
+}
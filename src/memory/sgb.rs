@@ -0,0 +1,526 @@
+use std::cmp;
+use std::mem;
+use ppu::{VideoMemory, Framebuffer, SCREEN_WIDTH, SCREEN_HEIGHT, LCDC_BG_MAP};
+use ppu::tiles;
+use ppu::vram_view::Image;
+use memory::io_regs;
+
+/*
+The Super Game Boy: a DMG on a SNES cartridge, which puts the Game Boy's
+screen in the middle of a 256x224 picture, with a border around it and
+colour on it.
+
+The game talks to it through the joypad port, in 16-byte packets, a bit at
+a time, least significant bit of each byte first:
+
+P1 written	means
+$00	reset pulse, starting a packet
+$20	a 0 bit (P14 low)
+$10	a 1 bit (P15 low)
+$30	between pulses
+
+After the 128 bits comes a 0 stop bit. The first byte of a command's first
+packet is the command code times 8, plus how many packets it takes (1-7).
+The SGB only listens to carts with $03 at $0146 and old licensee $33 at
+$014B.
+
+The game screen is split into 20x18 cells of 8x8, each coloured by one of
+four palettes of four RGB555 colours. Colour 0 is shared by all four.
+
+Commands:
+$00-$03	PAL01, PAL23, PAL03, PAL12 - colour 0, then colours 1-3 of two
+	palettes
+$04	ATTR_BLK - palettes inside, on and outside the edges of rectangles
+$05	ATTR_LIN - palettes for whole rows or columns of cells
+$06	ATTR_DIV - palettes either side of, and on, a row or column
+$07	ATTR_CHR - palettes cell by cell, 4 to a byte, high bits first
+$11	MLT_REQ - 1, 2 or 4 controllers
+$13	CHR_TRN - border tiles $00-$7F or $80-$FF
+$14	PCT_TRN - the border's map and palettes
+$17	MASK_EN - freeze the screen, black it out, or fill it with colour 0
+
+CHR_TRN and PCT_TRN copy 4KiB out of what the Game Boy is showing: the
+first 256 tiles on screen, row by row, as the LCD shows them at the end of
+the next frame. Border tiles are SNES 4-bit tiles, 32 bytes each; the
+border map is 32x32 entries of 2 bytes:
+
+bits 0-7	tile
+bits 10-12	palette, 4-7
+bit 14	X flip
+bit 15	Y flip
+
+followed by palettes 4-7, 16 colours each. Border colour 0 is see-through.
+
+With more than one controller, P1 reads $F minus the controller number
+when neither row is selected, and each time P15 goes back high, the next
+controller is wired through.
+*/
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+// where the game screen sits in the picture
+pub const GAME_LEFT: usize = 48;
+pub const GAME_TOP: usize = 40;
+pub const ATTR_COLUMNS: usize = SCREEN_WIDTH / 8;
+pub const ATTR_ROWS: usize = SCREEN_HEIGHT / 8;
+
+pub const SGB_FLAG_ADDR: usize = 0x0146;
+pub const OLD_LICENSEE_ADDR: usize = 0x014b;
+
+const PACKET_BYTES: usize = 16;
+const PACKET_BITS: usize = PACKET_BYTES * 8;
+const TRANSFER_BYTES: usize = 0x1000;
+const BORDER_MAP_BYTES: usize = 0x800;
+const BORDER_TILE_BYTES: usize = 32;
+
+const RESET_PULSE: u8 = 0x00;
+const ONE_PULSE: u8 = 0x10;
+const IDLE: u8 = 0x30;
+const P15: u8 = 0x20;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+// what the SGB starts with, before the game sends any palettes
+const DEFAULT_PALETTE: [u16; 4] = [0x67bf, 0x265b, 0x10b5, 0x2866];
+
+// whether a cart's header asks for SGB functions
+pub fn cart_supports_sgb(sgb_flag: u8, old_licensee: u8) -> bool {
+    sgb_flag == 0x03 && old_licensee == 0x33
+}
+
+// what MASK_EN puts over the game screen
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mask {
+    None,
+    // keeps showing the frame from when it was set
+    Freeze,
+    Black,
+    Color0,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Transfer {
+    // which half of the 256 border tiles
+    Tiles(usize),
+    Border,
+}
+
+pub struct Sgb {
+    // whether the cart asked for SGB functions
+    enabled: bool,
+    receiving: bool,
+    bits: usize,
+    packet: [u8; PACKET_BYTES],
+    // P1 bits 4-5 as last written
+    last_select: u8,
+    // the packets of the command coming in
+    command: Vec<u8>,
+    palettes: [[u16; 4]; 4],
+    attributes: [u8; ATTR_COLUMNS * ATTR_ROWS],
+    mask: Mask,
+    frozen: Option<Framebuffer>,
+    players: usize,
+    player: usize,
+    // waiting for the end of the frame
+    transfer: Option<Transfer>,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u8>,
+    border_palettes: [[u16; 16]; 4],
+}
+
+fn color(data: &[u8], idx: usize) -> u16 {
+    (u16::from(data[idx + 1]) << 8 | u16::from(data[idx])) & 0x7fff
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Sgb {
+            enabled: false,
+            receiving: false,
+            bits: 0,
+            packet: [0; PACKET_BYTES],
+            last_select: IDLE,
+            command: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: [0; ATTR_COLUMNS * ATTR_ROWS],
+            mask: Mask::None,
+            frozen: None,
+            players: 1,
+            player: 0,
+            transfer: None,
+            border_tiles: vec![0; 2 * TRANSFER_BYTES],
+            border_map: vec![0; BORDER_MAP_BYTES],
+            border_palettes: [[0; 16]; 4],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn players(&self) -> usize {
+        self.players
+    }
+
+    // the controller P1 reads, 0-3
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    pub fn palette(&self, palette: usize) -> [u16; 4] {
+        self.palettes[palette]
+    }
+
+    // the palette of the 8x8 cell at column `x`, row `y`
+    pub fn attribute(&self, x: usize, y: usize) -> usize {
+        self.attributes[y * ATTR_COLUMNS + x] as usize
+    }
+
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+
+    // a write to P1; `frame` is the last one finished, for MASK_EN to
+    // freeze
+    pub fn write_joypad(&mut self, p1: u8, frame: &Framebuffer) {
+        if !self.enabled {
+            return;
+        }
+        let select = p1 & IDLE;
+        let last = mem::replace(&mut self.last_select, select);
+        if select == last {
+            return;
+        }
+        match select {
+            RESET_PULSE => {
+                self.receiving = true;
+                self.bits = 0;
+                self.packet = [0; PACKET_BYTES];
+            }
+            // P15 going back high wires the next controller through
+            IDLE if (last & P15) == 0 && !self.receiving && self.players > 1 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            IDLE => (),
+            _ if self.receiving && last == IDLE => self.receive_bit(select == ONE_PULSE, frame),
+            _ => (),
+        }
+    }
+
+    fn receive_bit(&mut self, one: bool, frame: &Framebuffer) {
+        if self.bits == PACKET_BITS {
+            // the stop bit, which has to be 0
+            self.receiving = false;
+            if !one {
+                let packet = self.packet;
+                self.receive_packet(&packet, frame);
+            }
+            return;
+        }
+        if one {
+            self.packet[self.bits / 8] |= 1 << (self.bits % 8);
+        }
+        self.bits += 1;
+    }
+
+    fn receive_packet(&mut self, packet: &[u8], frame: &Framebuffer) {
+        self.command.extend_from_slice(packet);
+        let packets = cmp::max(self.command[0] & 0x07, 1) as usize;
+        if self.command.len() >= packets * PACKET_BYTES {
+            let command = mem::replace(&mut self.command, Vec::new());
+            self.execute(&command, frame);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8], frame: &Framebuffer) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            CHR_TRN => self.transfer = Some(Transfer::Tiles((data[1] & 1) as usize)),
+            PCT_TRN => self.transfer = Some(Transfer::Border),
+            MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    0 => Mask::None,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                };
+                self.frozen = match self.mask {
+                    Mask::Freeze => Some(frame.clone()),
+                    _ => None,
+                };
+            }
+            // the rest are for sound, the SNES and the boot ROM
+            _ => (),
+        }
+    }
+
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color_0 = color(data, 1);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color_0;
+        }
+        for i in 0..3 {
+            self.palettes[first][i + 1] = color(data, 3 + 2 * i);
+            self.palettes[second][i + 1] = color(data, 9 + 2 * i);
+        }
+    }
+
+    fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        self.attributes[y * ATTR_COLUMNS + x] = palette & 0x03;
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for set in data[2..].chunks(6).take(count) {
+            if set.len() < 6 {
+                break;
+            }
+            let inside = set[1] & 0x03;
+            let outside = (set[1] >> 4) & 0x03;
+            // changing just the inside or just the outside takes the edge
+            // along with it
+            let (control, edge) = match set[0] & 0x07 {
+                1 => (0x03, inside),
+                4 => (0x06, outside),
+                control => (control, (set[1] >> 2) & 0x03),
+            };
+            let (x1, y1) = ((set[2] & 0x1f) as usize, (set[3] & 0x1f) as usize);
+            let (x2, y2) = ((set[4] & 0x1f) as usize, (set[5] & 0x1f) as usize);
+            for y in 0..ATTR_ROWS {
+                for x in 0..ATTR_COLUMNS {
+                    let (bit, palette) = if x > x1 && x < x2 && y > y1 && y < y2 {
+                        (0x01, inside)
+                    } else if x < x1 || x > x2 || y < y1 || y > y2 {
+                        (0x04, outside)
+                    } else {
+                        (0x02, edge)
+                    };
+                    if (control & bit) != 0 {
+                        self.set_attribute(x, y, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &line in data[2..].iter().take(count) {
+            let at = (line & 0x1f) as usize;
+            let palette = (line >> 5) & 0x03;
+            if (line & 0x80) != 0 {
+                if at < ATTR_ROWS {
+                    for x in 0..ATTR_COLUMNS {
+                        self.set_attribute(x, at, palette);
+                    }
+                }
+            } else if at < ATTR_COLUMNS {
+                for y in 0..ATTR_ROWS {
+                    self.set_attribute(at, y, palette);
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0x03;
+        let before = (data[1] >> 2) & 0x03;
+        let on = (data[1] >> 4) & 0x03;
+        let rows = (data[1] & 0x40) != 0;
+        let at = (data[2] & 0x1f) as usize;
+        for y in 0..ATTR_ROWS {
+            for x in 0..ATTR_COLUMNS {
+                let pos = if rows { y } else { x };
+                let palette = match pos.cmp(&at) {
+                    cmp::Ordering::Less => before,
+                    cmp::Ordering::Equal => on,
+                    cmp::Ordering::Greater => after,
+                };
+                self.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = data[3] as usize | (data[4] as usize) << 8;
+        let down = (data[5] & 1) != 0;
+        let palettes = data[6..].iter().flat_map(|&byte| (0..4).map(move |i| byte >> (6 - 2 * i)));
+        for palette in palettes.take(count) {
+            if x >= ATTR_COLUMNS || y >= ATTR_ROWS {
+                break;
+            }
+            self.set_attribute(x, y, palette);
+            if down {
+                y += 1;
+                if y == ATTR_ROWS {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == ATTR_COLUMNS {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    // called at the end of each frame, which is when CHR_TRN and PCT_TRN
+    // read the screen
+    pub fn finish_frame(&mut self, video: &VideoMemory) {
+        let transfer = match self.transfer.take() {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        let data = transfer_data(video);
+        match transfer {
+            Transfer::Tiles(half) => {
+                self.border_tiles[half * TRANSFER_BYTES..(half + 1) * TRANSFER_BYTES]
+                    .copy_from_slice(&data);
+            }
+            Transfer::Border => {
+                self.border_map.copy_from_slice(&data[..BORDER_MAP_BYTES]);
+                for (palette, colors) in self.border_palettes.iter_mut().enumerate() {
+                    for (i, out) in colors.iter_mut().enumerate() {
+                        *out = color(&data, BORDER_MAP_BYTES + 32 * palette + 2 * i);
+                    }
+                }
+            }
+        }
+    }
+
+    // the whole picture: the border, and the game screen coloured in the
+    // middle of it
+    pub fn render(&self, frame: &Framebuffer) -> Image {
+        let mut image = Image::new(SGB_WIDTH, SGB_HEIGHT);
+        let backdrop = self.palettes[0][0];
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                image.set(x, y, self.border_pixel(x, y).unwrap_or(backdrop));
+            }
+        }
+        let frame = match self.mask {
+            Mask::Freeze => self.frozen.as_ref().unwrap_or(frame),
+            _ => frame,
+        };
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.mask {
+                    Mask::Black => 0,
+                    Mask::Color0 => backdrop,
+                    Mask::None | Mask::Freeze => {
+                        self.palettes[self.attribute(x / 8, y / 8)][(frame.shade(x, y) & 0x03) as usize]
+                    }
+                };
+                image.set(GAME_LEFT + x, GAME_TOP + y, color);
+            }
+        }
+        image
+    }
+
+    fn border_pixel(&self, x: usize, y: usize) -> Option<u16> {
+        let entry = 2 * ((y / 8) * 32 + x / 8);
+        let tile = self.border_map[entry] as usize;
+        let flags = self.border_map[entry + 1];
+        let column = if (flags & 0x40) != 0 { 7 - x % 8 } else { x % 8 };
+        let row = if (flags & 0x80) != 0 { 7 - y % 8 } else { y % 8 };
+        let data = &self.border_tiles[tile * BORDER_TILE_BYTES..(tile + 1) * BORDER_TILE_BYTES];
+        let plane = |idx: usize| (data[idx] >> (7 - column)) & 1;
+        let index = plane(2 * row)
+            | plane(2 * row + 1) << 1
+            | plane(16 + 2 * row) << 2
+            | plane(17 + 2 * row) << 3;
+        if index == 0 {
+            return None;
+        }
+        // palettes 4-7
+        let palette = ((flags >> 2) & 0x03) as usize;
+        Some(self.border_palettes[palette][index as usize])
+    }
+}
+
+// the first 256 tiles on screen, left to right and top to bottom
+fn transfer_data(video: &VideoMemory) -> Vec<u8> {
+    let lcdc = video.lcdc();
+    let map = if (lcdc & LCDC_BG_MAP) != 0 { 0x1c00 } else { 0x1800 };
+    let left = video.reg(io_regs::SCX) as usize / 8;
+    let top = video.reg(io_regs::SCY) as usize / 8;
+    (0..TRANSFER_BYTES / tiles::TILE_BYTES)
+        .flat_map(|i| {
+            let column = (left + i % ATTR_COLUMNS) % 32;
+            let row = (top + i / ATTR_COLUMNS) % 32;
+            let tile = video.vram_byte(0, map + row * 32 + column);
+            let offset = tiles::bg_tile_offset(lcdc, tile);
+            (0..tiles::TILE_BYTES).map(move |b| video.vram_byte(0, offset + b))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn command(bytes: &[u8]) -> Vec<u8> {
+        let mut data = bytes.to_vec();
+        data.resize(PACKET_BYTES, 0);
+        data
+    }
+
+    #[test]
+    fn attribute_blocks() {
+        let mut sgb = Sgb::new();
+        let frame = Framebuffer::new();
+        // inside 1, edge 2, outside 3, on the rectangle (2, 2)-(5, 4)
+        sgb.execute(&command(&[ATTR_BLK << 3 | 1, 1, 0x07, 0x39, 2, 2, 5, 4]), &frame);
+        assert_eq!(sgb.attribute(3, 3), 1);
+        assert_eq!(sgb.attribute(2, 3), 2);
+        assert_eq!(sgb.attribute(5, 4), 2);
+        assert_eq!(sgb.attribute(6, 3), 3);
+
+        // just the inside takes the edge with it
+        sgb.execute(&command(&[ATTR_BLK << 3 | 1, 1, 0x01, 0x00, 2, 2, 5, 4]), &frame);
+        assert_eq!(sgb.attribute(2, 3), 0);
+        assert_eq!(sgb.attribute(6, 3), 3);
+
+        // palette 2 left of column 4, 1 on it, 0 right of it
+        sgb.execute(&command(&[ATTR_DIV << 3 | 1, 0x18, 4]), &frame);
+        assert_eq!((sgb.attribute(3, 0), sgb.attribute(4, 17), sgb.attribute(5, 9)), (2, 1, 0));
+
+        // row 3 to palette 3, then cells on from (19, 16), wrapping
+        sgb.execute(&command(&[ATTR_LIN << 3 | 1, 1, 0x80 | 0x60 | 3]), &frame);
+        assert_eq!(sgb.attribute(10, 3), 3);
+        sgb.execute(&command(&[ATTR_CHR << 3 | 1, 19, 16, 3, 0, 0, 0b01_10_11_00]), &frame);
+        assert_eq!((sgb.attribute(19, 16), sgb.attribute(0, 17), sgb.attribute(1, 17)), (1, 2, 3));
+    }
+}
@@ -0,0 +1,492 @@
+use super::*;
+use super::io_regs;
+
+fn addr(idx: u16) -> a16 {
+    a16(Wrapping(idx))
+}
+
+fn byte(val: u8) -> d8 {
+    d8(Wrapping(val))
+}
+
+#[test]
+fn echo_ram_mirrors_work_ram() {
+    let mut memory = Memory::new_zeros();
+    memory.put_d8(addr(0xc123), byte(0x12));
+    assert_eq!(memory.read_d8(addr(0xe123)), Some(byte(0x12)));
+    memory.put_d8(addr(0xfdff), byte(0x34));
+    assert_eq!(memory.read_d8(addr(0xddff)), Some(byte(0x34)));
+}
+
+#[test]
+fn high_ram_is_storage() {
+    let mut memory = Memory::new_zeros();
+    memory.put_d16(addr(0xfffc), d16(Wrapping(0xbeef)));
+    assert_eq!(memory.read_d16(addr(0xfffc)), Some(d16(Wrapping(0xbeef))));
+}
+
+#[test]
+fn unusable_region_depends_on_model() {
+    let mut memory = Memory::new_zeros();
+    memory.put_d8(addr(0xfeb4), byte(0x12));
+    assert_eq!(memory.read_d8(addr(0xfeb4)), Some(byte(0x00)));
+    memory.set_model(CpuMode::CGB);
+    assert_eq!(memory.read_d8(addr(0xfeb4)), Some(byte(0xbb)));
+}
+
+#[test]
+fn missing_cartridge_ram_reads_ff() {
+    let memory = Memory::new_zeros();
+    assert_eq!(memory.read_d8(addr(0xa000)), Some(byte(0xff)));
+}
+
+#[test]
+fn cgb_vram_banks() {
+    let mut memory = Memory::new_zeros();
+    memory.set_model(CpuMode::CGB);
+    memory.put_d8(addr(0x9fff), byte(0x11));
+    memory.put_d8(addr(0xff4f), byte(0x01));
+    assert_eq!(memory.read_d8(addr(0x9fff)), Some(byte(0x00)));
+    memory.put_d8(addr(0x9fff), byte(0x22));
+    assert_eq!(memory.vram_bank(0).get(0x1fff), byte(0x11));
+    assert_eq!(memory.vram_bank(1).get(0x1fff), byte(0x22));
+}
+
+#[test]
+fn cgb_wram_banks() {
+    let mut memory = Memory::new_zeros();
+    memory.set_model(CpuMode::CGB);
+    for bank in 0..8 {
+        memory.put_d8(addr(0xff70), byte(bank));
+        memory.put_d8(addr(0xd000), byte(0x40 + bank));
+    }
+    // bank 0 maps bank 1, so bank 1 holds what was written second
+    memory.put_d8(addr(0xff70), byte(0x01));
+    assert_eq!(memory.read_d8(addr(0xd000)), Some(byte(0x41)));
+    memory.put_d8(addr(0xff70), byte(0x07));
+    assert_eq!(memory.read_d8(addr(0xd000)), Some(byte(0x47)));
+    // and DMG only ever sees bank 1
+    memory.set_model(CpuMode::DMG);
+    assert_eq!(memory.read_d8(addr(0xd000)), Some(byte(0x41)));
+}
+
+fn fill_dma_source(memory: &mut Memory, base: u16) {
+    for i in 0..0xa0 {
+        memory.put_d8(addr(base + i), byte(i as u8 + 1));
+    }
+}
+
+#[test]
+fn oam_dma_copies_over_640_cycles() {
+    let mut memory = Memory::new_zeros();
+    fill_dma_source(&mut memory, 0xc100);
+    memory.put_d8(addr(0xff80), byte(0x99));
+    memory.put_d8(addr(0xff46), byte(0xc1));
+
+    // startup, then the first two bytes
+    memory.tick(4 + 8);
+    assert_eq!(memory.read_d8(addr(0xc000)), Some(byte(0x02)));
+    assert_eq!(memory.read_d8(addr(0xff80)), Some(byte(0x99)));
+    assert_eq!(memory.put_d8(addr(0xc000), byte(0x55)), None);
+
+    memory.tick(640 - 8);
+    assert_eq!(memory.read_d8(addr(0xfe00)), Some(byte(0x01)));
+    assert_eq!(memory.read_d8(addr(0xfe9f)), Some(byte(0xa0)));
+    assert_eq!(memory.read_d8(addr(0xc000)), Some(byte(0x00)));
+}
+
+#[test]
+fn oam_dma_restart() {
+    let mut memory = Memory::new_zeros();
+    fill_dma_source(&mut memory, 0xc000);
+    for i in 0..0xa0 {
+        memory.put_d8(addr(0xd000 + i), byte(0x80));
+    }
+    memory.put_d8(addr(0xff46), byte(0xc0));
+    memory.tick(4 + 40);
+    memory.put_d8(addr(0xff46), byte(0xd0));
+    // the bus stays blocked through the new transfer's startup
+    memory.tick(2);
+    assert_eq!(memory.read_d8(addr(0xc000)), Some(byte(0x0a)));
+    memory.tick(2 + 640);
+    assert_eq!(memory.read_d8(addr(0xfe00)), Some(byte(0x80)));
+    assert_eq!(memory.read_d8(addr(0xfe9f)), Some(byte(0x80)));
+}
+
+#[test]
+fn oam_dma_restart_keeps_copying() {
+    let mut memory = Memory::new_zeros();
+    fill_dma_source(&mut memory, 0xc000);
+    for i in 0..0xa0 {
+        memory.put_d8(addr(0xd000 + i), byte(0x80));
+    }
+    memory.put_d8(addr(0xff46), byte(0xc0));
+    memory.tick(4 + 40);
+    memory.put_d8(addr(0xff46), byte(0xd0));
+    // the old transfer copies one more byte during the new one's startup
+    memory.tick(4);
+    assert_eq!(memory.object_attribute_memory[10], byte(0x0b));
+    assert_eq!(memory.read_d8(addr(0xc000)), Some(byte(0x0b)));
+    memory.tick(4);
+    assert_eq!(memory.object_attribute_memory[0], byte(0x80));
+    assert_eq!(memory.object_attribute_memory[11], byte(0x00));
+}
+
+fn cgb_with_dma_source() -> Memory {
+    let mut memory = Memory::new_zeros();
+    memory.set_model(CpuMode::CGB);
+    memory.put_d8(addr(0xff40), byte(0x80));
+    for i in 0..0x40 {
+        memory.put_d8(addr(0xc000 + i), byte(i as u8 + 1));
+    }
+    // source $C00F masks to $C000, destination $E105 masks to $8100
+    memory.put_d8(addr(0xff51), byte(0xc0));
+    memory.put_d8(addr(0xff52), byte(0x0f));
+    memory.put_d8(addr(0xff53), byte(0xe1));
+    memory.put_d8(addr(0xff54), byte(0x05));
+    memory
+}
+
+#[test]
+fn general_hdma() {
+    let mut memory = cgb_with_dma_source();
+    memory.put_d8(addr(0xff55), byte(0x02));
+    assert_eq!(memory.read_d8(addr(0x8100)), Some(byte(0x01)));
+    assert_eq!(memory.read_d8(addr(0x812f)), Some(byte(0x30)));
+    assert_eq!(memory.read_d8(addr(0x8130)), Some(byte(0x00)));
+    assert_eq!(memory.read_d8(addr(0xff55)), Some(byte(0xff)));
+    assert_eq!(memory.take_stall_cycles(), 3 * 32);
+
+    memory.io_mut().set(io_regs::KEY1, byte(0x80));
+    memory.put_d8(addr(0xff55), byte(0x00));
+    assert_eq!(memory.take_stall_cycles(), 64);
+}
+
+#[test]
+fn speed_switch_needs_key1_armed() {
+    let mut memory = Memory::new_zeros();
+    memory.set_model(CpuMode::CGB);
+    assert!(!memory.switch_speed());
+    memory.put_d8(addr(0xff4d), byte(0x01));
+    memory.put_d8(addr(0xff04), byte(0x00));
+    memory.tick(0x300);
+    assert!(memory.switch_speed());
+    assert!(memory.double_speed());
+    assert_eq!(memory.read_d8(addr(0xff4d)), Some(byte(0xfe)));
+    assert_eq!(memory.read_d8(addr(0xff04)), Some(byte(0x00)));
+    assert_eq!(memory.take_stall_cycles(), 8200);
+    // disarmed until bit 0 is set again
+    assert!(!memory.switch_speed());
+
+    memory.put_d8(addr(0xff4d), byte(0x01));
+    assert!(memory.switch_speed());
+    assert!(!memory.double_speed());
+    assert_eq!(memory.read_d8(addr(0xff4d)), Some(byte(0x7e)));
+
+    memory.set_model(CpuMode::DMG);
+    memory.put_d8(addr(0xff4d), byte(0x01));
+    assert!(!memory.switch_speed());
+}
+
+#[test]
+fn hblank_hdma_and_cancel() {
+    let mut memory = cgb_with_dma_source();
+    memory.put_d8(addr(0xff55), byte(0x83));
+    assert_eq!(memory.read_d8(addr(0xff55)), Some(byte(0x03)));
+    assert_eq!(memory.read_d8(addr(0x8100)), Some(byte(0x00)));
+
+    memory.enter_hblank();
+    assert_eq!(memory.read_d8(addr(0x810f)), Some(byte(0x10)));
+    assert_eq!(memory.read_d8(addr(0x8110)), Some(byte(0x00)));
+    assert_eq!(memory.read_d8(addr(0xff55)), Some(byte(0x02)));
+
+    memory.put_d8(addr(0xff55), byte(0x00));
+    assert_eq!(memory.read_d8(addr(0xff55)), Some(byte(0x82)));
+    memory.enter_hblank();
+    assert_eq!(memory.read_d8(addr(0x8110)), Some(byte(0x00)));
+}
+
+#[test]
+fn write_watch_sees_old_and_new_values() {
+    use super::watch::{WatchKind, WatchAction};
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    let mut memory = Memory::new_zeros();
+    memory.put_d8(addr(0xc010), byte(0x11));
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    let id = memory.add_watch(WatchKind::Write, addr(0xc000), addr(0xc0ff), move |event| {
+        let a16(Wrapping(pc)) = event.pc;
+        log.borrow_mut().push((pc, event.old_value, event.new_value));
+        WatchAction::Halt
+    });
+
+    memory.begin_instruction(addr(0x0150));
+    memory.put_d8(addr(0xc100), byte(0x33));
+    assert!(!memory.take_watch_halt());
+    // echo RAM is watched along with the work RAM it mirrors
+    memory.put_d8(addr(0xe010), byte(0x22));
+    memory.put_d8(addr(0xc010), byte(0x44));
+    assert!(memory.take_watch_halt());
+    assert!(!memory.take_watch_halt());
+    assert_eq!(
+        *seen.borrow(),
+        vec![(0x0150, byte(0x11), byte(0x22)), (0x0150, byte(0x22), byte(0x44))]
+    );
+
+    assert!(memory.remove_watch(id));
+    memory.put_d8(addr(0xc010), byte(0x55));
+    assert_eq!(seen.borrow().len(), 2);
+}
+
+#[test]
+fn watch_on_echo_ram_sees_work_ram() {
+    use super::watch::{WatchKind, WatchAction};
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    let mut memory = Memory::new_zeros();
+    let hits = Rc::new(Cell::new(0));
+    let count = hits.clone();
+    memory.add_watch(WatchKind::Write, addr(0xe010), addr(0xe010), move |_| {
+        count.set(count.get() + 1);
+        WatchAction::Continue
+    });
+    memory.put_d8(addr(0xc010), byte(0x01));
+    memory.put_d8(addr(0xe010), byte(0x02));
+    memory.put_d8(addr(0xc011), byte(0x03));
+    assert_eq!(hits.get(), 2);
+}
+
+#[test]
+fn read_and_execute_watches() {
+    use super::watch::{WatchKind, WatchAction};
+    use std::rc::Rc;
+    use std::cell::Cell;
+
+    let mut memory = Memory::new_zeros();
+    let reads = Rc::new(Cell::new(0));
+    let executes = Rc::new(Cell::new(0));
+    let (r, e) = (reads.clone(), executes.clone());
+    memory.add_watch(WatchKind::Read, addr(0xff80), addr(0xff80), move |_| {
+        r.set(r.get() + 1);
+        WatchAction::Continue
+    });
+    memory.add_watch(WatchKind::Execute, addr(0x0100), addr(0x0100), move |_| {
+        e.set(e.get() + 1);
+        WatchAction::Continue
+    });
+    memory.read_d8(addr(0xff80));
+    memory.read_d8(addr(0xff81));
+    memory.begin_instruction(addr(0x0100));
+    memory.begin_instruction(addr(0x0101));
+    assert_eq!(reads.get(), 1);
+    assert_eq!(executes.get(), 1);
+    assert!(!memory.take_watch_halt());
+}
+
+#[test]
+fn ppu_mode_locks_vram_and_oam() {
+    let mut memory = Memory::new_zeros();
+    memory.put_d8(addr(0x8000), byte(0x12));
+    memory.put_d8(addr(0xfe00), byte(0x34));
+    memory.put_d8(addr(0xff40), byte(0x80));
+
+    memory.io_mut().set(io_regs::STAT, byte(0x02));
+    assert_eq!(memory.read_d8(addr(0x8000)), Some(byte(0x12)));
+    assert_eq!(memory.read_d8(addr(0xfe00)), Some(byte(0xff)));
+
+    memory.io_mut().set(io_regs::STAT, byte(0x03));
+    assert_eq!(memory.read_d8(addr(0x8000)), Some(byte(0xff)));
+    assert_eq!(memory.put_d8(addr(0x8000), byte(0x56)), None);
+
+    memory.set_ppu_lock_bypass(true);
+    assert_eq!(memory.read_d8(addr(0x8000)), Some(byte(0x12)));
+    memory.set_ppu_lock_bypass(false);
+
+    // nothing's locked with the LCD off
+    memory.put_d8(addr(0xff40), byte(0x00));
+    assert_eq!(memory.read_d8(addr(0xfe00)), Some(byte(0x34)));
+}
+
+#[test]
+fn regions_report_current_banks() {
+    use number_types::banked_address::MemoryRegion;
+    use memory::cartridge::Mbc5;
+
+    let mut memory = Memory::with_cartridge(Box::new(Mbc5::new(
+        vec![d8::ZERO; 8 * 0x4000],
+        4 * 0x2000,
+        false,
+    )));
+    memory.put_d8(addr(0x2000), byte(0x03));
+    memory.put_d8(addr(0x4000), byte(0x02));
+
+    let regions = memory.regions().collect::<Vec<_>>();
+    assert_eq!(regions.len(), 12);
+    let romx = regions.iter().find(|r| r.region == MemoryRegion::RomBankN).unwrap();
+    assert_eq!((romx.name, romx.range, romx.bank, romx.writable), ("ROMX", (0x4000, 0x7fff), 3, false));
+    let sram = regions.iter().find(|r| r.region == MemoryRegion::CartridgeRam).unwrap();
+    assert_eq!((sram.bank, sram.writable), (2, false));
+
+    assert_eq!(memory.banked_address(addr(0x4a2f)).to_string(), "03:4A2F");
+    assert_eq!(memory.banked_address(addr(0xd000)).to_string(), "01:D000");
+}
+
+fn sgb_memory() -> Memory {
+    let mut rom = vec![d8::ZERO; 0x8000];
+    rom[sgb::SGB_FLAG_ADDR] = byte(0x03);
+    rom[sgb::OLD_LICENSEE_ADDR] = byte(0x33);
+    let mut memory = Memory::with_cartridge(Box::new(RomOnly::new(rom)));
+    memory.set_model(CpuMode::SGB);
+    memory
+}
+
+fn send_packet(memory: &mut Memory, packet: &[u8]) {
+    let mut bytes = packet.to_vec();
+    bytes.resize(16, 0);
+    memory.put_d8(addr(0xff00), byte(0x00));
+    memory.put_d8(addr(0xff00), byte(0x30));
+    for bit in (0..128).map(|i| (bytes[i / 8] >> (i % 8)) & 1).chain(Some(0)) {
+        memory.put_d8(addr(0xff00), byte(if bit == 1 { 0x10 } else { 0x20 }));
+        memory.put_d8(addr(0xff00), byte(0x30));
+    }
+}
+
+fn read_p1(memory: &mut Memory, select: u8) -> u8 {
+    memory.put_d8(addr(0xff00), byte(select));
+    let d8(Wrapping(p1)) = memory.read_d8(addr(0xff00)).unwrap();
+    p1
+}
+
+#[test]
+fn joypad_matrix() {
+    let mut memory = Memory::new_zeros();
+    memory.set_buttons(0, joypad::A | joypad::DOWN);
+    assert_eq!(read_p1(&mut memory, 0x20), 0xe7);
+    assert_eq!(read_p1(&mut memory, 0x10), 0xde);
+    assert_eq!(read_p1(&mut memory, 0x30), 0xff);
+}
+
+#[test]
+fn sgb_palettes_and_multiplayer() {
+    let mut memory = sgb_memory();
+    // PAL01: colour 0 red, palette 0 green, palette 1 blue
+    send_packet(&mut memory, &[
+        0x01, 0x1f, 0x00, 0xe0, 0x03, 0xe0, 0x03, 0xe0, 0x03, 0x00, 0x7c, 0x00, 0x7c, 0x00, 0x7c,
+    ]);
+    assert_eq!(memory.sgb().palette(0), [0x001f, 0x03e0, 0x03e0, 0x03e0]);
+    assert_eq!(memory.sgb().palette(1), [0x001f, 0x7c00, 0x7c00, 0x7c00]);
+    assert_eq!(memory.sgb().palette(3)[0], 0x001f);
+
+    // ATTR_DIV: everything right of column 0 on palette 1, and a screen
+    // all in colour 3
+    send_packet(&mut memory, &[0x31, 0x01, 0x00]);
+    memory.put_d8(addr(0xff40), byte(0x91));
+    memory.put_d8(addr(0xff47), byte(0xff));
+    for _ in 0..154 {
+        memory.tick(456);
+    }
+    let screen = memory.sgb_screen();
+    assert_eq!((screen.width(), screen.height()), (256, 224));
+    assert_eq!(screen.pixel(0, 0), 0x001f);
+    assert_eq!(screen.pixel(sgb::GAME_LEFT + 7, sgb::GAME_TOP), 0x03e0);
+    assert_eq!(screen.pixel(sgb::GAME_LEFT + 8, sgb::GAME_TOP + 143), 0x7c00);
+
+    // MLT_REQ for two, then P1 reads each controller in turn
+    memory.set_buttons(1, joypad::START);
+    assert_eq!(read_p1(&mut memory, 0x30) & 0x0f, 0x0f);
+    send_packet(&mut memory, &[0x89, 0x01]);
+    assert_eq!(memory.sgb().players(), 2);
+    assert_eq!(read_p1(&mut memory, 0x30) & 0x0f, 0x0f);
+    read_p1(&mut memory, 0x10);
+    assert_eq!(read_p1(&mut memory, 0x30) & 0x0f, 0x0e);
+    assert_eq!(read_p1(&mut memory, 0x10) & 0x0f, 0x07);
+    assert_eq!(read_p1(&mut memory, 0x30) & 0x0f, 0x0f);
+}
+
+#[test]
+fn sgb_needs_the_header_flag() {
+    let mut memory = Memory::new_zeros();
+    memory.set_model(CpuMode::SGB);
+    send_packet(&mut memory, &[0x89, 0x01]);
+    assert_eq!(memory.sgb().players(), 1);
+}
+
+#[test]
+fn sgb_border_transfer() {
+    let mut memory = sgb_memory();
+    // border tile 1 is all colour 1, from the third and fourth tiles on
+    // screen: its low bitplanes, then its high ones
+    for row in 0..8 {
+        memory.put_d8(addr(0x8010 + 2 * row), byte(0xff));
+    }
+    memory.put_d8(addr(0x9802), byte(0x01));
+    memory.put_d8(addr(0xff40), byte(0x91));
+    send_packet(&mut memory, &[0x99, 0x00]);
+    for _ in 0..154 {
+        memory.tick(456);
+    }
+
+    // then the map and palettes: tile 1 in the top-left corner, palette 4
+    // colour 1 white. The map's first entry comes from tile 0's first row.
+    for b in 0..16 {
+        memory.put_d8(addr(0x8010 + b), byte(0x00));
+    }
+    memory.put_d8(addr(0x8000), byte(0x01));
+    memory.put_d8(addr(0x8001), byte(0x10));
+    // palettes start $800 in, the start of tile $80 in the transfer
+    memory.put_d8(addr(0x9800 + 0x80 / 20 * 32 + 0x80 % 20), byte(0x02));
+    memory.put_d8(addr(0x8022), byte(0xff));
+    memory.put_d8(addr(0x8023), byte(0x7f));
+    send_packet(&mut memory, &[0xa1]);
+    for _ in 0..154 {
+        memory.tick(456);
+    }
+    let screen = memory.sgb_screen();
+    assert_eq!(screen.pixel(0, 0), 0x7fff);
+    assert_eq!(screen.pixel(8, 0), memory.sgb().palette(0)[0]);
+}
+
+// while locked, reads from $01xx go to $0180-$01FF, which read $AA
+fn locked_sachen_memory() -> Memory {
+    use self::cartridge::{Sachen, SachenKind};
+    let mut rom = vec![d8::ZERO; 0x8000];
+    for (idx, val) in rom.iter_mut().enumerate().take(0x0200).skip(0x0100) {
+        *val = byte(if idx < 0x0180 { 0x55 } else { 0xaa });
+    }
+    Memory::with_cartridge(Box::new(Sachen::new(rom, SachenKind::Mmc1)))
+}
+
+#[test]
+fn set_model_leaves_sachen_locked() {
+    let mut memory = locked_sachen_memory();
+    memory.set_model(CpuMode::CGB);
+    memory.set_palette_override(None);
+    memory.set_model(CpuMode::SGB);
+    memory.set_model(CpuMode::DMG);
+    assert_eq!(memory.read_d8(addr(0x0134)), Some(byte(0xaa)));
+}
+
+#[test]
+fn watches_leave_sachen_locked() {
+    use super::watch::{WatchKind, WatchAction};
+    let mut memory = locked_sachen_memory();
+    memory.add_watch(WatchKind::Execute, addr(0x0100), addr(0x01ff), |_| WatchAction::Continue);
+    memory.add_watch(WatchKind::Write, addr(0x0100), addr(0x01ff), |_| WatchAction::Continue);
+    for _ in 0..0x40 {
+        memory.begin_instruction(addr(0x0134));
+        memory.put_d8(addr(0x0134), byte(0x00));
+    }
+    assert_eq!(memory.read_d8(addr(0x0134)), Some(byte(0xaa)));
+}
+
+#[test]
+fn junk_rom_size_byte() {
+    let mut rom = vec![d8::ZERO; 0x8000];
+    for &size in &[0x54, 0xff] {
+        rom[0x0148] = byte(size);
+        assert!(Memory::from_rom(rom.clone()).is_some());
+    }
+}
@@ -0,0 +1,128 @@
+use number_types::a16_type::a16;
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+
+/*
+Watchpoints over the CPU's view of the address space.
+
+A watch covers an inclusive range of addresses and one kind of access.
+Reads and writes are the CPU's own, so DMA copies don't trigger them;
+executes trigger when the CPU starts an instruction whose opcode is in the
+range. For reads and executes the old and new values are the same. Echo
+RAM at $E000-$FDFF counts as the work RAM it mirrors, so a watch on $C000
+also sees accesses through $E000; the event has the address the CPU used.
+
+Each callback says whether emulation should carry on. If any says to halt,
+`Memory::take_watch_halt` reports it, and `Cpu::step` returns `Halt` once
+the current instruction is done; whoever is stepping the CPU stops there.
+*/
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchAction {
+    Continue,
+    Halt,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct WatchEvent {
+    pub kind: WatchKind,
+    // the address of the instruction doing the access
+    pub pc: a16,
+    pub address: a16,
+    pub old_value: d8,
+    pub new_value: d8,
+    // the cart's banks at $4000-$7FFF and $A000-$BFFF at the time
+    pub rom_bank: usize,
+    pub ram_bank: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct WatchId(usize);
+
+pub type WatchCallback = Box<dyn FnMut(&WatchEvent) -> WatchAction>;
+
+struct Watch {
+    id: WatchId,
+    kind: WatchKind,
+    start: u16,
+    end: u16,
+    callback: WatchCallback,
+}
+
+// echo RAM addresses as the work RAM behind them
+fn unecho(addr: u16) -> u16 {
+    match addr {
+        0xe000 ... 0xfdff => addr - 0x2000,
+        _ => addr,
+    }
+}
+
+impl Watch {
+    fn covers(&self, kind: WatchKind, addr: u16) -> bool {
+        let in_range = |addr| self.start <= addr && addr <= self.end;
+        self.kind == kind && (in_range(addr) || in_range(unecho(addr)))
+    }
+}
+
+pub struct Watchpoints {
+    watches: Vec<Watch>,
+    next_id: usize,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            watches: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn add(&mut self, kind: WatchKind, start: a16, end: a16, callback: WatchCallback) -> WatchId {
+        let (a16(Wrapping(start)), a16(Wrapping(end))) = (start, end);
+        // a watch on echo RAM is a watch on the work RAM behind it
+        let (start, end) = (unecho(start), unecho(end));
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+        self.watches.push(Watch {
+            id,
+            kind,
+            start: ::std::cmp::min(start, end),
+            end: ::std::cmp::max(start, end),
+            callback,
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: WatchId) -> bool {
+        let before = self.watches.len();
+        self.watches.retain(|watch| watch.id != id);
+        self.watches.len() != before
+    }
+
+    pub fn covers(&self, kind: WatchKind, addr: u16) -> bool {
+        self.watches.iter().any(|watch| watch.covers(kind, addr))
+    }
+
+    // runs every callback watching the access, halting if any of them says to
+    pub fn fire(&mut self, event: &WatchEvent) -> WatchAction {
+        let a16(Wrapping(addr)) = event.address;
+        let mut action = WatchAction::Continue;
+        for watch in self.watches.iter_mut() {
+            if watch.covers(event.kind, addr) && (watch.callback)(event) == WatchAction::Halt {
+                action = WatchAction::Halt;
+            }
+        }
+        action
+    }
+}
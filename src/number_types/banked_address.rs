@@ -0,0 +1,124 @@
+use std::fmt;
+use std::num::Wrapping;
+use number_types::a16_type::a16;
+
+// the areas of the address space, split wherever banking differs
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MemoryRegion {
+    RomBank0,
+    RomBankN,
+    VideoRam,
+    CartridgeRam,
+    WorkRamBank0,
+    WorkRamBankN,
+    EchoRam,
+    Oam,
+    Unusable,
+    Io,
+    HighRam,
+    InterruptEnable,
+}
+
+impl MemoryRegion {
+    pub const ALL: [MemoryRegion; 12] = [
+        MemoryRegion::RomBank0,
+        MemoryRegion::RomBankN,
+        MemoryRegion::VideoRam,
+        MemoryRegion::CartridgeRam,
+        MemoryRegion::WorkRamBank0,
+        MemoryRegion::WorkRamBankN,
+        MemoryRegion::EchoRam,
+        MemoryRegion::Oam,
+        MemoryRegion::Unusable,
+        MemoryRegion::Io,
+        MemoryRegion::HighRam,
+        MemoryRegion::InterruptEnable,
+    ];
+
+    // the names symbol files and debuggers conventionally use
+    pub fn name(&self) -> &'static str {
+        match *self {
+            MemoryRegion::RomBank0 => "ROM0",
+            MemoryRegion::RomBankN => "ROMX",
+            MemoryRegion::VideoRam => "VRAM",
+            MemoryRegion::CartridgeRam => "SRAM",
+            MemoryRegion::WorkRamBank0 => "WRAM0",
+            MemoryRegion::WorkRamBankN => "WRAMX",
+            MemoryRegion::EchoRam => "ECHO",
+            MemoryRegion::Oam => "OAM",
+            MemoryRegion::Unusable => "UNUSABLE",
+            MemoryRegion::Io => "IO",
+            MemoryRegion::HighRam => "HRAM",
+            MemoryRegion::InterruptEnable => "IE",
+        }
+    }
+
+    // first and last address, inclusive
+    pub fn range(&self) -> (u16, u16) {
+        match *self {
+            MemoryRegion::RomBank0 => (0x0000, 0x3fff),
+            MemoryRegion::RomBankN => (0x4000, 0x7fff),
+            MemoryRegion::VideoRam => (0x8000, 0x9fff),
+            MemoryRegion::CartridgeRam => (0xa000, 0xbfff),
+            MemoryRegion::WorkRamBank0 => (0xc000, 0xcfff),
+            MemoryRegion::WorkRamBankN => (0xd000, 0xdfff),
+            MemoryRegion::EchoRam => (0xe000, 0xfdff),
+            MemoryRegion::Oam => (0xfe00, 0xfe9f),
+            MemoryRegion::Unusable => (0xfea0, 0xfeff),
+            MemoryRegion::Io => (0xff00, 0xff7f),
+            MemoryRegion::HighRam => (0xff80, 0xfffe),
+            MemoryRegion::InterruptEnable => (0xffff, 0xffff),
+        }
+    }
+
+    pub fn containing(a16(Wrapping(addr)): a16) -> Self {
+        *MemoryRegion::ALL.iter()
+            .find(|region| addr <= region.range().1)
+            .expect("the regions cover the whole address space")
+    }
+}
+
+// an address along with the bank it's in, so $4000 in bank 3 and $4000 in
+// bank 4 are told apart. `offset` is from the start of the region.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BankedAddress {
+    pub region: MemoryRegion,
+    pub bank: usize,
+    pub offset: u16,
+}
+
+impl BankedAddress {
+    pub fn address(&self) -> a16 {
+        a16(Wrapping(self.region.range().0 + self.offset))
+    }
+}
+
+// `03:4A2F`, the bank then the CPU address
+impl fmt::Display for BankedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let a16(Wrapping(addr)) = self.address();
+        write!(f, "{:02X}:{:04X}", self.bank, addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let addr = BankedAddress {
+            region: MemoryRegion::RomBankN,
+            bank: 3,
+            offset: 0x0a2f,
+        };
+        assert_eq!(addr.to_string(), "03:4A2F");
+    }
+
+    #[test]
+    fn containing() {
+        assert_eq!(MemoryRegion::containing(a16(Wrapping(0x3fff))), MemoryRegion::RomBank0);
+        assert_eq!(MemoryRegion::containing(a16(Wrapping(0xfea0))), MemoryRegion::Unusable);
+        assert_eq!(MemoryRegion::containing(a16(Wrapping(0xffff))), MemoryRegion::InterruptEnable);
+    }
+}
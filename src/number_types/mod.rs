@@ -2,3 +2,4 @@ pub mod d16_type;
 pub mod d8_type;
 pub mod a16_type;
 pub mod a8_type;
+pub mod banked_address;
@@ -0,0 +1,334 @@
+use super::palette::CgbPalettes;
+
+/*
+What the CGB boot ROM does for DMG games: a game whose header doesn't claim
+CGB support gets the CGB drawing in DMG compatibility mode, through
+palettes the boot ROM picks for it and loads into BG palette 0 and object
+palettes 0 and 1.
+
+Only games with a Nintendo licensee get picked for: old licensee $01, or
+$33 with new licensee "01". Their palette comes from the sum of the 16
+title bytes, $0134-$0143, looked up in a table of known sums. Some sums
+are shared by a few games, which the 4th letter of the title tells apart.
+Everything else gets the palette of the Right + A combination.
+
+Holding one of 12 button combinations while the logo is up overrides the
+choice, for any DMG game.
+
+KEY0, $FF4C, is how the boot ROM puts the CGB in compatibility mode:
+bit 2	DMG compatibility
+*/
+pub const KEY0_DMG_COMPAT: u8 = 0x04;
+
+pub const HEADER_START: usize = 0x0100;
+pub const HEADER_END: usize = 0x0150;
+
+const LOGO_START: usize = 0x0104 - HEADER_START;
+const TITLE_START: usize = 0x0134 - HEADER_START;
+const TITLE_LENGTH: usize = 16;
+const CGB_FLAG: usize = 0x0143 - HEADER_START;
+const NEW_LICENSEE: usize = 0x0144 - HEADER_START;
+const OLD_LICENSEE: usize = 0x014b - HEADER_START;
+
+// the boot ROM won't start a cart without this at $0104-$0133
+const LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0c, 0x00, 0x0d, 0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e,
+    0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99, 0xbb, 0xbb, 0x67, 0x63,
+    0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+// the boot ROM's 30 palettes, in RGB555
+const PALETTES: [[u16; 4]; 30] = [
+    [0x7fff, 0x32bf, 0x00d0, 0x0000],
+    [0x639f, 0x4279, 0x15b0, 0x04cb],
+    [0x7fff, 0x6e31, 0x454a, 0x0000],
+    [0x7fff, 0x1bef, 0x0200, 0x0000],
+    [0x7fff, 0x421f, 0x1cf2, 0x0000],
+    [0x7fff, 0x5294, 0x294a, 0x0000],
+    [0x7fff, 0x03ff, 0x012f, 0x0000],
+    [0x7fff, 0x03ef, 0x01d6, 0x0000],
+    [0x7fff, 0x42b5, 0x3dc8, 0x0000],
+    [0x7e74, 0x03ff, 0x0180, 0x0000],
+    [0x67ff, 0x77ac, 0x1a13, 0x2d6b],
+    [0x7ed6, 0x4bff, 0x2175, 0x0000],
+    [0x53ff, 0x4a5f, 0x7e52, 0x0000],
+    [0x4fff, 0x7ed2, 0x3a4c, 0x1ce0],
+    [0x03ed, 0x7fff, 0x255f, 0x0000],
+    [0x036a, 0x021f, 0x03ff, 0x7fff],
+    [0x7fff, 0x01df, 0x0112, 0x0000],
+    [0x231f, 0x035f, 0x00f2, 0x0009],
+    [0x7fff, 0x03ea, 0x011f, 0x0000],
+    [0x299f, 0x001a, 0x000c, 0x0000],
+    [0x7fff, 0x027f, 0x001f, 0x0000],
+    [0x7fff, 0x03e0, 0x0206, 0x0120],
+    [0x7fff, 0x7eeb, 0x001f, 0x7c00],
+    [0x7fff, 0x3fff, 0x7e00, 0x001f],
+    [0x7fff, 0x03ff, 0x001f, 0x0000],
+    [0x03ff, 0x001f, 0x000c, 0x0000],
+    [0x7fff, 0x033f, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037f, 0x7fff],
+    [0x7fff, 0x7e8c, 0x7c00, 0x0000],
+    [0x7fff, 0x1bef, 0x6180, 0x0000],
+];
+
+// where palette `idx` starts, counted in colours into `PALETTES`
+const fn palette(idx: usize) -> usize {
+    idx * 4
+}
+
+// where the OBJ0, OBJ1 and BG palettes of each combination start. Three of
+// them start partway into a palette, as they do in the boot ROM.
+const COMBINATIONS: [(usize, usize, usize); 51] = [
+    (palette(4), palette(4), palette(29)),
+    (palette(18), palette(18), palette(18)),
+    (palette(20), palette(20), palette(20)),
+    (palette(24), palette(24), palette(24)),
+    (palette(9), palette(9), palette(9)),
+    (palette(0), palette(0), palette(0)),
+    (palette(27), palette(27), palette(27)),
+    (palette(5), palette(5), palette(5)),
+    (palette(12), palette(12), palette(12)),
+    (palette(26), palette(26), palette(26)),
+    (palette(16), palette(8), palette(8)),
+    (palette(4), palette(28), palette(28)),
+    (palette(4), palette(2), palette(2)),
+    (palette(3), palette(4), palette(4)),
+    (palette(4), palette(29), palette(29)),
+    (palette(28), palette(4), palette(28)),
+    (palette(2), palette(17), palette(2)),
+    (palette(16), palette(16), palette(8)),
+    (palette(4), palette(4), palette(7)),
+    (palette(4), palette(4), palette(18)),
+    (palette(4), palette(4), palette(20)),
+    (palette(19), palette(19), palette(9)),
+    (palette(4) - 1, palette(4) - 1, palette(11)),
+    (palette(17), palette(17), palette(2)),
+    (palette(4), palette(4), palette(2)),
+    (palette(4), palette(4), palette(3)),
+    (palette(28), palette(28), palette(0)),
+    (palette(3), palette(3), palette(0)),
+    (palette(0), palette(0), palette(1)),
+    (palette(18), palette(22), palette(18)),
+    (palette(20), palette(22), palette(20)),
+    (palette(24), palette(22), palette(24)),
+    (palette(16), palette(22), palette(8)),
+    (palette(17), palette(4), palette(13)),
+    (palette(28) - 1, palette(0), palette(14)),
+    (palette(28) - 1, palette(4), palette(15)),
+    (palette(19), palette(22), palette(9)),
+    (palette(16), palette(28), palette(10)),
+    (palette(4), palette(23), palette(28)),
+    (palette(17), palette(22), palette(2)),
+    (palette(4), palette(0), palette(2)),
+    (palette(4), palette(28), palette(3)),
+    (palette(28), palette(3), palette(0)),
+    (palette(3), palette(28), palette(4)),
+    (palette(21), palette(28), palette(4)),
+    (palette(3), palette(28), palette(0)),
+    (palette(25), palette(3), palette(28)),
+    (palette(0), palette(28), palette(8)),
+    (palette(4), palette(3), palette(28)),
+    (palette(28), palette(3), palette(6)),
+    (palette(4), palette(28), palette(29)),
+];
+
+// title sums the boot ROM knows. From `FIRST_SHARED` on, a sum only matches
+// along with the 4th title letter at the same place in `FOURTH_LETTERS`,
+// and the shared sums repeat for each game that has them.
+const CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xd1, 0xdb, 0xf2, 0x3c, 0x8c, 0x92, 0x3d, 0x5c, 0x58, 0xc9,
+    0x3e, 0x70, 0x1d, 0x59, 0x69, 0x19, 0x35, 0xa8, 0x14, 0xaa, 0x75, 0x95, 0x99, 0x34,
+    0x6f, 0x15, 0xff, 0x97, 0x4b, 0x90, 0x17, 0x10, 0x39, 0xf7, 0xf6, 0xa2, 0x49, 0x4e,
+    0x43, 0x68, 0xe0, 0x8b, 0xf0, 0xce, 0x0c, 0x29, 0xe8, 0xb7, 0x86, 0x9a, 0x52, 0x01,
+    0x9d, 0x71, 0x9c, 0xbd, 0x5d, 0x6d, 0x67, 0x3f, 0x6b,
+    0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4,
+    0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4,
+    0xb3,
+];
+const FIRST_SHARED: usize = 65;
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// the combination for each entry of `CHECKSUMS`
+const CHECKSUM_COMBINATIONS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37,
+    30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18,
+    9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38,
+    26, 42, 30, 41, 34, 34, 5, 42, 6, 5, 33, 25, 42, 42,
+    40, 2, 16, 25, 42, 42, 5, 0, 39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50,
+    17, 46, 6, 27, 0, 0, 47, 0, 41, 0, 0, 0, 0, 0,
+    0,
+];
+
+// the button combinations held during the logo that pick a palette
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PaletteOverride {
+    Up,
+    UpA,
+    UpB,
+    Left,
+    LeftA,
+    LeftB,
+    Down,
+    DownA,
+    DownB,
+    Right,
+    RightA,
+    RightB,
+}
+
+impl PaletteOverride {
+    fn combination(self) -> usize {
+        use self::PaletteOverride::*;
+        match self {
+            Right => 1,
+            Left => 48,
+            Up => 5,
+            Down => 8,
+            RightA => 0,
+            LeftA => 40,
+            UpA => 43,
+            DownA => 3,
+            RightB => 6,
+            LeftB => 7,
+            UpB => 28,
+            DownB => 49,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Colorization {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+impl Colorization {
+    fn from_combination(idx: usize) -> Self {
+        let (obj0, obj1, bg) = COMBINATIONS[idx];
+        let colors = |start: usize| {
+            let mut palette = [0; 4];
+            for (i, color) in palette.iter_mut().enumerate() {
+                let at = start + i;
+                *color = PALETTES[at / 4][at % 4];
+            }
+            palette
+        };
+        Colorization {
+            bg: colors(bg),
+            obj0: colors(obj0),
+            obj1: colors(obj1),
+        }
+    }
+
+    // into BG palette 0 and object palettes 0 and 1, as the boot ROM does
+    pub fn load(&self, palettes: &mut CgbPalettes) {
+        for index in 0..4 {
+            palettes.bg.set_color(0, index as u8, self.bg[index]);
+            palettes.obj.set_color(0, index as u8, self.obj0[index]);
+            palettes.obj.set_color(1, index as u8, self.obj1[index]);
+        }
+    }
+}
+
+// whether the boot ROM would start this cart in DMG compatibility mode.
+// `header` is ROM $0100-$014F.
+pub fn needs_colorization(header: &[u8]) -> bool {
+    header.len() >= HEADER_END - HEADER_START
+        && header[LOGO_START..LOGO_START + LOGO.len()] == LOGO[..]
+        && (header[CGB_FLAG] & 0x80) == 0
+}
+
+fn nintendo_licensed(header: &[u8]) -> bool {
+    match header[OLD_LICENSEE] {
+        0x01 => true,
+        0x33 => &header[NEW_LICENSEE..NEW_LICENSEE + 2] == b"01",
+        _ => false,
+    }
+}
+
+pub fn title_checksum(header: &[u8]) -> u8 {
+    header[TITLE_START..TITLE_START + TITLE_LENGTH]
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn lookup(header: &[u8]) -> usize {
+    if !nintendo_licensed(header) {
+        return 0;
+    }
+    let checksum = title_checksum(header);
+    let fourth_letter = header[TITLE_START + 3];
+    let found = CHECKSUMS.iter().enumerate().position(|(i, &sum)| {
+        sum == checksum && (i < FIRST_SHARED || FOURTH_LETTERS[i - FIRST_SHARED] == fourth_letter)
+    });
+    match found {
+        Some(i) => CHECKSUM_COMBINATIONS[i] as usize,
+        None => 0,
+    }
+}
+
+// the palettes the boot ROM would pick for a cart, `header` being ROM
+// $0100-$014F
+pub fn colorization(header: &[u8], choice: Option<PaletteOverride>) -> Colorization {
+    let combination = match choice {
+        Some(choice) => choice.combination(),
+        None => lookup(header),
+    };
+    Colorization::from_combination(combination)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(title: &[u8], old_licensee: u8) -> Vec<u8> {
+        let mut header = vec![0; HEADER_END - HEADER_START];
+        header[LOGO_START..LOGO_START + LOGO.len()].copy_from_slice(&LOGO);
+        header[TITLE_START..TITLE_START + title.len()].copy_from_slice(title);
+        header[OLD_LICENSEE] = old_licensee;
+        header
+    }
+
+    #[test]
+    fn picks_by_checksum_and_letter() {
+        // sum $14; red BG, green OBJ0
+        let red = header(b"POKEMON RED", 0x01);
+        assert_eq!(title_checksum(&red), 0x14);
+        let colors = colorization(&red, None);
+        assert_eq!(colors.bg, [0x7fff, 0x421f, 0x1cf2, 0x0000]);
+        assert_eq!(colors.obj0, [0x7fff, 0x1bef, 0x0200, 0x0000]);
+
+        // a shared sum, told apart by the 4th letter
+        let blue = header(b"POKEMON BLUE", 0x01);
+        assert_eq!(title_checksum(&blue), 0x61);
+        assert_eq!(colorization(&blue, None).bg, [0x7fff, 0x7e8c, 0x7c00, 0x0000]);
+        let mut other = blue.clone();
+        other[TITLE_START + 3] = b'X';
+        other[TITLE_START + 4] = other[TITLE_START + 4].wrapping_sub(b'X' - b'E');
+        assert_eq!(title_checksum(&other), 0x61);
+        assert_eq!(colorization(&other, None), Colorization::from_combination(0));
+
+        // other licensees get the default whatever the title
+        assert_eq!(colorization(&header(b"POKEMON RED", 0x08), None), Colorization::from_combination(0));
+    }
+
+    #[test]
+    fn buttons_override() {
+        let red = header(b"POKEMON RED", 0x01);
+        let colors = colorization(&red, Some(PaletteOverride::LeftB));
+        assert_eq!(colors.bg, [0x7fff, 0x5294, 0x294a, 0x0000]);
+        assert_eq!(colors.obj1, colors.bg);
+    }
+
+    #[test]
+    fn only_dmg_carts_with_a_logo() {
+        let mut cart = header(b"TETRIS", 0x01);
+        assert!(needs_colorization(&cart));
+        cart[CGB_FLAG] = 0x80;
+        assert!(!needs_colorization(&cart));
+        assert!(!needs_colorization(&[0; HEADER_END - HEADER_START]));
+    }
+}
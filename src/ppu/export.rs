@@ -0,0 +1,183 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use super::{Framebuffer, SCREEN_WIDTH, SCREEN_HEIGHT};
+use super::palette::{self, DmgPalette};
+
+/*
+Getting frames out of the emulator: PNG screenshots, and frame dumps that
+write each frame in a range as it's finished, either to a Y4M video or as
+numbered PNGs.
+
+DMG frames go through a DMG palette on the way out; CGB frames are already
+RGB555. Both come out as 8 bits a channel.
+
+Y4M is raw video that ffmpeg and most players read: a one-line header, then
+each frame as "FRAME" and its Y, Cb and Cr planes. These are written at
+full resolution (4:4:4), converted with the BT.601 studio-range matrix.
+The frame rate is the exact one, 4194304 / 70224 frames a second.
+*/
+const FRAME_RATE: (u32, u32) = (4194304, 70224);
+
+// a frame as 24-bit RGB, row by row
+pub fn frame_rgb888(frame: &Framebuffer, dmg_palette: &DmgPalette) -> Vec<u8> {
+    frame
+        .to_rgb555(dmg_palette)
+        .iter()
+        .flat_map(|&color| palette::rgb555_to_rgb888(color).to_vec())
+        .collect()
+}
+
+// any image as a PNG, from 24-bit RGB row by row
+pub fn write_rgb_png<W: Write>(out: W, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgb)?;
+    Ok(())
+}
+
+pub fn write_png<W: Write>(out: W, frame: &Framebuffer, dmg_palette: &DmgPalette) -> io::Result<()> {
+    write_rgb_png(out, SCREEN_WIDTH, SCREEN_HEIGHT, &frame_rgb888(frame, dmg_palette))
+}
+
+pub fn save_png<P: AsRef<Path>>(path: P, frame: &Framebuffer, dmg_palette: &DmgPalette) -> io::Result<()> {
+    write_png(BufWriter::new(File::create(path)?), frame, dmg_palette)
+}
+
+fn y4m_header() -> String {
+    format!(
+        "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444\n",
+        SCREEN_WIDTH, SCREEN_HEIGHT, FRAME_RATE.0, FRAME_RATE.1
+    )
+}
+
+// one frame's "FRAME" line and Y, Cb and Cr planes
+fn y4m_frame(rgb: &[u8]) -> Vec<u8> {
+    let pixels = SCREEN_WIDTH * SCREEN_HEIGHT;
+    let mut out = Vec::with_capacity(6 + 3 * pixels);
+    out.extend_from_slice(b"FRAME\n");
+    let planes: [fn(i32, i32, i32) -> i32; 3] = [
+        |r, g, b| ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16,
+        |r, g, b| ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128,
+        |r, g, b| ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128,
+    ];
+    for plane in planes.iter() {
+        out.extend(rgb.chunks(3).map(|px| plane(px[0] as i32, px[1] as i32, px[2] as i32) as u8));
+    }
+    out
+}
+
+enum DumpTarget {
+    Y4m(BufWriter<File>),
+    // the directory the numbered PNGs go in
+    PngSequence(PathBuf),
+}
+
+// writes frames `frames` of a run as they're finished, counting from the
+// first frame after power on. Give it to `Ppu::start_frame_dump`.
+pub struct FrameDump {
+    target: DumpTarget,
+    frames: Range<u64>,
+    // the first thing that went wrong, after which nothing more is written
+    error: Option<io::Error>,
+}
+
+impl FrameDump {
+    pub fn y4m<P: AsRef<Path>>(path: P, frames: Range<u64>) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(y4m_header().as_bytes())?;
+        Ok(FrameDump {
+            target: DumpTarget::Y4m(out),
+            frames,
+            error: None,
+        })
+    }
+
+    // frame N goes in `dir/frame_N.png`, N padded to 6 digits
+    pub fn png_sequence<P: AsRef<Path>>(dir: P, frames: Range<u64>) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(FrameDump {
+            target: DumpTarget::PngSequence(dir.as_ref().to_path_buf()),
+            frames,
+            error: None,
+        })
+    }
+
+    // whether every frame it wants has gone by
+    pub fn done(&self, next_frame: u64) -> bool {
+        next_frame >= self.frames.end
+    }
+
+    pub fn push(&mut self, number: u64, frame: &Framebuffer, dmg_palette: &DmgPalette) {
+        if self.error.is_some() || number < self.frames.start || number >= self.frames.end {
+            return;
+        }
+        let written = match self.target {
+            DumpTarget::Y4m(ref mut out) => {
+                out.write_all(&y4m_frame(&frame_rgb888(frame, dmg_palette)))
+            }
+            DumpTarget::PngSequence(ref dir) => {
+                save_png(dir.join(format!("frame_{:06}.png", number)), frame, dmg_palette)
+            }
+        };
+        self.error = written.err();
+    }
+
+    // flushes what's left, and reports anything that went wrong on the way
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        match self.target {
+            DumpTarget::Y4m(mut out) => out.flush(),
+            DumpTarget::PngSequence(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ppu::PixelFormat;
+    use std::env;
+
+    #[test]
+    fn png_round_trip() {
+        let frame = Framebuffer::new();
+        let mut bytes = Vec::new();
+        write_png(&mut bytes, &frame, &DmgPalette::POCKET_GREY).unwrap();
+
+        let decoder = png::Decoder::new(&bytes[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        assert_eq!((info.width, info.height), (160, 144));
+        let lightest = palette::rgb555_to_rgb888(DmgPalette::POCKET_GREY.colors[0]);
+        assert_eq!(&buf[..3], &lightest[..]);
+    }
+
+    #[test]
+    fn y4m_frames_in_range() {
+        let path = env::temp_dir().join(format!("rgb-dump-{}.y4m", ::std::process::id()));
+        let mut dump = FrameDump::y4m(&path, 1..3).unwrap();
+        let frame = Framebuffer::with_format(PixelFormat::Rgb555);
+        for number in 0..4 {
+            dump.push(number, &frame, &DmgPalette::CLASSIC_GREEN);
+        }
+        assert!(dump.done(3));
+        dump.finish().unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let header = y4m_header();
+        let frame_len = 6 + 3 * SCREEN_WIDTH * SCREEN_HEIGHT;
+        assert_eq!(bytes.len(), header.len() + 2 * frame_len);
+        // white is Y 235, and no colour
+        let body = &bytes[header.len() + 6..];
+        assert_eq!(body[0], 235);
+        assert_eq!(body[SCREEN_WIDTH * SCREEN_HEIGHT], 128);
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::VecDeque;
+use memory::io_regs;
+use super::{VideoMemory, tiles, sprites, palette, SCREEN_WIDTH};
+use super::{LCDC_BG_ON, LCDC_OBJ_ON, LCDC_WINDOW_ON, LCDC_WINDOW_MAP, LCDC_BG_MAP};
+use super::sprites::Sprite;
+
+/*
+The pixel FIFO renderer: mode 3 is run a dot at a time, the way the
+hardware does it, so register writes part-way along a line take effect
+part-way along the line, and mode 3 takes as long as the line needs.
+
+The BG fetcher works in 2-dot steps: read the tile number, read the low
+byte of the tile row, read the high byte, then wait until the BG FIFO is
+empty to push all 8 pixels into it. On CGB the tile number step also reads
+the tile's attributes, which go into the FIFO with its pixels. SCX and SCY
+are read at each fetch, and the palettes as each pixel goes out. Every dot
+the BG FIFO isn't empty, one pixel leaves it, mixed with a pixel from the
+object FIFO if there is one.
+
+Mode 3 is 172 dots at the least: the first fetch of each line is thrown
+away, so the first pixel goes out 12 dots in. On top of that:
+- the SCX & 7 pixels scrolled off the left are fetched and dropped, a dot
+  each
+- starting the window empties the BG FIFO and restarts the fetcher on the
+  window map, 6 dots
+- each object stops the pixels for 6 dots while its row is fetched into
+  the object FIFO
+*/
+const FETCH_STEP_DOTS: u8 = 2;
+const OBJECT_FETCH_DOTS: u8 = 6;
+const MAP_WIDTH: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ObjPixel {
+    color: u8,
+    sprite: Sprite,
+}
+
+pub struct PixelFifo {
+    ly: u8,
+    // pixels output so far, which is also the X of the next one
+    x: usize,
+    // colour indices, with the CGB attributes of their tiles
+    bg_fifo: VecDeque<(u8, u8)>,
+    obj_fifo: VecDeque<Option<ObjPixel>>,
+    step: FetchStep,
+    step_dots: u8,
+    // the map column the fetcher is on, counted from the left of the
+    // screen or window
+    fetch_column: usize,
+    tile: u8,
+    attrs: u8,
+    low: u8,
+    high: u8,
+    // the line's throwaway first fetch still to come
+    first_fetch: bool,
+    discard: usize,
+    window_active: bool,
+    window_drawn: bool,
+    window_line: u8,
+    // dots left of an object fetch, during which nothing else moves
+    stall: u8,
+    // the line's objects in the order their fetches happen, nearest the
+    // left first
+    sprites: Vec<Sprite>,
+    done: bool,
+}
+
+impl PixelFifo {
+    pub fn new() -> Self {
+        PixelFifo {
+            ly: 0,
+            x: 0,
+            bg_fifo: VecDeque::with_capacity(16),
+            obj_fifo: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            step_dots: 0,
+            fetch_column: 0,
+            tile: 0,
+            attrs: 0,
+            low: 0,
+            high: 0,
+            first_fetch: true,
+            discard: 0,
+            window_active: false,
+            window_drawn: false,
+            window_line: 0,
+            stall: 0,
+            sprites: Vec::new(),
+            done: true,
+        }
+    }
+
+    // sets up for mode 3 of line `ly`
+    pub fn start_line(&mut self, video: &VideoMemory, ly: u8, window_line: u8) {
+        self.ly = ly;
+        self.x = 0;
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+        self.step = FetchStep::Tile;
+        self.step_dots = 0;
+        self.fetch_column = 0;
+        self.first_fetch = true;
+        self.discard = (video.reg(io_regs::SCX) & 7) as usize;
+        self.window_active = false;
+        self.window_drawn = false;
+        self.window_line = window_line;
+        self.stall = 0;
+        self.sprites = if (video.lcdc() & LCDC_OBJ_ON) != 0 {
+            let mut sprites = sprites::oam_scan(video, ly);
+            // fetches go left to right whatever the priority; stable, so
+            // OAM order survives among equal X positions
+            sprites.sort_by_key(|sprite| sprite.x);
+            sprites.reverse();
+            sprites
+        } else {
+            Vec::new()
+        };
+        self.done = false;
+    }
+
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    // whether the window was drawn on this line, so its line counter
+    // should move on
+    pub fn window_drawn(&self) -> bool {
+        self.window_drawn
+    }
+
+    // runs one dot of mode 3, drawing into `line`
+    pub fn tick(&mut self, video: &VideoMemory, line: &mut [u16]) {
+        if self.done {
+            return;
+        }
+        if self.stall > 0 {
+            self.stall -= 1;
+            return;
+        }
+        self.start_window(video);
+        self.tick_fetcher(video);
+
+        if self.bg_fifo.is_empty() || self.start_object_fetch(video) {
+            return;
+        }
+
+        let (bg, attrs) = self.bg_fifo.pop_front().unwrap_or((0, 0));
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
+        }
+        let obj = self.obj_fifo.pop_front().and_then(|pixel| pixel);
+        line[self.x] = self.mix(video, bg, attrs, obj);
+        self.x += 1;
+        if self.x == SCREEN_WIDTH {
+            self.done = true;
+        }
+    }
+
+    fn mix(&self, video: &VideoMemory, bg: u8, attrs: u8, obj: Option<ObjPixel>) -> u16 {
+        // DMG blanks the BG and window with LCDC bit 0; see
+        // `palette::object_over_bg` for CGB
+        let bg = if !video.cgb_features() && (video.lcdc() & LCDC_BG_ON) == 0 { 0 } else { bg };
+        if let Some(obj) = obj {
+            if palette::object_over_bg(video, &obj.sprite, bg, attrs) {
+                return palette::object_color(video, &obj.sprite, obj.color);
+            }
+        }
+        palette::bg_color(video, bg, attrs)
+    }
+
+    // the fetcher starts over on the window map once the pixels reach WX,
+    // so the window costs the dots of one fetch
+    fn start_window(&mut self, video: &VideoMemory) {
+        let lcdc = video.lcdc();
+        let wx = video.reg(io_regs::WX) as usize;
+        if self.window_active
+            || (lcdc & LCDC_WINDOW_ON) == 0
+            || self.ly < video.reg(io_regs::WY)
+            || wx > SCREEN_WIDTH + 6
+            || self.x + 7 < wx
+        {
+            return;
+        }
+        self.window_active = true;
+        self.window_drawn = true;
+        self.bg_fifo.clear();
+        self.step = FetchStep::Tile;
+        self.step_dots = 0;
+        self.fetch_column = 0;
+        // a WX below 7 starts the window with some of it off the left edge
+        self.discard = 7usize.saturating_sub(wx);
+    }
+
+    fn start_object_fetch(&mut self, video: &VideoMemory) -> bool {
+        let due = match self.sprites.last() {
+            Some(sprite) => sprite.x as usize <= self.x + 8,
+            None => false,
+        };
+        if !due {
+            return false;
+        }
+        let sprite = self.sprites.pop().unwrap();
+        let height = sprites::object_height(video.lcdc());
+        let row = sprite.row(video, self.ly, height);
+        while self.obj_fifo.len() < 8 {
+            self.obj_fifo.push_back(None);
+        }
+        for (i, &color) in row.iter().enumerate() {
+            // where this pixel lands, relative to the next one out
+            let column = sprite.x as usize + i;
+            if column < self.x + 8 || color == 0 {
+                continue;
+            }
+            let slot = &mut self.obj_fifo[column - self.x - 8];
+            let wins = match *slot {
+                None => true,
+                // by X, what's already there was fetched for a smaller X and
+                // keeps its place; by OAM order, the earlier one goes on top
+                Some(existing) => {
+                    !sprites::x_priority(video) && sprite.oam_index < existing.sprite.oam_index
+                }
+            };
+            if wins {
+                *slot = Some(ObjPixel { color, sprite });
+            }
+        }
+        self.stall = OBJECT_FETCH_DOTS - 1;
+        true
+    }
+
+    fn tick_fetcher(&mut self, video: &VideoMemory) {
+        if self.step == FetchStep::Push {
+            if self.bg_fifo.is_empty() {
+                self.push_row();
+            }
+            return;
+        }
+        self.step_dots += 1;
+        if self.step_dots < FETCH_STEP_DOTS {
+            return;
+        }
+        self.step_dots = 0;
+        let lcdc = video.lcdc();
+        self.step = match self.step {
+            FetchStep::Tile => {
+                let map_offset = self.map_address(video);
+                self.tile = video.vram_byte(0, map_offset);
+                self.attrs = video.bg_attributes(map_offset);
+                FetchStep::DataLow
+            }
+            FetchStep::DataLow => {
+                let offset = tiles::bg_tile_offset(lcdc, self.tile) + 2 * self.tile_row(video);
+                self.low = video.vram_byte(self.tile_bank(), offset);
+                FetchStep::DataHigh
+            }
+            FetchStep::DataHigh => {
+                let offset = tiles::bg_tile_offset(lcdc, self.tile) + 2 * self.tile_row(video) + 1;
+                self.high = video.vram_byte(self.tile_bank(), offset);
+                if self.first_fetch {
+                    // thrown away, and fetched again
+                    self.first_fetch = false;
+                    FetchStep::Tile
+                } else {
+                    FetchStep::Push
+                }
+            }
+            FetchStep::Push => FetchStep::Push,
+        };
+    }
+
+    fn push_row(&mut self) {
+        let x_flip = (self.attrs & tiles::ATTR_X_FLIP) != 0;
+        for i in 0..8 {
+            let bit = if x_flip { i } else { 7 - i };
+            let color = (((self.high >> bit) & 1) << 1) | ((self.low >> bit) & 1);
+            self.bg_fifo.push_back((color, self.attrs));
+        }
+        self.fetch_column += 1;
+        self.step = FetchStep::Tile;
+    }
+
+    fn tile_bank(&self) -> usize {
+        if (self.attrs & tiles::ATTR_BANK) != 0 { 1 } else { 0 }
+    }
+
+    fn tile_row(&self, video: &VideoMemory) -> usize {
+        let row = if self.window_active {
+            self.window_line as usize % 8
+        } else {
+            self.ly.wrapping_add(video.reg(io_regs::SCY)) as usize % 8
+        };
+        if (self.attrs & tiles::ATTR_Y_FLIP) != 0 { 7 - row } else { row }
+    }
+
+    fn map_address(&self, video: &VideoMemory) -> usize {
+        let lcdc = video.lcdc();
+        if self.window_active {
+            let map = if (lcdc & LCDC_WINDOW_MAP) != 0 { 0x1c00 } else { 0x1800 };
+            map + (self.window_line as usize / 8) * MAP_WIDTH + (self.fetch_column % MAP_WIDTH)
+        } else {
+            let map = if (lcdc & LCDC_BG_MAP) != 0 { 0x1c00 } else { 0x1800 };
+            let y = self.ly.wrapping_add(video.reg(io_regs::SCY)) as usize;
+            let column = (video.reg(io_regs::SCX) as usize / 8 + self.fetch_column) % MAP_WIDTH;
+            map + (y / 8) * MAP_WIDTH + column
+        }
+    }
+}
@@ -0,0 +1,633 @@
+use number_types::d8_type::d8;
+use std::io;
+use std::num::Wrapping;
+use std::path::Path;
+use cpu::CpuMode;
+use memory::VideoRamBank;
+use memory::io_regs::{self, IoRegisters};
+
+pub mod tiles;
+pub mod sprites;
+pub mod palette;
+pub mod colorize;
+pub mod export;
+pub mod vram_view;
+pub mod raster;
+mod scanline;
+mod fifo;
+
+use self::fifo::PixelFifo;
+use self::palette::{CgbPalettes, DmgPalette};
+use self::export::FrameDump;
+use self::raster::{RasterPoint, RasterEvent, RasterObserver, PpuRegisters, PpuWrite, WriteRecorder};
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/*
+LCDC, $FF40:
+
+bit 7	LCD on
+bit 6	window tile map - 0: $9800, 1: $9C00
+bit 5	window on
+bit 4	BG and window tile data - 0: $8800 addressing, 1: $8000 addressing
+bit 3	BG tile map - 0: $9800, 1: $9C00
+bit 2	object size - 0: 8x8, 1: 8x16
+bit 1	objects on
+bit 0	DMG: BG and window on
+*/
+pub const LCDC_LCD_ON: u8 = 0x80;
+pub const LCDC_WINDOW_MAP: u8 = 0x40;
+pub const LCDC_WINDOW_ON: u8 = 0x20;
+pub const LCDC_TILE_DATA: u8 = 0x10;
+pub const LCDC_BG_MAP: u8 = 0x08;
+pub const LCDC_OBJ_SIZE: u8 = 0x04;
+pub const LCDC_OBJ_ON: u8 = 0x02;
+pub const LCDC_BG_ON: u8 = 0x01;
+
+/*
+Timing is in dots, which are T-cycles at single speed; the PPU doesn't
+speed up in double speed mode.
+
+Each line is 456 dots. Lines 0-143 start in mode 2 (OAM scan) for 80 dots,
+then mode 3 (drawing) for 172 or more, then mode 0 (HBlank) for the rest.
+Lines 144-153 are mode 1 (VBlank) all the way through. How long mode 3
+takes depends on the renderer; see `Renderer`.
+
+STAT, $FF41:
+
+bit 6	LYC=LY interrupt select
+bit 5	mode 2 interrupt select
+bit 4	mode 1 interrupt select
+bit 3	mode 0 interrupt select
+bit 2	LYC=LY, read-only
+bits 0-1	mode, read-only
+
+The STAT interrupt fires when the OR of the selected conditions goes from
+false to true. A condition becoming true while another selected one already
+holds the line high fires nothing, which is known as STAT blocking.
+
+Turning the LCD off sets LY and the mode to 0 and stops the PPU, which
+starts again from the top of line 0 when it's turned back on.
+*/
+pub const LINE_DOTS: u64 = 456;
+pub const OAM_SCAN_DOTS: u64 = 80;
+pub const DRAW_DOTS: u64 = 172;
+pub const VBLANK_LINE: u8 = 144;
+pub const LINES_PER_FRAME: u8 = 154;
+
+// IF bits
+pub const INT_VBLANK: u8 = 0x01;
+pub const INT_STAT: u8 = 0x02;
+
+const STAT_LYC_SELECT: u8 = 0x40;
+const STAT_MODE_2_SELECT: u8 = 0x20;
+const STAT_MODE_1_SELECT: u8 = 0x10;
+const STAT_MODE_0_SELECT: u8 = 0x08;
+const STAT_COINCIDENCE: u8 = 0x04;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+// how lines get drawn, picked when the machine is built
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Renderer {
+    // each line in one go as mode 3 starts, with mode 3 always 172 dots.
+    // Fast, and right for almost everything
+    Scanline,
+    // dot by dot through the fetchers and pixel FIFOs, with mode 3 as long
+    // as SCX, the window and objects make it, and mid-line register writes
+    // showing where they land
+    PixelFifo,
+}
+
+fn get(io: &IoRegisters, idx: usize) -> u8 {
+    let d8(Wrapping(val)) = io.get(idx);
+    val
+}
+
+pub fn request_interrupt(io: &mut IoRegisters, bit: u8) {
+    let flags = get(io, io_regs::IF) | bit;
+    io.set(io_regs::IF, d8(Wrapping(flags)));
+}
+
+// what the PPU gets to look at: the registers, both VRAM banks, OAM and
+// palette RAM, whatever the CPU has locked or mapped
+pub struct VideoMemory<'a> {
+    pub model: CpuMode,
+    pub io: &'a IoRegisters,
+    pub vram: &'a [VideoRamBank; 2],
+    pub oam: &'a [d8; 0xa0],
+    pub palettes: &'a CgbPalettes,
+}
+
+impl<'a> VideoMemory<'a> {
+    pub fn reg(&self, idx: usize) -> u8 {
+        let d8(Wrapping(val)) = self.io.get(idx);
+        val
+    }
+
+    // `offset` is from $8000
+    pub fn vram_byte(&self, bank: usize, offset: usize) -> u8 {
+        let d8(Wrapping(val)) = self.vram[bank].get(offset);
+        val
+    }
+
+    pub fn lcdc(&self) -> u8 {
+        self.reg(io_regs::LCDC)
+    }
+
+    // a CGB running a DMG game, which it draws the DMG way, only through
+    // CGB palette RAM; see `colorize`
+    pub fn dmg_compat(&self) -> bool {
+        match self.model {
+            CpuMode::CGB => (self.reg(io_regs::KEY0) & colorize::KEY0_DMG_COMPAT) != 0,
+            CpuMode::DMG | CpuMode::MGB | CpuMode::SGB => false,
+        }
+    }
+
+    // whether BG attributes, the 8 palettes a side, OPRI and the CGB
+    // meaning of LCDC bit 0 are in use
+    pub fn cgb_features(&self) -> bool {
+        match self.model {
+            CpuMode::CGB => !self.dmg_compat(),
+            CpuMode::DMG | CpuMode::MGB | CpuMode::SGB => false,
+        }
+    }
+
+    // the CGB attributes of the BG map entry at `map_offset`; DMG has none
+    pub fn bg_attributes(&self, map_offset: usize) -> u8 {
+        if self.cgb_features() {
+            self.vram_byte(1, map_offset)
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelFormat {
+    // DMG shades, 0 (lightest) to 3 (darkest)
+    Shades,
+    // CGB colours, RGB555 as in palette RAM
+    Rgb555,
+}
+
+impl PixelFormat {
+    pub fn for_model(model: CpuMode) -> Self {
+        match model {
+            CpuMode::CGB => PixelFormat::Rgb555,
+            CpuMode::DMG | CpuMode::MGB | CpuMode::SGB => PixelFormat::Shades,
+        }
+    }
+
+    pub fn white(self) -> u16 {
+        match self {
+            PixelFormat::Shades => 0,
+            PixelFormat::Rgb555 => palette::WHITE,
+        }
+    }
+}
+
+// one frame, row by row, in whichever format the model draws in
+#[derive(Clone)]
+pub struct Framebuffer {
+    format: PixelFormat,
+    pixels: Vec<u16>,
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer::with_format(PixelFormat::Shades)
+    }
+
+    // a blank, white frame
+    pub fn with_format(format: PixelFormat) -> Self {
+        Framebuffer {
+            format,
+            pixels: vec![format.white(); SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * SCREEN_WIDTH + x]
+    }
+
+    // a pixel of a DMG frame
+    pub fn shade(&self, x: usize, y: usize) -> u8 {
+        self.pixel(x, y) as u8
+    }
+
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    // the frame in colour, DMG shades going through `dmg_palette`
+    pub fn to_rgb555(&self, dmg_palette: &DmgPalette) -> Vec<u16> {
+        match self.format {
+            PixelFormat::Shades => self.pixels.iter().map(|&shade| dmg_palette.color(shade as u8)).collect(),
+            PixelFormat::Rgb555 => self.pixels.clone(),
+        }
+    }
+
+    // starts over blank if the format has changed, as the model has
+    fn line_mut(&mut self, format: PixelFormat, y: usize) -> &mut [u16] {
+        if self.format != format {
+            *self = Framebuffer::with_format(format);
+        }
+        &mut self.pixels[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH]
+    }
+}
+
+pub struct Ppu {
+    renderer: Renderer,
+    fifo: PixelFifo,
+    // how DMG shades look once they're turned into colours
+    dmg_palette: DmgPalette,
+    // drawn into line by line, then swapped with `front` once it's full
+    back: Framebuffer,
+    front: Framebuffer,
+    frame_ready: bool,
+    // frames finished since power on
+    frame_count: u64,
+    frame_dump: Option<FrameDump>,
+    raster_observers: Vec<RasterObserver>,
+    write_recorder: Option<WriteRecorder>,
+    // which line of the window comes next; it only moves on lines where
+    // the window was drawn
+    window_line: u8,
+    enabled: bool,
+    ly: u8,
+    // how far along the current line, 0-455
+    dot: u64,
+    mode: Mode,
+    // how long mode 3 is on this line; until the pixel FIFO renderer has
+    // finished the line, as long as it could be
+    draw_dots: u64,
+    stat_line: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Ppu::with_renderer(Renderer::Scanline)
+    }
+
+    pub fn with_renderer(renderer: Renderer) -> Self {
+        Ppu {
+            renderer,
+            fifo: PixelFifo::new(),
+            dmg_palette: DmgPalette::CLASSIC_GREEN,
+            back: Framebuffer::new(),
+            front: Framebuffer::new(),
+            frame_ready: false,
+            frame_count: 0,
+            frame_dump: None,
+            raster_observers: Vec::new(),
+            write_recorder: None,
+            window_line: 0,
+            enabled: false,
+            ly: 0,
+            dot: 0,
+            mode: Mode::HBlank,
+            draw_dots: DRAW_DOTS,
+            stat_line: false,
+        }
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn dmg_palette(&self) -> DmgPalette {
+        self.dmg_palette
+    }
+
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    pub fn dot(&self) -> u64 {
+        self.dot
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // runs the PPU for `dots` dots, keeping LY and STAT up to date, raising
+    // interrupts and drawing each line in mode 3. Returns how many times it
+    // entered HBlank, for HBlank DMA.
+    pub fn step(
+        &mut self,
+        dots: u64,
+        io: &mut IoRegisters,
+        vram: &[VideoRamBank; 2],
+        oam: &[d8; 0xa0],
+        palettes: &CgbPalettes,
+        model: CpuMode,
+    ) -> u32 {
+        if (get(io, io_regs::LCDC) & LCDC_LCD_ON) == 0 {
+            if self.enabled {
+                self.switch_off(io);
+            }
+            return 0;
+        }
+        if !self.enabled {
+            self.enabled = true;
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = Mode::OamScan;
+            self.update_registers(io);
+            self.notify(RasterPoint::LineStart, io);
+            self.notify(RasterPoint::Mode(Mode::OamScan), io);
+        }
+
+        let mut hblanks = 0;
+        let mut remaining = dots;
+        while remaining > 0 {
+            let advance = ::std::cmp::min(remaining, self.next_boundary() - self.dot);
+            self.dot += advance;
+            remaining -= advance;
+            let new_line = self.dot == LINE_DOTS;
+            if new_line {
+                self.dot = 0;
+                self.ly = (self.ly + 1) % LINES_PER_FRAME;
+            }
+            if self.mode == Mode::Drawing && self.renderer == Renderer::PixelFifo {
+                let video = VideoMemory { model, io: &*io, vram, oam, palettes };
+                self.tick_fifo(&video);
+            }
+            let mode = self.mode_now();
+            let mode_changed = mode != self.mode;
+            if mode_changed {
+                self.mode = mode;
+                match mode {
+                    Mode::Drawing => {
+                        let video = VideoMemory { model, io: &*io, vram, oam, palettes };
+                        self.start_drawing(&video);
+                    }
+                    Mode::HBlank => hblanks += 1,
+                    Mode::VBlank => request_interrupt(io, INT_VBLANK),
+                    Mode::OamScan => (),
+                }
+            }
+            self.update_registers(io);
+            if new_line {
+                self.notify(RasterPoint::LineStart, io);
+            }
+            if mode_changed {
+                self.notify(RasterPoint::Mode(mode), io);
+            }
+        }
+        // catches LYC and STAT writes made since the last step
+        self.update_registers(io);
+        hblanks
+    }
+
+    // calls `observer` at the start of each line and each mode change; see
+    // `raster`
+    pub fn add_raster_observer<F>(&mut self, observer: F)
+        where F: FnMut(&RasterEvent) + 'static
+    {
+        self.raster_observers.push(Box::new(observer));
+    }
+
+    pub fn clear_raster_observers(&mut self) {
+        self.raster_observers.clear();
+    }
+
+    fn notify(&mut self, point: RasterPoint, io: &IoRegisters) {
+        if self.raster_observers.is_empty() {
+            return;
+        }
+        let event = RasterEvent {
+            point,
+            frame: self.frame_count,
+            ly: self.ly,
+            dot: self.dot,
+            registers: PpuRegisters::from_io(io),
+        };
+        for observer in self.raster_observers.iter_mut() {
+            observer(&event);
+        }
+    }
+
+    // logs PPU register writes from now on, until it's taken back
+    pub fn start_recording_writes(&mut self) {
+        self.write_recorder = Some(WriteRecorder::new());
+    }
+
+    pub fn take_write_recording(&mut self) -> Option<WriteRecorder> {
+        self.write_recorder.take()
+    }
+
+    pub fn write_recording(&self) -> Option<&WriteRecorder> {
+        self.write_recorder.as_ref()
+    }
+
+    // called by memory with each I/O register write that lands
+    pub fn record_write(&mut self, reg: usize, value: u8) {
+        let (frame, ly, dot) = (self.frame_count, self.ly, self.dot);
+        if let Some(ref mut recorder) = self.write_recorder {
+            recorder.record(PpuWrite { frame, ly, dot, reg, value });
+        }
+    }
+
+    fn start_drawing(&mut self, video: &VideoMemory) {
+        let ly = self.ly;
+        match self.renderer {
+            Renderer::Scanline => {
+                self.draw_dots = DRAW_DOTS;
+                self.render_line(video, ly);
+            }
+            Renderer::PixelFifo => {
+                self.draw_dots = LINE_DOTS - OAM_SCAN_DOTS;
+                if ly == 0 {
+                    self.window_line = 0;
+                }
+                self.fifo.start_line(video, ly, self.window_line);
+            }
+        }
+    }
+
+    // runs the pixel FIFOs for the dot just gone, ending mode 3 once the
+    // line is done
+    fn tick_fifo(&mut self, video: &VideoMemory) {
+        let y = self.ly as usize;
+        let format = PixelFormat::for_model(video.model);
+        self.fifo.tick(video, self.back.line_mut(format, y));
+        if self.fifo.done() {
+            self.draw_dots = self.dot - OAM_SCAN_DOTS;
+            if self.fifo.window_drawn() {
+                self.window_line = self.window_line.wrapping_add(1);
+            }
+            self.finish_line(y);
+        }
+    }
+
+    fn next_boundary(&self) -> u64 {
+        let draw_end = OAM_SCAN_DOTS + self.draw_dots;
+        if self.ly >= VBLANK_LINE || self.dot >= draw_end {
+            LINE_DOTS
+        } else if self.dot >= OAM_SCAN_DOTS {
+            match self.renderer {
+                Renderer::Scanline => draw_end,
+                // a dot at a time, so the FIFOs see each one
+                Renderer::PixelFifo => self.dot + 1,
+            }
+        } else {
+            OAM_SCAN_DOTS
+        }
+    }
+
+    fn mode_now(&self) -> Mode {
+        if self.ly >= VBLANK_LINE {
+            Mode::VBlank
+        } else if self.dot < OAM_SCAN_DOTS {
+            Mode::OamScan
+        } else if self.dot < OAM_SCAN_DOTS + self.draw_dots {
+            Mode::Drawing
+        } else {
+            Mode::HBlank
+        }
+    }
+
+    fn update_registers(&mut self, io: &mut IoRegisters) {
+        let stat = get(io, io_regs::STAT);
+        let coincidence = self.ly == get(io, io_regs::LYC);
+        let mut new_stat = (stat & 0xf8) | self.mode as u8;
+        if coincidence {
+            new_stat |= STAT_COINCIDENCE;
+        }
+        io.set(io_regs::LY, d8(Wrapping(self.ly)));
+        io.set(io_regs::STAT, d8(Wrapping(new_stat)));
+
+        let selected = |bit: u8| (stat & bit) != 0;
+        let line = (selected(STAT_LYC_SELECT) && coincidence)
+            || (selected(STAT_MODE_2_SELECT) && self.mode == Mode::OamScan)
+            || (selected(STAT_MODE_1_SELECT) && self.mode == Mode::VBlank)
+            || (selected(STAT_MODE_0_SELECT) && self.mode == Mode::HBlank);
+        if line && !self.stat_line {
+            request_interrupt(io, INT_STAT);
+        }
+        self.stat_line = line;
+    }
+
+    fn switch_off(&mut self, io: &mut IoRegisters) {
+        self.enabled = false;
+        self.ly = 0;
+        self.dot = 0;
+        self.mode = Mode::HBlank;
+        self.stat_line = false;
+        io.set(io_regs::LY, d8::ZERO);
+        let stat = get(io, io_regs::STAT) & 0xf8;
+        io.set(io_regs::STAT, d8(Wrapping(stat)));
+        // the screen goes blank while it's off
+        self.front = Framebuffer::with_format(self.front.format());
+        self.frame_ready = true;
+    }
+
+    // draws line `ly` as things stand now
+    pub fn render_line(&mut self, video: &VideoMemory, ly: u8) {
+        if ly == 0 {
+            self.window_line = 0;
+        }
+        let y = ly as usize;
+        if y >= SCREEN_HEIGHT {
+            return;
+        }
+        let mut bg_indices = [0; SCREEN_WIDTH];
+        let mut bg_attrs = [0; SCREEN_WIDTH];
+        scanline::render_background(video, ly, &mut self.window_line, &mut bg_indices, &mut bg_attrs);
+        let line = self.back.line_mut(PixelFormat::for_model(video.model), y);
+        for (x, pixel) in line.iter_mut().enumerate() {
+            *pixel = palette::bg_color(video, bg_indices[x], bg_attrs[x]);
+        }
+        let lcdc = video.lcdc();
+        if (lcdc & LCDC_LCD_ON) != 0 && (lcdc & LCDC_OBJ_ON) != 0 {
+            sprites::render_line(video, ly, &bg_indices, &bg_attrs, line);
+        }
+        self.finish_line(y);
+    }
+
+    fn finish_line(&mut self, y: usize) {
+        if y == SCREEN_HEIGHT - 1 {
+            ::std::mem::swap(&mut self.back, &mut self.front);
+            self.frame_ready = true;
+            if let Some(ref mut dump) = self.frame_dump {
+                dump.push(self.frame_count, &self.front, &self.dmg_palette);
+            }
+            self.frame_count += 1;
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // starts writing out frames as they're finished; see `export`
+    pub fn start_frame_dump(&mut self, dump: FrameDump) {
+        self.frame_dump = Some(dump);
+    }
+
+    // stops the dump, handing it back to be finished
+    pub fn take_frame_dump(&mut self) -> Option<FrameDump> {
+        self.frame_dump.take()
+    }
+
+    // whether the dump has had all the frames it wants
+    pub fn frame_dump_done(&self) -> bool {
+        match self.frame_dump {
+            Some(ref dump) => dump.done(self.frame_count),
+            None => true,
+        }
+    }
+
+    // the last finished frame as a PNG, DMG shades going through the DMG
+    // palette
+    pub fn save_screenshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        export::save_png(path, &self.front, &self.dmg_palette)
+    }
+
+    pub fn render_frame(&mut self, video: &VideoMemory) {
+        for ly in 0..SCREEN_HEIGHT as u8 {
+            self.render_line(video, ly);
+        }
+    }
+
+    // the frame that's just been finished, once per frame
+    pub fn take_frame(&mut self) -> Option<&Framebuffer> {
+        if self.frame_ready {
+            self.frame_ready = false;
+            Some(&self.front)
+        } else {
+            None
+        }
+    }
+
+    // the last finished frame
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.front
+    }
+
+    // the last finished frame in colour, whatever the model
+    pub fn frame_rgb555(&self) -> Vec<u16> {
+        self.front.to_rgb555(&self.dmg_palette)
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,220 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use memory::io_regs;
+use super::{VideoMemory, tiles, LCDC_BG_ON};
+use super::sprites::Sprite;
+
+/*
+CGB palette RAM: 8 BG palettes and 8 object palettes of 4 colours each, 64
+bytes a side. Each colour is 2 bytes, little-endian RGB555:
+
+bits 0-4	red
+bits 5-9	green
+bits 10-14	blue
+
+The CPU gets at them one byte at a time through an index register and a
+data register: BCPS/BCPD for the BG side, OCPS/OCPD for objects.
+
+BCPS/OCPS:
+bit 7	move the index on by one after each data write
+bits 0-5	index
+
+Like VRAM, palette RAM is locked from the CPU in mode 3.
+*/
+pub const PALETTE_RAM_SIZE: usize = 0x40;
+pub const PALETTES: usize = 8;
+pub const WHITE: u16 = 0x7fff;
+
+const AUTO_INCREMENT: u8 = 0x80;
+const INDEX_MASK: u8 = 0x3f;
+
+#[derive(Clone)]
+pub struct PaletteRam {
+    data: [u8; PALETTE_RAM_SIZE],
+}
+
+impl PaletteRam {
+    // power-on palette RAM is junk; white is as good as any
+    pub fn new() -> Self {
+        PaletteRam {
+            data: [0xff; PALETTE_RAM_SIZE],
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> u8 {
+        self.data[idx & INDEX_MASK as usize]
+    }
+
+    pub fn set(&mut self, idx: usize, val: u8) {
+        self.data[idx & INDEX_MASK as usize] = val;
+    }
+
+    pub fn color(&self, palette: usize, index: u8) -> u16 {
+        let offset = 8 * palette + 2 * index as usize;
+        (u16::from(self.data[offset + 1]) << 8 | u16::from(self.data[offset])) & 0x7fff
+    }
+
+    pub fn set_color(&mut self, palette: usize, index: u8, color: u16) {
+        let offset = 8 * palette + 2 * index as usize;
+        self.data[offset] = color as u8;
+        self.data[offset + 1] = (color >> 8) as u8;
+    }
+}
+
+#[derive(Clone)]
+pub struct CgbPalettes {
+    pub bg: PaletteRam,
+    pub obj: PaletteRam,
+}
+
+impl CgbPalettes {
+    pub fn new() -> Self {
+        CgbPalettes {
+            bg: PaletteRam::new(),
+            obj: PaletteRam::new(),
+        }
+    }
+
+    // `spec_reg` is BCPS or OCPS
+    fn ram_mut(&mut self, spec_reg: usize) -> &mut PaletteRam {
+        if spec_reg == io_regs::BCPS { &mut self.bg } else { &mut self.obj }
+    }
+
+    fn ram(&self, spec_reg: usize) -> &PaletteRam {
+        if spec_reg == io_regs::BCPS { &self.bg } else { &self.obj }
+    }
+
+    // a read of BCPD or OCPD, given the index register
+    pub fn read_data(&self, spec_reg: usize, spec: d8) -> d8 {
+        let d8(Wrapping(spec)) = spec;
+        d8(Wrapping(self.ram(spec_reg).get(spec as usize)))
+    }
+
+    // a write to BCPD or OCPD; returns the index register as it is after
+    pub fn write_data(&mut self, spec_reg: usize, spec: d8, val: d8) -> d8 {
+        let d8(Wrapping(spec)) = spec;
+        let d8(Wrapping(val)) = val;
+        self.ram_mut(spec_reg).set(spec as usize, val);
+        if (spec & AUTO_INCREMENT) != 0 {
+            d8(Wrapping(AUTO_INCREMENT | (spec.wrapping_add(1) & INDEX_MASK)))
+        } else {
+            d8(Wrapping(spec))
+        }
+    }
+}
+
+// a finished pixel: a DMG shade, or an RGB555 colour on CGB. A CGB running
+// a DMG game looks the shade up in BG palette 0.
+pub fn bg_color(video: &VideoMemory, index: u8, attrs: u8) -> u16 {
+    if video.cgb_features() {
+        return video.palettes.bg.color((attrs & 0x07) as usize, index);
+    }
+    let shade = tiles::apply_palette(video.reg(io_regs::BGP), index);
+    if video.dmg_compat() {
+        video.palettes.bg.color(0, shade)
+    } else {
+        u16::from(shade)
+    }
+}
+
+// as `bg_color`; a CGB running a DMG game uses object palette 0 for OBP0
+// and 1 for OBP1
+pub fn object_color(video: &VideoMemory, sprite: &Sprite, index: u8) -> u16 {
+    if video.cgb_features() {
+        return video.palettes.obj.color(sprite.cgb_palette(), index);
+    }
+    let obp = sprite.dmg_palette();
+    let shade = tiles::apply_palette(video.reg(obp), index);
+    if video.dmg_compat() {
+        video.palettes.obj.color(obp - io_regs::OBP0, shade)
+    } else {
+        u16::from(shade)
+    }
+}
+
+// whether an object's pixel goes over the BG or window pixel beneath it.
+// BG colour 0 is always under. On CGB, clearing LCDC bit 0 puts every
+// object over the BG, and otherwise either the object's flag or the BG
+// tile's attribute can put the BG on top.
+pub fn object_over_bg(video: &VideoMemory, sprite: &Sprite, bg_index: u8, bg_attrs: u8) -> bool {
+    if bg_index == 0 {
+        return true;
+    }
+    if video.cgb_features() {
+        (video.lcdc() & LCDC_BG_ON) == 0
+            || !(sprite.behind_bg() || (bg_attrs & tiles::ATTR_PRIORITY) != 0)
+    } else {
+        !sprite.behind_bg()
+    }
+}
+
+// what the four DMG shades look like on screen, lightest first, as RGB555
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DmgPalette {
+    pub colors: [u16; 4],
+}
+
+impl DmgPalette {
+    // the original DMG's green LCD
+    pub const CLASSIC_GREEN: DmgPalette = DmgPalette {
+        colors: [0x06f3, 0x06b1, 0x1986, 0x04e1],
+    };
+    // the Pocket's grey one
+    pub const POCKET_GREY: DmgPalette = DmgPalette {
+        colors: [0x5338, 0x3651, 0x1d49, 0x0c63],
+    };
+
+    // from 24-bit 0xRRGGBB colours, lightest first; the low 3 bits of
+    // each channel are dropped
+    pub fn from_rgb888(colors: [u32; 4]) -> Self {
+        let mut palette = DmgPalette { colors: [0; 4] };
+        for (out, &color) in palette.colors.iter_mut().zip(colors.iter()) {
+            *out = rgb888_to_rgb555(color);
+        }
+        palette
+    }
+
+    pub fn color(&self, shade: u8) -> u16 {
+        self.colors[(shade & 0x03) as usize]
+    }
+}
+
+pub fn rgb888_to_rgb555(color: u32) -> u16 {
+    let channel = |shift: u32| ((color >> shift) & 0xff) as u16 >> 3;
+    channel(0) << 10 | channel(8) << 5 | channel(16)
+}
+
+// scaled so 31 comes out as 255
+pub fn rgb555_to_rgb888(color: u16) -> [u8; 3] {
+    let channel = |shift: u16| {
+        let val = (color >> shift) & 0x1f;
+        ((val << 3) | (val >> 2)) as u8
+    };
+    [channel(0), channel(5), channel(10)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn byte(val: u8) -> d8 {
+        d8(Wrapping(val))
+    }
+
+    #[test]
+    fn auto_increment_wraps() {
+        let mut palettes = CgbPalettes::new();
+        let spec = palettes.write_data(io_regs::BCPS, byte(0xbe), byte(0x1f));
+        assert_eq!(spec, byte(0xbf));
+        let spec = palettes.write_data(io_regs::BCPS, spec, byte(0x00));
+        assert_eq!(spec, byte(0x80));
+        assert_eq!(palettes.bg.color(7, 3), 0x001f);
+        assert_eq!(palettes.read_data(io_regs::BCPS, byte(0x3e)), byte(0x1f));
+
+        // without bit 7 the index stays put
+        let spec = palettes.write_data(io_regs::OCPS, byte(0x05), byte(0x12));
+        assert_eq!(spec, byte(0x05));
+        assert_eq!(palettes.obj.get(5), 0x12);
+        assert_eq!(palettes.bg.get(5), 0xff);
+    }
+}
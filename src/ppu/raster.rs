@@ -0,0 +1,187 @@
+use number_types::d8_type::d8;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::num::Wrapping;
+use std::path::Path;
+use memory::io_regs::{self, IoRegisters};
+use super::Mode;
+
+/*
+Seeing raster effects: what a game changes part-way through a frame, like
+SCX wobbles, palette swaps and LCDC toggles.
+
+Raster observers are called at the start of every line while the LCD is
+on, and every time the mode changes, with a snapshot of the PPU's
+registers as they are at that point. The one for mode 3 comes once the
+line has been set up, so with the scanline renderer it has what the line
+was drawn with.
+
+The write recorder logs every CPU write to a PPU register with where the
+PPU was when it landed: the frame, LY and the dot along the line. Writes
+that don't land, like to palette RAM in mode 3, aren't logged.
+*/
+
+// where along the frame a raster observer is called
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RasterPoint {
+    LineStart,
+    // the PPU just went into this mode
+    Mode(Mode),
+}
+
+// the registers that shape what's drawn
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PpuRegisters {
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+}
+
+impl PpuRegisters {
+    pub fn from_io(io: &IoRegisters) -> Self {
+        let get = |idx: usize| {
+            let d8(Wrapping(val)) = io.get(idx);
+            val
+        };
+        PpuRegisters {
+            lcdc: get(io_regs::LCDC),
+            stat: get(io_regs::STAT),
+            scy: get(io_regs::SCY),
+            scx: get(io_regs::SCX),
+            ly: get(io_regs::LY),
+            lyc: get(io_regs::LYC),
+            bgp: get(io_regs::BGP),
+            obp0: get(io_regs::OBP0),
+            obp1: get(io_regs::OBP1),
+            wy: get(io_regs::WY),
+            wx: get(io_regs::WX),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RasterEvent {
+    pub point: RasterPoint,
+    // frames finished since power on
+    pub frame: u64,
+    pub ly: u8,
+    pub dot: u64,
+    pub registers: PpuRegisters,
+}
+
+pub type RasterObserver = Box<dyn FnMut(&RasterEvent)>;
+
+// the PPU's registers by name, for the recorder
+pub fn register_name(reg: usize) -> Option<&'static str> {
+    let name = match reg {
+        io_regs::LCDC => "LCDC",
+        io_regs::STAT => "STAT",
+        io_regs::SCY => "SCY",
+        io_regs::SCX => "SCX",
+        io_regs::LY => "LY",
+        io_regs::LYC => "LYC",
+        io_regs::DMA => "DMA",
+        io_regs::BGP => "BGP",
+        io_regs::OBP0 => "OBP0",
+        io_regs::OBP1 => "OBP1",
+        io_regs::WY => "WY",
+        io_regs::WX => "WX",
+        io_regs::VBK => "VBK",
+        io_regs::BCPS => "BCPS",
+        io_regs::BCPD => "BCPD",
+        io_regs::OCPS => "OCPS",
+        io_regs::OCPD => "OCPD",
+        io_regs::OPRI => "OPRI",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PpuWrite {
+    pub frame: u64,
+    pub ly: u8,
+    pub dot: u64,
+    // relative to $FF00, like the `io_regs` constants
+    pub reg: usize,
+    pub value: u8,
+}
+
+// every PPU register write, in order. Give it to
+// `Ppu::start_recording_writes`.
+#[derive(Debug, Clone)]
+pub struct WriteRecorder {
+    writes: Vec<PpuWrite>,
+}
+
+impl WriteRecorder {
+    pub fn new() -> Self {
+        WriteRecorder { writes: Vec::new() }
+    }
+
+    pub fn writes(&self) -> &[PpuWrite] {
+        &self.writes
+    }
+
+    pub fn clear(&mut self) {
+        self.writes.clear();
+    }
+
+    // ignores anything that isn't a PPU register
+    pub fn record(&mut self, write: PpuWrite) {
+        if register_name(write.reg).is_some() {
+            self.writes.push(write);
+        }
+    }
+
+    // one write a row, under a header:
+    // `frame,ly,dot,register,address,value`
+    pub fn write_csv<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "frame,ly,dot,register,address,value")?;
+        for write in &self.writes {
+            writeln!(
+                out,
+                "{},{},{},{},{:04X},{:02X}",
+                write.frame,
+                write.ly,
+                write.dot,
+                register_name(write.reg).unwrap_or("?"),
+                0xff00 + write.reg,
+                write.value
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_csv(&mut out)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn csv_rows() {
+        let mut recorder = WriteRecorder::new();
+        recorder.record(PpuWrite { frame: 2, ly: 40, dot: 252, reg: io_regs::SCX, value: 0x1c });
+        recorder.record(PpuWrite { frame: 2, ly: 41, dot: 8, reg: io_regs::TAC, value: 0x05 });
+        let mut out = Vec::new();
+        recorder.write_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "frame,ly,dot,register,address,value\n2,40,252,SCX,FF43,1C\n"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use memory::io_regs;
+use super::{VideoMemory, tiles, SCREEN_WIDTH};
+use super::{LCDC_LCD_ON, LCDC_BG_ON, LCDC_BG_MAP, LCDC_WINDOW_ON, LCDC_WINDOW_MAP};
+
+/*
+The scanline renderer: each line is drawn in one go, from the registers as
+they are when it's drawn, so changes part-way along a line don't show.
+
+The BG map is 32x32 tiles, wrapping in both directions; SCX and SCY pick
+the top-left corner of the screen within it. The window has no scrolling
+and no wrapping: its top-left corner is drawn at (WX - 7, WY).
+*/
+const MAP_WIDTH: usize = 32;
+const TILE_MAP_0: usize = 0x1800;
+const TILE_MAP_1: usize = 0x1c00;
+
+fn map_base(lcdc: u8, bit: u8) -> usize {
+    if (lcdc & bit) != 0 {
+        TILE_MAP_1
+    } else {
+        TILE_MAP_0
+    }
+}
+
+// the colour index and CGB attributes of one BG or window pixel, from map
+// coordinates
+fn map_pixel(video: &VideoMemory, map: usize, x: usize, y: usize) -> (u8, u8) {
+    let map_offset = map + (y / 8) * MAP_WIDTH + x / 8;
+    let tile = video.vram_byte(0, map_offset);
+    let attrs = video.bg_attributes(map_offset);
+    (tiles::bg_tile_row(video, tile, attrs, y % 8)[x % 8], attrs)
+}
+
+// fills `indices` with the BG and window colour indices of line `ly`,
+// before the palette, and `attrs` with their CGB attributes
+pub fn render_background(
+    video: &VideoMemory,
+    ly: u8,
+    window_line: &mut u8,
+    indices: &mut [u8; SCREEN_WIDTH],
+    attrs: &mut [u8; SCREEN_WIDTH],
+) {
+    let lcdc = video.lcdc();
+    // on CGB, LCDC bit 0 is about object priority instead; see
+    // `palette::object_over_bg`
+    let bg_off = !video.cgb_features() && (lcdc & LCDC_BG_ON) == 0;
+    if (lcdc & LCDC_LCD_ON) == 0 || bg_off {
+        *indices = [0; SCREEN_WIDTH];
+        *attrs = [0; SCREEN_WIDTH];
+        return;
+    }
+
+    let scx = video.reg(io_regs::SCX);
+    let scy = video.reg(io_regs::SCY);
+    let bg_map = map_base(lcdc, LCDC_BG_MAP);
+    let y = ly.wrapping_add(scy) as usize;
+    for x in 0..SCREEN_WIDTH {
+        let map_x = (x as u8).wrapping_add(scx) as usize;
+        let (index, attr) = map_pixel(video, bg_map, map_x, y);
+        indices[x] = index;
+        attrs[x] = attr;
+    }
+
+    let wx = video.reg(io_regs::WX) as usize;
+    let wy = video.reg(io_regs::WY);
+    if (lcdc & LCDC_WINDOW_ON) == 0 || ly < wy || wx > SCREEN_WIDTH + 6 {
+        return;
+    }
+    let window_map = map_base(lcdc, LCDC_WINDOW_MAP);
+    let left = wx.saturating_sub(7);
+    // WX below 7 pushes the window's left edge off screen
+    let skip = 7usize.saturating_sub(wx);
+    for x in left..SCREEN_WIDTH {
+        let (index, attr) = map_pixel(video, window_map, x - left + skip, *window_line as usize);
+        indices[x] = index;
+        attrs[x] = attr;
+    }
+    *window_line = window_line.wrapping_add(1);
+}
@@ -0,0 +1,168 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use memory::io_regs;
+use super::{VideoMemory, tiles, palette, SCREEN_WIDTH, LCDC_OBJ_SIZE};
+
+/*
+OAM holds 40 objects of 4 bytes each:
+
+0	Y position + 16
+1	X position + 8
+2	tile number, always with $8000 addressing. In 8x16 mode bit 0 is
+	ignored: the top half is the even tile and the bottom half the odd one
+3	flags:
+	bit 7	BG and window colours 1-3 are drawn over the object
+	bit 6	Y flip
+	bit 5	X flip
+	bit 4	DMG palette - 0: OBP0, 1: OBP1
+	bit 3	CGB VRAM bank
+	bits 0-2	CGB palette
+
+During the OAM scan at the start of each line, the PPU picks the first 10
+objects in OAM order that are on the line, whatever their X position;
+objects off the sides of the screen still count.
+
+Where objects overlap, DMG draws the one with the smaller X on top, and the
+one earlier in OAM on ties. CGB goes by OAM order, unless bit 0 of OPRI is
+set, when it does what DMG does. Colour 0 is transparent, and a transparent
+pixel of the winning object lets the next one show through.
+*/
+pub const OAM_OBJECTS: usize = 40;
+pub const MAX_OBJECTS_PER_LINE: usize = 10;
+
+const FLAG_BEHIND_BG: u8 = 0x80;
+const FLAG_Y_FLIP: u8 = 0x40;
+const FLAG_X_FLIP: u8 = 0x20;
+const FLAG_DMG_PALETTE: u8 = 0x10;
+const FLAG_CGB_BANK: u8 = 0x08;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+    pub oam_index: usize,
+}
+
+impl Sprite {
+    pub fn from_oam(oam: &[d8; 0xa0], oam_index: usize) -> Self {
+        let byte = |i: usize| {
+            let d8(Wrapping(val)) = oam[4 * oam_index + i];
+            val
+        };
+        Sprite {
+            y: byte(0),
+            x: byte(1),
+            tile: byte(2),
+            flags: byte(3),
+            oam_index,
+        }
+    }
+
+    pub fn behind_bg(&self) -> bool {
+        (self.flags & FLAG_BEHIND_BG) != 0
+    }
+
+    pub fn dmg_palette(&self) -> usize {
+        if (self.flags & FLAG_DMG_PALETTE) != 0 {
+            io_regs::OBP1
+        } else {
+            io_regs::OBP0
+        }
+    }
+
+    pub fn cgb_palette(&self) -> usize {
+        (self.flags & 0x07) as usize
+    }
+
+    pub fn cgb_bank(&self) -> usize {
+        ((self.flags & FLAG_CGB_BANK) >> 3) as usize
+    }
+
+    // the object's colour indices on line `ly`, leftmost first on screen
+    pub fn row(&self, video: &VideoMemory, ly: u8, height: u8) -> [u8; 8] {
+        let mut row = (ly as usize + 16) - self.y as usize;
+        if (self.flags & FLAG_Y_FLIP) != 0 {
+            row = height as usize - 1 - row;
+        }
+        let tile = if height == 16 { self.tile & 0xfe } else { self.tile };
+        let bank = if video.cgb_features() { self.cgb_bank() } else { 0 };
+        // the bottom half of an 8x16 object is the next tile along
+        let offset = (tile as usize + row / 8) * tiles::TILE_BYTES;
+        let mut pixels = tiles::tile_row(&video.vram[bank], offset, row % 8);
+        if (self.flags & FLAG_X_FLIP) != 0 {
+            pixels.reverse();
+        }
+        pixels
+    }
+}
+
+// whether overlapping objects go by X position rather than OAM order
+pub fn x_priority(video: &VideoMemory) -> bool {
+    if video.cgb_features() {
+        (video.reg(io_regs::OPRI) & 0x01) != 0
+    } else {
+        true
+    }
+}
+
+pub fn object_height(lcdc: u8) -> u8 {
+    if (lcdc & LCDC_OBJ_SIZE) != 0 { 16 } else { 8 }
+}
+
+// the objects on line `ly`, in drawing priority order, highest first
+pub fn oam_scan(video: &VideoMemory, ly: u8) -> Vec<Sprite> {
+    let height = object_height(video.lcdc()) as usize;
+    let line = ly as usize + 16;
+    let mut sprites: Vec<Sprite> = (0..OAM_OBJECTS)
+        .map(|i| Sprite::from_oam(video.oam, i))
+        .filter(|sprite| sprite.y as usize <= line && line < sprite.y as usize + height)
+        .take(MAX_OBJECTS_PER_LINE)
+        .collect();
+    if x_priority(video) {
+        // a stable sort keeps OAM order among equal X positions
+        sprites.sort_by_key(|sprite| sprite.x);
+    }
+    sprites
+}
+
+// the winning object's pixel at each position on line `ly`, as (sprite,
+// colour index) pairs, before any BG priority is applied
+pub fn resolve_line(video: &VideoMemory, ly: u8) -> [Option<(Sprite, u8)>; SCREEN_WIDTH] {
+    let mut pixels = [None; SCREEN_WIDTH];
+    let height = object_height(video.lcdc());
+    for sprite in oam_scan(video, ly) {
+        let row = sprite.row(video, ly, height);
+        for (i, &color) in row.iter().enumerate() {
+            let x = sprite.x as usize + i;
+            if x < 8 || x >= SCREEN_WIDTH + 8 || color == 0 {
+                continue;
+            }
+            let pixel = &mut pixels[x - 8];
+            if pixel.is_none() {
+                *pixel = Some((sprite, color));
+            }
+        }
+    }
+    pixels
+}
+
+// draws line `ly`'s objects over `line`, given the BG and window colour
+// indices and attributes under them
+pub fn render_line(
+    video: &VideoMemory,
+    ly: u8,
+    bg_indices: &[u8; SCREEN_WIDTH],
+    bg_attrs: &[u8; SCREEN_WIDTH],
+    line: &mut [u16],
+) {
+    let pixels = resolve_line(video, ly);
+    for (x, pixel) in pixels.iter().enumerate() {
+        if let Some((sprite, color)) = *pixel {
+            if palette::object_over_bg(video, &sprite, bg_indices[x], bg_attrs[x]) {
+                line[x] = palette::object_color(video, &sprite, color);
+            }
+        }
+    }
+}
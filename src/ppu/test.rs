@@ -0,0 +1,551 @@
+use super::*;
+use memory::Memory;
+use number_types::a16_type::a16;
+use memory::io_regs;
+
+fn put(memory: &mut Memory, idx: u16, val: u8) {
+    memory.put_d8(a16(Wrapping(idx)), d8(Wrapping(val)));
+}
+
+// tile 1 is solid colour 1, tile 2 solid colour 3, and tile 3 has a
+// colour 2 pixel in its top-left corner and colour 0 elsewhere
+fn memory_with_tiles() -> Memory {
+    let mut memory = Memory::new_zeros();
+    for row in 0..8 {
+        put(&mut memory, 0x8010 + 2 * row, 0xff);
+        put(&mut memory, 0x8020 + 2 * row, 0xff);
+        put(&mut memory, 0x8021 + 2 * row, 0xff);
+    }
+    put(&mut memory, 0x8031, 0x80);
+    // identity palette
+    put(&mut memory, 0xff47, 0b11_10_01_00);
+    memory
+}
+
+#[test]
+fn background_scrolls_and_wraps() {
+    let mut memory = memory_with_tiles();
+    put(&mut memory, 0x9800, 0x03);
+    put(&mut memory, 0x9800 + 31, 0x01);
+    put(&mut memory, 0xff40, LCDC_LCD_ON | LCDC_TILE_DATA | LCDC_BG_ON);
+
+    let mut ppu = Ppu::new();
+    ppu.render_frame(&memory.video());
+    let frame = ppu.take_frame().unwrap().clone();
+    assert_eq!(frame.shade(0, 0), 2);
+    assert_eq!(frame.shade(1, 0), 0);
+    assert!(ppu.take_frame().is_none());
+
+    // scrolling left by 4 wraps the map's last column onto the screen
+    put(&mut memory, 0xff43, 0xfc);
+    put(&mut memory, 0xff42, 0x00);
+    ppu.render_frame(&memory.video());
+    let frame = ppu.take_frame().unwrap();
+    assert_eq!(frame.shade(3, 0), 1);
+    assert_eq!(frame.shade(4, 0), 2);
+}
+
+#[test]
+fn signed_tile_data() {
+    let mut memory = memory_with_tiles();
+    // tile $80 with $8800 addressing is at $8800; tile 0 is at $9000
+    for row in 0..8 {
+        put(&mut memory, 0x9000 + 2 * row, 0xff);
+    }
+    put(&mut memory, 0xff40, LCDC_LCD_ON | LCDC_BG_ON);
+    let mut ppu = Ppu::new();
+    ppu.render_frame(&memory.video());
+    assert_eq!(ppu.framebuffer().shade(0, 0), 1);
+}
+
+#[test]
+fn window_line_counter() {
+    let mut memory = memory_with_tiles();
+    // window map at $9C00: row 0 is tile 1, row 1 tile 2
+    for x in 0..32 {
+        put(&mut memory, 0x9c00 + x, 0x01);
+        put(&mut memory, 0x9c20 + x, 0x02);
+    }
+    put(&mut memory, 0xff4a, 10);
+    put(&mut memory, 0xff4b, 7 + 80);
+    put(&mut memory, 0xff40, LCDC_LCD_ON | LCDC_TILE_DATA | LCDC_BG_ON | LCDC_WINDOW_ON | LCDC_WINDOW_MAP);
+
+    let mut ppu = Ppu::new();
+    let mut frame = Framebuffer::new();
+    for ly in 0..SCREEN_HEIGHT as u8 {
+        // turning the window off for a few lines holds its line counter
+        let window = if ly >= 12 && ly < 20 { 0 } else { LCDC_WINDOW_ON };
+        put(&mut memory, 0xff40, LCDC_LCD_ON | LCDC_TILE_DATA | LCDC_BG_ON | window | LCDC_WINDOW_MAP);
+        ppu.render_line(&memory.video(), ly);
+        if let Some(done) = ppu.take_frame() {
+            frame = done.clone();
+        }
+    }
+    assert_eq!(frame.shade(79, 10), 0);
+    assert_eq!(frame.shade(80, 10), 1);
+    assert_eq!(frame.shade(80, 12), 0);
+    // the window's 9th line, drawn on line 26 because of the gap
+    assert_eq!(frame.shade(80, 20), 1);
+    assert_eq!(frame.shade(80, 25), 1);
+    assert_eq!(frame.shade(80, 26), 3);
+}
+
+fn put_sprite(memory: &mut Memory, index: u16, y: u8, x: u8, tile: u8, flags: u8) {
+    let base = 0xfe00 + 4 * index;
+    put(memory, base, y);
+    put(memory, base + 1, x);
+    put(memory, base + 2, tile);
+    put(memory, base + 3, flags);
+}
+
+fn sprite_memory() -> Memory {
+    let mut memory = memory_with_tiles();
+    put(&mut memory, 0xff48, 0b11_10_01_00);
+    put(&mut memory, 0xff49, 0b00_00_00_00);
+    memory
+}
+
+const LCD_BG_OBJ: u8 = LCDC_LCD_ON | LCDC_TILE_DATA | LCDC_BG_ON | LCDC_OBJ_ON;
+
+#[test]
+fn ten_objects_per_line() {
+    let mut memory = sprite_memory();
+    // eleven solid objects on line 0, the first of them far off to the right
+    put_sprite(&mut memory, 0, 16, 200, 0x02, 0);
+    for i in 1..11 {
+        put_sprite(&mut memory, i, 16, 8 * i as u8, 0x02, 0);
+    }
+    put(&mut memory, 0xff40, LCD_BG_OBJ);
+    let mut ppu = Ppu::new();
+    ppu.render_frame(&memory.video());
+    let frame = ppu.framebuffer();
+    assert_eq!(frame.shade(8 * 9 - 8, 0), 3);
+    assert_eq!(frame.shade(8 * 10 - 8, 0), 0);
+}
+
+#[test]
+fn flips_and_tall_objects() {
+    let mut memory = sprite_memory();
+    // tile 4 is tile 3 (top-left dot) and tile 5 solid colour 1
+    put(&mut memory, 0x8041, 0x80);
+    for row in 0..8 {
+        put(&mut memory, 0x8050 + 2 * row, 0xff);
+    }
+    put_sprite(&mut memory, 0, 16, 8, 0x05, 0x60);
+    put(&mut memory, 0xff40, LCD_BG_OBJ | LCDC_OBJ_SIZE);
+    let mut ppu = Ppu::new();
+    ppu.render_frame(&memory.video());
+    let frame = ppu.framebuffer();
+    // flipped both ways, the solid half is on top and the dot bottom-right
+    assert_eq!(frame.shade(0, 0), 1);
+    assert_eq!(frame.shade(7, 15), 2);
+    assert_eq!(frame.shade(0, 15), 0);
+}
+
+#[test]
+fn dmg_priority() {
+    let mut memory = sprite_memory();
+    // BG colour 1 on the left half of the first tile row
+    put(&mut memory, 0x9800, 0x01);
+    // object 1 has the smaller X, so beats object 0 where they overlap
+    put_sprite(&mut memory, 0, 16, 12, 0x02, 0x10);
+    put_sprite(&mut memory, 1, 16, 8, 0x01, 0x80);
+    put(&mut memory, 0xff40, LCD_BG_OBJ);
+    let mut ppu = Ppu::new();
+    ppu.render_frame(&memory.video());
+    let frame = ppu.framebuffer();
+    // object 1 is behind the BG, which is colour 1 for its whole width
+    assert_eq!(frame.shade(0, 0), 1);
+    assert_eq!(frame.shade(5, 0), 1);
+    // object 0, with OBP1, past object 1's right edge
+    assert_eq!(frame.shade(8, 0), 0);
+
+    put(&mut memory, 0x9800, 0x00);
+    ppu.render_frame(&memory.video());
+    let frame = ppu.framebuffer();
+    assert_eq!(frame.shade(5, 0), 1);
+    assert_eq!(frame.shade(8, 0), 0);
+}
+
+fn interrupt_flags(memory: &Memory) -> u8 {
+    let d8(Wrapping(flags)) = memory.peek_io(io_regs::IF);
+    flags & 0x1f
+}
+
+fn clear_interrupts(memory: &mut Memory) {
+    put(memory, 0xff0f, 0x00);
+}
+
+#[test]
+fn modes_and_ly() {
+    let mut memory = Memory::new_zeros();
+    put(&mut memory, 0xff40, LCDC_LCD_ON);
+    memory.tick(4);
+    assert_eq!(memory.ppu().mode(), Mode::OamScan);
+    memory.tick(80);
+    assert_eq!(memory.ppu().mode(), Mode::Drawing);
+    // mode 3, and LY matches LYC's 0
+    assert_eq!(memory.read_d8(a16(Wrapping(0xff41))), Some(d8(Wrapping(0x87))));
+    memory.tick(172);
+    assert_eq!(memory.ppu().mode(), Mode::HBlank);
+    memory.tick(456 - 256);
+    assert_eq!(memory.read_d8(a16(Wrapping(0xff44))), Some(d8(Wrapping(1))));
+
+    // the CPU can't write LY
+    put(&mut memory, 0xff44, 0x50);
+    assert_eq!(memory.peek_io(io_regs::LY), d8(Wrapping(1)));
+
+    memory.tick(456 * 143);
+    assert_eq!(memory.ppu().ly(), 144);
+    assert_eq!(memory.ppu().mode(), Mode::VBlank);
+    assert_eq!(interrupt_flags(&memory), INT_VBLANK);
+    assert!(memory.ppu_mut().take_frame().is_some());
+
+    memory.tick(456 * 10);
+    assert_eq!(memory.ppu().ly(), 0);
+    assert_eq!(memory.ppu().mode(), Mode::OamScan);
+}
+
+#[test]
+fn lyc_coincidence() {
+    let mut memory = Memory::new_zeros();
+    put(&mut memory, 0xff45, 2);
+    put(&mut memory, 0xff41, STAT_LYC_SELECT);
+    put(&mut memory, 0xff40, LCDC_LCD_ON);
+    memory.tick(456 + 4);
+    assert_eq!(interrupt_flags(&memory), 0);
+    memory.tick(456);
+    assert_eq!(interrupt_flags(&memory), INT_STAT);
+    let d8(Wrapping(stat)) = memory.peek_io(io_regs::STAT);
+    assert_eq!(stat & STAT_COINCIDENCE, STAT_COINCIDENCE);
+}
+
+#[test]
+fn stat_blocking() {
+    let mut memory = Memory::new_zeros();
+    put(&mut memory, 0xff45, 1);
+    put(&mut memory, 0xff41, STAT_LYC_SELECT | STAT_MODE_0_SELECT);
+    put(&mut memory, 0xff40, LCDC_LCD_ON);
+    memory.tick(4);
+    assert_eq!(interrupt_flags(&memory), 0);
+    memory.tick(252);
+    assert_eq!(interrupt_flags(&memory), INT_STAT);
+    clear_interrupts(&mut memory);
+
+    // line 0's HBlank is still holding the line high when LY becomes 1, and
+    // LYC=LY then holds it through line 1's HBlank, so neither fires
+    memory.tick(456);
+    assert_eq!(memory.ppu().ly(), 1);
+    assert_eq!(memory.ppu().mode(), Mode::HBlank);
+    assert_eq!(interrupt_flags(&memory), 0);
+
+    // line 2's mode 2 and 3 let the line drop
+    memory.tick(456);
+    assert_eq!(interrupt_flags(&memory), INT_STAT);
+}
+
+#[test]
+fn lcd_off_resets_ly() {
+    let mut memory = Memory::new_zeros();
+    put(&mut memory, 0xff40, LCDC_LCD_ON);
+    memory.tick(456 * 5 + 100);
+    put(&mut memory, 0xff40, 0x00);
+    memory.tick(4);
+    assert_eq!(memory.peek_io(io_regs::LY), d8(Wrapping(0)));
+    memory.tick(456 * 5);
+    assert_eq!(memory.ppu().ly(), 0);
+    let d8(Wrapping(stat)) = memory.peek_io(io_regs::STAT);
+    assert_eq!(stat & 0x03, 0);
+}
+
+// BG scrolled off the tile grid, the window from partway along line 20 and
+// objects overlapping each other, the window and the left edge
+fn busy_scene(renderer: Renderer) -> Memory {
+    let mut memory = sprite_memory();
+    memory.set_renderer(renderer);
+    for i in 0..0x400 {
+        put(&mut memory, 0x9800 + i, (i % 4) as u8);
+        put(&mut memory, 0x9c00 + i, ((i + 1) % 4) as u8);
+    }
+    put(&mut memory, 0xff42, 5);
+    put(&mut memory, 0xff43, 3);
+    put(&mut memory, 0xff4a, 20);
+    put(&mut memory, 0xff4b, 7 + 50);
+    put_sprite(&mut memory, 0, 16, 4, 0x02, 0);
+    put_sprite(&mut memory, 1, 18, 40, 0x03, 0x20);
+    put_sprite(&mut memory, 2, 20, 36, 0x02, 0x90);
+    put_sprite(&mut memory, 3, 30, 60, 0x01, 0x10);
+    put(&mut memory, 0xff40, LCD_BG_OBJ | LCDC_WINDOW_ON | LCDC_WINDOW_MAP);
+    memory
+}
+
+#[test]
+fn pixel_fifo_draws_what_scanline_draws() {
+    let mut frames = Vec::new();
+    for &renderer in &[Renderer::Scanline, Renderer::PixelFifo] {
+        let mut memory = busy_scene(renderer);
+        memory.tick(456 * LINES_PER_FRAME as u64);
+        assert_eq!(memory.ppu().renderer(), renderer);
+        frames.push(memory.ppu_mut().take_frame().unwrap().clone());
+    }
+    assert!(frames[0].pixels() == frames[1].pixels());
+}
+
+// how long mode 3 lasts on line 0
+fn mode_3_dots(memory: &mut Memory) -> u64 {
+    let mut dots = 0;
+    while memory.ppu().mode() != Mode::HBlank || dots == 0 {
+        memory.tick(1);
+        if memory.ppu().mode() == Mode::Drawing {
+            dots += 1;
+        }
+    }
+    dots
+}
+
+#[test]
+fn pixel_fifo_mode_3_length() {
+    let setup = |scx: u8, window: bool, sprite: bool| {
+        let mut memory = sprite_memory();
+        memory.set_renderer(Renderer::PixelFifo);
+        put(&mut memory, 0xff43, scx);
+        put(&mut memory, 0xff4b, 7 + 80);
+        if sprite {
+            put_sprite(&mut memory, 0, 16, 50, 0x02, 0);
+        }
+        let window = if window { LCDC_WINDOW_ON } else { 0 };
+        put(&mut memory, 0xff40, LCD_BG_OBJ | window);
+        memory
+    };
+    assert_eq!(mode_3_dots(&mut setup(0, false, false)), DRAW_DOTS);
+    assert_eq!(mode_3_dots(&mut setup(3, false, false)), DRAW_DOTS + 3);
+    assert_eq!(mode_3_dots(&mut setup(0, true, false)), DRAW_DOTS + 6);
+    assert_eq!(mode_3_dots(&mut setup(0, false, true)), DRAW_DOTS + 6);
+    assert_eq!(mode_3_dots(&mut setup(3, true, true)), DRAW_DOTS + 15);
+
+    // the scanline renderer always takes the same time
+    let mut memory = setup(3, true, true);
+    memory.set_renderer(Renderer::Scanline);
+    assert_eq!(mode_3_dots(&mut memory), DRAW_DOTS);
+}
+
+fn put_color(memory: &mut Memory, spec_reg: u16, palette: u8, index: u8, color: u16) {
+    put(memory, spec_reg, 0x80 | (8 * palette + 2 * index));
+    put(memory, spec_reg + 1, color as u8);
+    put(memory, spec_reg + 1, (color >> 8) as u8);
+}
+
+// tile 3 flipped onto the left of the BG with palette 2, and a solid object
+// with palette 1 over the next tile along
+fn cgb_scene(renderer: Renderer, bg_attrs: u8, lcdc: u8) -> Framebuffer {
+    let mut memory = sprite_memory();
+    memory.set_model(CpuMode::CGB);
+    memory.set_renderer(renderer);
+    put(&mut memory, 0x9800, 0x03);
+    put(&mut memory, 0x9801, 0x01);
+    put(&mut memory, 0xff4f, 1);
+    put(&mut memory, 0x9800, 0x02 | tiles::ATTR_X_FLIP);
+    put(&mut memory, 0x9801, bg_attrs);
+    put(&mut memory, 0xff4f, 0);
+    put_color(&mut memory, 0xff68, 2, 0, 0x001f);
+    put_color(&mut memory, 0xff68, 2, 2, 0x7c00);
+    put_color(&mut memory, 0xff68, 0, 1, 0x03e0);
+    put_color(&mut memory, 0xff6a, 1, 3, 0x1234);
+    put_sprite(&mut memory, 0, 16, 16, 0x02, 0x01);
+    put(&mut memory, 0xff40, lcdc);
+    memory.tick(456 * LINES_PER_FRAME as u64);
+    memory.ppu().framebuffer().clone()
+}
+
+#[test]
+fn cgb_colors_and_priority() {
+    for &renderer in &[Renderer::Scanline, Renderer::PixelFifo] {
+        let frame = cgb_scene(renderer, 0, LCD_BG_OBJ);
+        assert_eq!(frame.format(), PixelFormat::Rgb555);
+        assert_eq!(frame.pixel(0, 0), 0x001f);
+        assert_eq!(frame.pixel(7, 0), 0x7c00);
+        assert_eq!(frame.pixel(8, 0), 0x1234);
+        // untouched palette RAM is white
+        assert_eq!(frame.pixel(0, 8), 0x7fff);
+
+        // the BG tile's priority attribute puts it over the object...
+        let frame = cgb_scene(renderer, tiles::ATTR_PRIORITY, LCD_BG_OBJ);
+        assert_eq!(frame.pixel(8, 0), 0x03e0);
+        // ...unless LCDC bit 0 is clear, which doesn't hide the BG on CGB
+        let frame = cgb_scene(renderer, tiles::ATTR_PRIORITY, LCD_BG_OBJ & !LCDC_BG_ON);
+        assert_eq!(frame.pixel(8, 0), 0x1234);
+        assert_eq!(frame.pixel(7, 0), 0x7c00);
+    }
+}
+
+// a DMG cart as the CGB boot ROM sees it, with tile 1 solid colour 1 over
+// the whole BG
+fn dmg_cart_on_cgb(title: &[u8]) -> Memory {
+    let logo = [
+        0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83,
+        0x00, 0x0c, 0x00, 0x0d, 0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e,
+        0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99, 0xbb, 0xbb, 0x67, 0x63,
+        0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+    ];
+    let mut rom = vec![d8::ZERO; 0x8000];
+    for (i, &byte) in logo.iter().enumerate() {
+        rom[0x104 + i] = d8(Wrapping(byte));
+    }
+    for (i, &byte) in title.iter().enumerate() {
+        rom[0x134 + i] = d8(Wrapping(byte));
+    }
+    rom[0x14b] = d8(Wrapping(0x01));
+    let mut memory = Memory::from_rom(rom).unwrap();
+    memory.set_model(CpuMode::CGB);
+    for row in 0..8 {
+        put(&mut memory, 0x8010 + 2 * row, 0xff);
+    }
+    for i in 0..0x400 {
+        put(&mut memory, 0x9800 + i, 0x01);
+    }
+    put(&mut memory, 0xff47, 0b11_10_01_00);
+    put(&mut memory, 0xff40, LCDC_LCD_ON | LCDC_TILE_DATA | LCDC_BG_ON);
+    memory
+}
+
+#[test]
+fn dmg_games_colorized_on_cgb() {
+    let mut memory = dmg_cart_on_cgb(b"POKEMON RED");
+    assert_eq!(memory.peek_io(io_regs::KEY0), d8(Wrapping(0xf7)));
+    memory.tick(456 * LINES_PER_FRAME as u64);
+    let frame = memory.ppu().framebuffer();
+    assert_eq!(frame.format(), PixelFormat::Rgb555);
+    assert_eq!(frame.pixel(0, 0), 0x421f);
+
+    memory.set_palette_override(Some(colorize::PaletteOverride::LeftB));
+    memory.tick(456 * LINES_PER_FRAME as u64);
+    assert_eq!(memory.ppu().framebuffer().pixel(0, 0), 0x5294);
+    // BGP still picks the shade
+    put(&mut memory, 0xff47, 0b11_10_11_00);
+    memory.tick(456 * LINES_PER_FRAME as u64);
+    assert_eq!(memory.ppu().framebuffer().pixel(0, 0), 0x0000);
+}
+
+#[test]
+fn dmg_palettes() {
+    let mut memory = memory_with_tiles();
+    put(&mut memory, 0x9800, 0x02);
+    put(&mut memory, 0xff40, LCDC_LCD_ON | LCDC_TILE_DATA | LCDC_BG_ON);
+    memory.tick(456 * LINES_PER_FRAME as u64);
+    assert_eq!(memory.ppu().frame_rgb555()[0], DmgPalette::CLASSIC_GREEN.colors[3]);
+
+    let custom = DmgPalette::from_rgb888([0xffffff, 0xaaaaaa, 0x555555, 0xff0000]);
+    memory.ppu_mut().set_dmg_palette(custom);
+    assert_eq!(memory.ppu().frame_rgb555()[0], 0x001f);
+    assert_eq!(memory.ppu().frame_rgb555()[8], 0x7fff);
+}
+
+#[test]
+fn frame_dump_as_pngs() {
+    let dir = ::std::env::temp_dir().join(format!("rgb-frames-{}", ::std::process::id()));
+    let mut memory = memory_with_tiles();
+    put(&mut memory, 0xff40, LCDC_LCD_ON | LCDC_TILE_DATA | LCDC_BG_ON);
+    memory.ppu_mut().start_frame_dump(export::FrameDump::png_sequence(&dir, 1..2).unwrap());
+    while !memory.ppu().frame_dump_done() {
+        memory.tick(456);
+    }
+    memory.ppu_mut().take_frame_dump().unwrap().finish().unwrap();
+    let mut files: Vec<_> = ::std::fs::read_dir(&dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    ::std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(files, vec!["frame_000001.png".to_string()]);
+}
+
+#[test]
+fn vram_views() {
+    let mut memory = memory_with_tiles();
+    put(&mut memory, 0x9801, 0x02);
+    put(&mut memory, 0xff40, LCDC_LCD_ON | LCDC_TILE_DATA | LCDC_BG_ON);
+    put(&mut memory, 0xff43, 252);
+    put(&mut memory, 0xff42, 4);
+    put_sprite(&mut memory, 3, 16, 8, 0x02, 0x30);
+    let palette = DmgPalette::POCKET_GREY;
+
+    let sheet = vram_view::tile_sheet(&memory.video(), &palette);
+    assert_eq!((sheet.width(), sheet.height()), (128, 192));
+    assert_eq!(sheet.pixel(8, 0), palette.colors[1]);
+    assert_eq!(sheet.pixel(24, 7), palette.colors[0]);
+
+    let map = vram_view::bg_map(&memory.video(), 0, &palette);
+    assert_eq!(map.pixel(8, 0), palette.colors[3]);
+    // the screen's outline wraps round from the right edge
+    assert_eq!(map.pixel(252, 4), vram_view::VIEWPORT_COLOR);
+    assert_eq!(map.pixel(252 + 159 - 256, 4 + 143), vram_view::VIEWPORT_COLOR);
+    assert_eq!(map.pixel(9, 8), palette.colors[0]);
+    let other = vram_view::bg_map(&memory.video(), 1, &palette);
+    assert_eq!(other.pixel(252, 4), palette.colors[0]);
+
+    let oam = vram_view::oam_table(&memory.video());
+    assert_eq!(oam.len(), 40);
+    assert!(oam[3].visible && oam[3].x_flip && !oam[3].y_flip);
+    assert_eq!(oam[3].dmg_palette, 1);
+    assert_eq!(oam[3].to_string(), "#03 X:   0 Y:   0 tile 02 OBP1 pal 0 bank 0 xflip");
+    assert!(!oam[0].visible);
+}
+
+#[test]
+fn cgb_tile_sheet_has_both_banks() {
+    let mut memory = Memory::new_zeros();
+    memory.set_model(CpuMode::CGB);
+    put(&mut memory, 0xff4f, 1);
+    put(&mut memory, 0x8000, 0xff);
+    let sheet = vram_view::tile_sheet(&memory.video(), &DmgPalette::CLASSIC_GREEN);
+    assert_eq!(sheet.width(), 256);
+    assert_eq!(sheet.pixel(0, 0), DmgPalette::CLASSIC_GREEN.colors[0]);
+    assert_eq!(sheet.pixel(128, 0), DmgPalette::CLASSIC_GREEN.colors[1]);
+}
+
+#[test]
+fn raster_observers_and_write_recording() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use ppu::raster::{RasterPoint, RasterEvent};
+
+    let mut memory = Memory::new_zeros();
+    let events: Rc<RefCell<Vec<RasterEvent>>> = Rc::new(RefCell::new(Vec::new()));
+    let seen = events.clone();
+    memory.ppu_mut().add_raster_observer(move |event| seen.borrow_mut().push(*event));
+    memory.ppu_mut().start_recording_writes();
+
+    put(&mut memory, 0xff40, LCDC_LCD_ON);
+    memory.tick(456 * 10 + 300);
+    put(&mut memory, 0xff43, 0x21);
+    put(&mut memory, 0xff01, 0x55);
+    memory.tick(456 * 144);
+
+    let events = events.borrow();
+    let line_10: Vec<_> = events
+        .iter()
+        .filter(|event| event.ly == 10)
+        .map(|event| (event.point, event.dot))
+        .collect();
+    assert_eq!(
+        line_10,
+        vec![
+            (RasterPoint::LineStart, 0),
+            (RasterPoint::Mode(Mode::OamScan), 0),
+            (RasterPoint::Mode(Mode::Drawing), 80),
+            (RasterPoint::Mode(Mode::HBlank), 252),
+        ]
+    );
+    let line_11 = events.iter().find(|event| event.ly == 11).unwrap();
+    assert_eq!(line_11.registers.scx, 0x21);
+    assert_eq!(line_11.registers.ly, 11);
+    let starts = events.iter().filter(|event| event.point == RasterPoint::LineStart).count();
+    // every line of the frame, and the first of the next
+    assert_eq!(starts, 155);
+    assert!(events.iter().any(|event| event.ly == 144 && event.point == RasterPoint::Mode(Mode::VBlank)));
+
+    let recording = memory.ppu_mut().take_write_recording().unwrap();
+    let writes = recording.writes();
+    assert_eq!(writes.len(), 2);
+    let scx = writes[1];
+    assert_eq!((scx.reg, scx.value, scx.ly, scx.dot), (io_regs::SCX, 0x21, 10, 300));
+}
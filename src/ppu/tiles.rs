@@ -0,0 +1,88 @@
+use number_types::d8_type::d8;
+use std::num::Wrapping;
+use memory::VideoRamBank;
+use super::VideoMemory;
+
+/*
+Tiles are 8x8, 16 bytes each, two bytes per row. Bit 7 of each byte is the
+leftmost pixel; the first byte gives bit 0 of each pixel's colour index and
+the second byte bit 1.
+
+LCDC bit 4 picks how the BG and window find their tiles:
+1 - $8000 addressing: tile numbers 0-255 from $8000
+0 - $8800 addressing: tile numbers -128-127 from $9000
+Objects always use $8000 addressing.
+*/
+pub const TILE_BYTES: usize = 16;
+
+/*
+BG map attributes, CGB only. They're in VRAM bank 1, at the same offset as
+the tile number in bank 0:
+
+bit 7	BG and window over objects
+bit 6	Y flip
+bit 5	X flip
+bit 3	tile VRAM bank
+bits 0-2	palette
+*/
+pub const ATTR_PRIORITY: u8 = 0x80;
+pub const ATTR_Y_FLIP: u8 = 0x40;
+pub const ATTR_X_FLIP: u8 = 0x20;
+pub const ATTR_BANK: u8 = 0x08;
+
+// offset from $8000 of a BG or window tile
+pub fn bg_tile_offset(lcdc: u8, tile: u8) -> usize {
+    if (lcdc & 0x10) != 0 {
+        tile as usize * TILE_BYTES
+    } else {
+        (0x1000 + (tile as i8 as isize) * TILE_BYTES as isize) as usize
+    }
+}
+
+// the colour indices of one row of a tile, leftmost first
+pub fn tile_row(bank: &VideoRamBank, tile_offset: usize, row: usize) -> [u8; 8] {
+    let d8(Wrapping(low)) = bank.get(tile_offset + 2 * row);
+    let d8(Wrapping(high)) = bank.get(tile_offset + 2 * row + 1);
+    let mut pixels = [0; 8];
+    for (x, pixel) in pixels.iter_mut().enumerate() {
+        let bit = 7 - x;
+        *pixel = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+    }
+    pixels
+}
+
+// one row of a BG or window tile, with the bank and flips from its CGB
+// attributes
+pub fn bg_tile_row(video: &VideoMemory, tile: u8, attrs: u8, row: usize) -> [u8; 8] {
+    let bank = if (attrs & ATTR_BANK) != 0 { 1 } else { 0 };
+    let row = if (attrs & ATTR_Y_FLIP) != 0 { 7 - row } else { row };
+    let mut pixels = tile_row(&video.vram[bank], bg_tile_offset(video.lcdc(), tile), row);
+    if (attrs & ATTR_X_FLIP) != 0 {
+        pixels.reverse();
+    }
+    pixels
+}
+
+// looks a colour index up in a DMG palette register
+pub fn apply_palette(palette: u8, index: u8) -> u8 {
+    (palette >> (2 * index)) & 0x03
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signed_addressing() {
+        assert_eq!(bg_tile_offset(0x10, 0x80), 0x0800);
+        assert_eq!(bg_tile_offset(0x00, 0x80), 0x0800);
+        assert_eq!(bg_tile_offset(0x00, 0x7f), 0x17f0);
+        assert_eq!(bg_tile_offset(0x00, 0x00), 0x1000);
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(apply_palette(0b11_10_01_00, 2), 2);
+        assert_eq!(apply_palette(0b00_01_10_11, 0), 3);
+    }
+}
@@ -0,0 +1,245 @@
+use number_types::d8_type::d8;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::num::Wrapping;
+use std::path::Path;
+use cpu::CpuMode;
+use memory::io_regs;
+use super::{VideoMemory, PixelFormat, tiles, palette, SCREEN_WIDTH, SCREEN_HEIGHT, LCDC_BG_MAP};
+use super::palette::DmgPalette;
+use super::sprites::{self, Sprite};
+use super::export;
+
+/*
+Debug views of VRAM and OAM, as images and tables, straight from memory
+and whatever the PPU is doing. They draw everything as it stands now, so
+mid-frame changes to VRAM show up however far the frame has got.
+
+- the tile sheet: the 384 tiles at $8000-$97FF, 16 to a row, 128x192. On
+  CGB bank 1's tiles go to the right of bank 0's, making it 256x192. Tiles
+  have no palette of their own, so colour indices go straight through the
+  DMG palette given.
+- a BG map: all 32x32 tiles of the map at $9800 or $9C00, 256x256, with
+  their attributes and palettes, and for the map the BG is using, the
+  screen's 160x144 outlined where SCX and SCY put it, wrapping around.
+- the OAM table: all 40 objects, decoded.
+*/
+pub const TILES_PER_BANK: usize = 384;
+const SHEET_COLUMNS: usize = 16;
+const MAP_SIZE: usize = 256;
+// how the viewport is outlined on BG map views
+pub const VIEWPORT_COLOR: u16 = 0x001f;
+
+// an RGB555 image, row by row, starting black
+#[derive(Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u16>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: u16) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    pub fn rgb888(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|&color| palette::rgb555_to_rgb888(color).to_vec()).collect()
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let out = BufWriter::new(File::create(path)?);
+        export::write_rgb_png(out, self.width, self.height, &self.rgb888())
+    }
+}
+
+fn byte(data: &[d8], idx: usize) -> u8 {
+    let d8(Wrapping(val)) = data[idx];
+    val
+}
+
+// one row of a tile's colour indices, from raw tile data
+fn decode_row(tile_data: &[d8], row: usize) -> [u8; 8] {
+    let low = byte(tile_data, 2 * row);
+    let high = byte(tile_data, 2 * row + 1);
+    let mut pixels = [0; 8];
+    for (x, pixel) in pixels.iter_mut().enumerate() {
+        let bit = 7 - x;
+        *pixel = (((high >> bit) & 1) << 1) | ((low >> bit) & 1);
+    }
+    pixels
+}
+
+fn banks(video: &VideoMemory) -> usize {
+    match video.model {
+        CpuMode::CGB => 2,
+        CpuMode::DMG | CpuMode::MGB | CpuMode::SGB => 1,
+    }
+}
+
+pub fn tile_sheet(video: &VideoMemory, colors: &DmgPalette) -> Image {
+    let bank_width = SHEET_COLUMNS * 8;
+    let rows = TILES_PER_BANK / SHEET_COLUMNS;
+    let mut image = Image::new(bank_width * banks(video), rows * 8);
+    for bank in 0..banks(video) {
+        let character_ram = video.vram[bank].character_ram();
+        for tile in 0..TILES_PER_BANK {
+            let data = &character_ram[tile * tiles::TILE_BYTES..(tile + 1) * tiles::TILE_BYTES];
+            let left = bank * bank_width + (tile % SHEET_COLUMNS) * 8;
+            let top = (tile / SHEET_COLUMNS) * 8;
+            for row in 0..8 {
+                for (x, &index) in decode_row(data, row).iter().enumerate() {
+                    image.set(left + x, top + row, colors.color(index));
+                }
+            }
+        }
+    }
+    image
+}
+
+// `map` is 0 for $9800 and 1 for $9C00. `dmg_palette` colours DMG shades.
+pub fn bg_map(video: &VideoMemory, map: usize, dmg_palette: &DmgPalette) -> Image {
+    let mut image = Image::new(MAP_SIZE, MAP_SIZE);
+    let format = PixelFormat::for_model(video.model);
+    let tile_numbers = video.vram[0].background_map(map);
+    let attributes = video.vram[1].background_map(map);
+    let lcdc = video.lcdc();
+    for entry in 0..tile_numbers.len() {
+        let tile = byte(tile_numbers, entry);
+        let attrs = if video.cgb_features() { byte(attributes, entry) } else { 0 };
+        let bank = if (attrs & tiles::ATTR_BANK) != 0 { 1 } else { 0 };
+        let offset = tiles::bg_tile_offset(lcdc, tile);
+        let data = &video.vram[bank].character_ram()[offset..offset + tiles::TILE_BYTES];
+        for row in 0..8 {
+            let source_row = if (attrs & tiles::ATTR_Y_FLIP) != 0 { 7 - row } else { row };
+            let mut pixels = decode_row(data, source_row);
+            if (attrs & tiles::ATTR_X_FLIP) != 0 {
+                pixels.reverse();
+            }
+            for (x, &index) in pixels.iter().enumerate() {
+                let color = palette::bg_color(video, index, attrs);
+                let color = match format {
+                    PixelFormat::Shades => dmg_palette.color(color as u8),
+                    PixelFormat::Rgb555 => color,
+                };
+                image.set((entry % 32) * 8 + x, (entry / 32) * 8 + row, color);
+            }
+        }
+    }
+
+    let bg_map = if (lcdc & LCDC_BG_MAP) != 0 { 1 } else { 0 };
+    if map == bg_map {
+        outline_viewport(video, &mut image);
+    }
+    image
+}
+
+fn outline_viewport(video: &VideoMemory, image: &mut Image) {
+    let scx = video.reg(io_regs::SCX) as usize;
+    let scy = video.reg(io_regs::SCY) as usize;
+    let mut plot = |x: usize, y: usize| image.set((scx + x) % MAP_SIZE, (scy + y) % MAP_SIZE, VIEWPORT_COLOR);
+    for x in 0..SCREEN_WIDTH {
+        plot(x, 0);
+        plot(x, SCREEN_HEIGHT - 1);
+    }
+    for y in 0..SCREEN_HEIGHT {
+        plot(0, y);
+        plot(SCREEN_WIDTH - 1, y);
+    }
+}
+
+// one object in OAM, decoded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OamEntry {
+    pub sprite: Sprite,
+    // where its top-left corner is on screen, which can be off it
+    pub screen_x: i16,
+    pub screen_y: i16,
+    pub behind_bg: bool,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    // 0 for OBP0, 1 for OBP1
+    pub dmg_palette: usize,
+    pub cgb_palette: usize,
+    pub cgb_bank: usize,
+    // whether any of it is on screen
+    pub visible: bool,
+}
+
+pub fn oam_table(video: &VideoMemory) -> Vec<OamEntry> {
+    let height = sprites::object_height(video.lcdc()) as i16;
+    (0..sprites::OAM_OBJECTS)
+        .map(|i| {
+            let sprite = Sprite::from_oam(video.oam, i);
+            let screen_x = sprite.x as i16 - 8;
+            let screen_y = sprite.y as i16 - 16;
+            OamEntry {
+                sprite,
+                screen_x,
+                screen_y,
+                behind_bg: sprite.behind_bg(),
+                x_flip: (sprite.flags & 0x20) != 0,
+                y_flip: (sprite.flags & 0x40) != 0,
+                dmg_palette: sprite.dmg_palette() - io_regs::OBP0,
+                cgb_palette: sprite.cgb_palette(),
+                cgb_bank: sprite.cgb_bank(),
+                visible: screen_x > -8
+                    && screen_x < SCREEN_WIDTH as i16
+                    && screen_y > -height
+                    && screen_y < SCREEN_HEIGHT as i16,
+            }
+        })
+        .collect()
+}
+
+// one line of a table, e.g.
+// `#05 X: 16 Y: 32 tile 1A OBP1 pal 3 bank 1 behind xflip`
+impl fmt::Display for OamEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{:02} X:{:4} Y:{:4} tile {:02X} OBP{} pal {} bank {}",
+            self.sprite.oam_index,
+            self.screen_x,
+            self.screen_y,
+            self.sprite.tile,
+            self.dmg_palette,
+            self.cgb_palette,
+            self.cgb_bank
+        )?;
+        for &(set, name) in &[(self.behind_bg, " behind"), (self.x_flip, " xflip"), (self.y_flip, " yflip")] {
+            if set {
+                write!(f, "{}", name)?;
+            }
+        }
+        if !self.visible {
+            write!(f, " offscreen")?;
+        }
+        Ok(())
+    }
+}